@@ -1,17 +1,21 @@
 use core::sync::atomic::Ordering;
 
-use crate::connection::{Connection, ConnectionV4, ConnectionV6, Direction, Key, Verdict};
+use crate::connection::{
+    Connection, ConnectionDetails, ConnectionV4, ConnectionV6, Direction, EndReason, Key, Verdict,
+};
 use crate::device::{Device, Packet};
 
 use crate::dbg;
+use crate::egress_policy::ConnectionEgress;
 use crate::id_cache;
+use crate::user_sid;
 use smoltcp::wire::{
     IpAddress, IpProtocol, Ipv4Address, Ipv6Address, IPV4_HEADER_LEN, IPV6_HEADER_LEN,
 };
 use wdk::filter_engine::callout_data::CalloutData;
 use wdk::filter_engine::layer::{
     self, FieldsAleAuthConnectV4, FieldsAleAuthConnectV6, FieldsAleAuthRecvAcceptV4,
-    FieldsAleAuthRecvAcceptV6, ValueType,
+    FieldsAleAuthRecvAcceptV6, FieldsAleBindRedirectV4, FieldsAleBindRedirectV6, ValueType,
 };
 use wdk::filter_engine::net_buffer::NetBufferList;
 use wdk::filter_engine::packet::{Injector, TransportPacketList};
@@ -32,6 +36,9 @@ struct AleLayerData {
     remote_port: u16,
     interface_index: u32,
     sub_interface_index: u32,
+    compartment_id: u32,
+    // Index of the user id (token access information) field of the layer.
+    user_id_field: usize,
 }
 
 impl AleLayerData {
@@ -55,6 +62,10 @@ impl AleLayerData {
         }
     }
 
+    fn is_outbound(&self) -> bool {
+        matches!(self.direction, Direction::Outbound)
+    }
+
     fn is_loopback(&self) -> bool {
         match (self.local_ip, self.remote_ip) {
             (IpAddress::Ipv4(local), IpAddress::Ipv4(remote)) => {
@@ -96,8 +107,10 @@ pub fn ale_layer_connect_v4(data: CalloutData) {
         local_port: data.get_value_u16(Fields::IpLocalPort as usize),
         remote_ip: get_ipv4_address(&data, Fields::IpRemoteAddress as usize),
         remote_port: data.get_value_u16(Fields::IpRemotePort as usize),
-        interface_index: 0,
-        sub_interface_index: 0,
+        interface_index: data.get_value_u32(Fields::InterfaceIndex as usize),
+        sub_interface_index: data.get_value_u32(Fields::SubInterfaceIndex as usize),
+        compartment_id: data.get_value_u32(Fields::CompartmentId as usize),
+        user_id_field: Fields::AleUserId as usize,
     };
 
     ale_layer_auth_outbound(data, ale_data);
@@ -117,6 +130,8 @@ pub fn ale_layer_accept_v4(data: CalloutData) {
         remote_port: data.get_value_u16(Fields::IpRemotePort as usize),
         interface_index: data.get_value_u32(Fields::InterfaceIndex as usize),
         sub_interface_index: data.get_value_u32(Fields::SubInterfaceIndex as usize),
+        compartment_id: data.get_value_u32(Fields::CompartmentId as usize),
+        user_id_field: Fields::AleUserId as usize,
     };
     ale_layer_auth_inbound(data, ale_data);
 }
@@ -136,6 +151,8 @@ pub fn ale_layer_connect_v6(data: CalloutData) {
         remote_port: data.get_value_u16(Fields::IpRemotePort as usize),
         interface_index: data.get_value_u32(Fields::InterfaceIndex as usize),
         sub_interface_index: data.get_value_u32(Fields::SubInterfaceIndex as usize),
+        compartment_id: data.get_value_u32(Fields::CompartmentId as usize),
+        user_id_field: Fields::AleUserId as usize,
     };
 
     ale_layer_auth_outbound(data, ale_data);
@@ -155,10 +172,110 @@ pub fn ale_layer_accept_v6(data: CalloutData) {
         remote_port: data.get_value_u16(Fields::IpRemotePort as usize),
         interface_index: data.get_value_u32(Fields::InterfaceIndex as usize),
         sub_interface_index: data.get_value_u32(Fields::SubInterfaceIndex as usize),
+        compartment_id: data.get_value_u32(Fields::CompartmentId as usize),
+        user_id_field: Fields::AleUserId as usize,
     };
     ale_layer_auth_inbound(data, ale_data);
 }
 
+pub fn ale_bind_redirect_v4(data: CalloutData) {
+    type Fields = FieldsAleBindRedirectV4;
+    let local_ip = get_ipv4_address(&data, Fields::IpLocalAddress as usize);
+    let local_port = data.get_value_u16(Fields::IpLocalPort as usize);
+    let protocol = get_protocol(&data, Fields::IpProtocol as usize);
+    ale_bind_redirect(data, protocol, local_ip, local_port);
+}
+
+pub fn ale_bind_redirect_v6(data: CalloutData) {
+    type Fields = FieldsAleBindRedirectV6;
+    let local_ip = get_ipv6_address(&data, Fields::IpLocalAddress as usize);
+    let local_port = data.get_value_u16(Fields::IpLocalPort as usize);
+    let protocol = get_protocol(&data, Fields::IpProtocol as usize);
+    ale_bind_redirect(data, protocol, local_ip, local_port);
+}
+
+// Bind requests (ALE Bind Redirect layers).
+//
+// Rewrites the local address of the socket when the bind policy pins its process to an address.
+// The request is always permitted, this layer does not decide whether the socket may connect.
+fn ale_bind_redirect(
+    mut data: CalloutData,
+    protocol: IpProtocol,
+    local_ip: IpAddress,
+    local_port: u16,
+) {
+    let Some(device) = crate::entry::get_device() else {
+        return;
+    };
+
+    let redirect_ip = {
+        let policy = device.bind_policy.read_lock();
+        if device.is_shutting_down() || policy.is_empty() {
+            None
+        } else {
+            let process_id = data.get_process_id().unwrap_or(0);
+            // The path is only needed for application rules, but it is cheap compared to the bind.
+            // Rules hold the normalised path, the same one user space sees in connection events.
+            let app_path = data
+                .get_process_path()
+                .map(|path| device.normalize_process_path(path));
+            policy
+                .find(process_id, app_path.as_deref(), local_ip)
+                .map(|ip| (process_id, ip))
+        }
+    };
+
+    if let Some((process_id, redirect_ip)) = redirect_ip {
+        let result = match redirect_ip {
+            IpAddress::Ipv4(ip) => data.redirect_bind(&ip.octets()),
+            IpAddress::Ipv6(ip) => data.redirect_bind(&ip.octets()),
+        };
+        match result {
+            Ok(true) => {
+                crate::dbg!(
+                    "bind redirect PID: {} {} -> {}",
+                    process_id,
+                    local_ip,
+                    redirect_ip
+                );
+                let info = match (local_ip, redirect_ip) {
+                    (IpAddress::Ipv4(original), IpAddress::Ipv4(redirect)) => {
+                        protocol::info::bind_redirect_event_v4_info(
+                            process_id,
+                            protocol.into(),
+                            original.octets(),
+                            redirect.octets(),
+                            local_port,
+                        )
+                    }
+                    (IpAddress::Ipv6(original), IpAddress::Ipv6(redirect)) => {
+                        protocol::info::bind_redirect_event_v6_info(
+                            process_id,
+                            protocol.into(),
+                            original.octets(),
+                            redirect.octets(),
+                            local_port,
+                        )
+                    }
+                    _ => {
+                        data.action_permit();
+                        return;
+                    }
+                };
+                _ = device.event_queue.push(info);
+                // Make sure a lower weight filter does not undo the redirect.
+                data.clear_write_flag();
+            }
+            Ok(false) => {}
+            Err(err) => {
+                crate::err!("failed to redirect bind: {}", err);
+            }
+        }
+    }
+
+    data.action_permit();
+}
+
 // Outbound connections (ALE Auth Connect layers).
 //
 // The ALE layer runs *before* the packet layer for outbound traffic, so permitting here means the
@@ -179,6 +296,19 @@ fn ale_layer_auth_outbound(mut data: CalloutData, ale_data: AleLayerData) {
         return;
     }
 
+    // The kill switch comes first: trusted processes are not exempt from it.
+    if kill_switch_blocks(device, &ale_data) {
+        crate::dbg!("kill switch block: {}", ale_data.as_key());
+        data.action_block();
+        return;
+    }
+
+    if device.is_trusted_process(ale_data.process_id) {
+        accept_trusted(device, &data, &ale_data);
+        data.action_permit();
+        return;
+    }
+
     // Only TCP and UDP are associated with a connection and handled here. Everything else is
     // permitted and handled by the packet layer.
     if !matches!(ale_data.protocol, IpProtocol::Tcp | IpProtocol::Udp) {
@@ -188,6 +318,12 @@ fn ale_layer_auth_outbound(mut data: CalloutData, ale_data: AleLayerData) {
 
     let key = ale_data.as_key();
 
+    if device.is_monitor_only() {
+        monitor_connection(device, &data, &key, &ale_data);
+        data.action_permit();
+        return;
+    }
+
     // Check if connection is already in cache.
     let verdict = device.connection_cache.get_verdict(&key);
 
@@ -200,9 +336,13 @@ fn ale_layer_auth_outbound(mut data: CalloutData, ale_data: AleLayerData) {
             // process id (missing packet id, nothing to reinject) and permit. The packet layer
             // sends the real packet and applies the actual verdict after user space decides.
             Verdict::Undecided => {
-                if let Some(info) =
-                    id_cache::build_info_only(&key, ale_data.process_id, ale_data.direction)
-                {
+                let (id, details) = cached_details(device, &key);
+                if let Some(info) = id_cache::build_info_only(
+                    &key,
+                    ale_data.process_id,
+                    ale_data.direction,
+                    &details.as_metadata(id),
+                ) {
                     let _ = device.event_queue.push(info);
                 }
                 data.action_permit();
@@ -245,10 +385,14 @@ fn ale_layer_auth_outbound(mut data: CalloutData, ale_data: AleLayerData) {
                 key,
                 ale_data.process_id
             );
-            add_connection(device, &key, &ale_data, None);
-            if let Some(info) =
-                id_cache::build_info_only(&key, ale_data.process_id, ale_data.direction)
-            {
+            let details = capture_details(device, &data, &ale_data);
+            let id = add_connection(device, &key, &ale_data, None, details.clone());
+            if let Some(info) = id_cache::build_info_only(
+                &key,
+                ale_data.process_id,
+                ale_data.direction,
+                &details.as_metadata(id),
+            ) {
                 let _ = device.event_queue.push(info);
             }
             data.action_permit();
@@ -257,6 +401,10 @@ fn ale_layer_auth_outbound(mut data: CalloutData, ale_data: AleLayerData) {
             // be returned as the result of the connect call; the packet is reinjected once user
             // space decides.
             crate::dbg!("pending connection: {} PID: {}", key, ale_data.process_id);
+            // Added before the event is sent, so the event carries the connection id and a
+            // verdict that comes back right away finds the connection.
+            let details = capture_details(device, &data, &ale_data);
+            let id = add_connection(device, &key, &ale_data, None, details.clone());
             match save_packet_outbound(device, &mut data, &ale_data) {
                 Ok(packet) => {
                     let info = device.packet_cache.push(
@@ -264,6 +412,7 @@ fn ale_layer_auth_outbound(mut data: CalloutData, ale_data: AleLayerData) {
                         ale_data.process_id,
                         ale_data.direction,
                         true,
+                        &details.as_metadata(id),
                     );
                     if let Some(info) = info {
                         let _ = device.event_queue.push(info);
@@ -273,7 +422,6 @@ fn ale_layer_auth_outbound(mut data: CalloutData, ale_data: AleLayerData) {
                     crate::err!("failed to pend packet: {}", err);
                 }
             };
-            add_connection(device, &key, &ale_data, None);
 
             // Drop the packet. It will be re-injected after user space returns a verdict.
             data.block_and_absorb();
@@ -303,6 +451,19 @@ fn ale_layer_auth_inbound(mut data: CalloutData, ale_data: AleLayerData) {
         return;
     }
 
+    // The kill switch comes first: trusted processes are not exempt from it.
+    if kill_switch_blocks(device, &ale_data) {
+        crate::dbg!("kill switch block: {}", ale_data.as_key());
+        data.action_block();
+        return;
+    }
+
+    if device.is_trusted_process(ale_data.process_id) {
+        accept_trusted(device, &data, &ale_data);
+        data.action_permit();
+        return;
+    }
+
     // Only TCP and UDP are associated with a connection and handled here. Everything else was
     // already handled by the packet layer.
     if !matches!(ale_data.protocol, IpProtocol::Tcp | IpProtocol::Udp) {
@@ -312,6 +473,12 @@ fn ale_layer_auth_inbound(mut data: CalloutData, ale_data: AleLayerData) {
 
     let key = ale_data.as_key();
 
+    if device.is_monitor_only() {
+        monitor_connection(device, &data, &key, &ale_data);
+        data.action_permit();
+        return;
+    }
+
     // Check if connection is already in cache.
     let verdict = device.connection_cache.get_verdict(&key);
 
@@ -323,6 +490,7 @@ fn ale_layer_auth_inbound(mut data: CalloutData, ale_data: AleLayerData) {
             // Save this packet too so it is sent to user space and reinjected with the verdict.
             Verdict::Undecided => {
                 crate::dbg!("saving packet: {}", key);
+                let (id, details) = cached_details(device, &key);
                 match save_packet_inbound(device, &mut data, &ale_data, false) {
                     Ok(packet) => {
                         let info = device.packet_cache.push(
@@ -330,6 +498,7 @@ fn ale_layer_auth_inbound(mut data: CalloutData, ale_data: AleLayerData) {
                             ale_data.process_id,
                             ale_data.direction,
                             true,
+                            &details.as_metadata(id),
                         );
                         if let Some(info) = info {
                             let _ = device.event_queue.push(info);
@@ -378,11 +547,21 @@ fn ale_layer_auth_inbound(mut data: CalloutData, ale_data: AleLayerData) {
                 key,
                 ale_data.process_id
             );
-            add_connection(device, &key, &ale_data, Some(Verdict::Accept));
+            let details = capture_details(device, &data, &ale_data);
+            let id = add_connection(
+                device,
+                &key,
+                &ale_data,
+                Some(Verdict::Accept),
+                details.clone(),
+            );
 
-            if let Some(info) =
-                id_cache::build_info_only(&key, ale_data.process_id, ale_data.direction)
-            {
+            if let Some(info) = id_cache::build_info_only(
+                &key,
+                ale_data.process_id,
+                ale_data.direction,
+                &details.as_metadata(id),
+            ) {
                 let _ = device.event_queue.push(info);
             }
 
@@ -398,6 +577,10 @@ fn ale_layer_auth_inbound(mut data: CalloutData, ale_data: AleLayerData) {
         // (see `Device::reset_filters_and_inject`) instead of dropping the packet.
         crate::dbg!("pending connection: {} {}", key, ale_data.direction);
         let can_pend_connection = !ale_data.reauthorize;
+        // Added before the event is sent, see the outbound case.
+        crate::dbg!("adding connection: {} PID: {}", key, ale_data.process_id);
+        let details = capture_details(device, &data, &ale_data);
+        let id = add_connection(device, &key, &ale_data, None, details.clone());
         match save_packet_inbound(device, &mut data, &ale_data, can_pend_connection) {
             Ok(packet) => {
                 let info = device.packet_cache.push(
@@ -405,6 +588,7 @@ fn ale_layer_auth_inbound(mut data: CalloutData, ale_data: AleLayerData) {
                     ale_data.process_id,
                     ale_data.direction,
                     true,
+                    &details.as_metadata(id),
                 );
                 if let Some(info) = info {
                     let _ = device.event_queue.push(info);
@@ -415,35 +599,180 @@ fn ale_layer_auth_inbound(mut data: CalloutData, ale_data: AleLayerData) {
             }
         };
 
-        // Connection is not in cache, add it.
-        crate::dbg!("adding connection: {} PID: {}", key, ale_data.process_id);
-        add_connection(device, &key, &ale_data, None);
-
         // Drop the packet. It will be re-injected after user space returns a verdict.
         data.block_and_absorb();
     }
 }
 
-fn add_connection(device: &Device, key: &Key, ale_data: &AleLayerData, verdict: Option<Verdict>) {
+// Connections of trusted processes get a permanent accept without asking user space and without an
+// event. Checked before the kill switch: the trusted processes include the one running the tunnel,
+// whose own connection to the tunnel server does not go through the tunnel.
+fn accept_trusted(device: &Device, data: &CalloutData, ale_data: &AleLayerData) {
+    if !matches!(ale_data.protocol, IpProtocol::Tcp | IpProtocol::Udp) {
+        return;
+    }
+    let key = ale_data.as_key();
+    match device.connection_cache.get_verdict(&key) {
+        Some(Verdict::PermanentAccept) => {}
+        // The process was registered after the connection was added.
+        Some(_) => {
+            _ = device
+                .connection_cache
+                .update_connection(key, Verdict::PermanentAccept);
+        }
+        None => {
+            add_connection(
+                device,
+                &key,
+                ale_data,
+                Some(Verdict::PermanentAccept),
+                capture_details(device, data, ale_data),
+            );
+        }
+    }
+}
+
+// Monitor-only mode. Nothing is pended: a new connection is added without a verdict and reported
+// with an info-only event (missing packet id, nothing to reinject), then permitted like everything
+// else. User space can still set a verdict, it is just not enforced while the mode is on.
+fn monitor_connection(device: &Device, data: &CalloutData, key: &Key, ale_data: &AleLayerData) {
+    let (id, details) = match device.connection_cache.get_verdict(key) {
+        // User space already knows about this connection and has decided.
+        Some(verdict) if !matches!(verdict, Verdict::Undecided) => return,
+        Some(_) => cached_details(device, key),
+        None => {
+            let details = capture_details(device, data, ale_data);
+            let id = add_connection(device, key, ale_data, None, details.clone());
+            (id, details)
+        }
+    };
+    if let Some(info) = id_cache::build_info_only(
+        key,
+        ale_data.process_id,
+        ale_data.direction,
+        &details.as_metadata(id),
+    ) {
+        let _ = device.event_queue.push(info);
+    }
+}
+
+// Captures the details of a new connection from the classify data. Only called for new
+// connections: the details are kept with the connection, see `cached_details`.
+fn capture_details(
+    device: &Device,
+    data: &CalloutData,
+    ale_data: &AleLayerData,
+) -> ConnectionDetails {
+    ConnectionDetails {
+        process_path: data
+            .get_process_path()
+            .map(|path| device.normalize_process_path(path)),
+        user_sid: data
+            .get_value_user_sid(ale_data.user_id_field)
+            .and_then(user_sid::format_sid),
+        parent_process_id: device
+            .process_table
+            .read_lock()
+            .parent_of(ale_data.process_id)
+            .unwrap_or(0),
+        interface_index: egress_interface_index(device, ale_data),
+        sub_interface_index: ale_data.sub_interface_index,
+        compartment_id: ale_data.compartment_id,
+    }
+}
+
+// Returns the id of the connection and the details captured when it was added.
+fn cached_details(device: &Device, key: &Key) -> (u64, ConnectionDetails) {
+    let details = if key.is_ipv6() {
+        device
+            .connection_cache
+            .get_connection_v6(key)
+            .map(|conn| (conn.get_id(), conn.get_details().clone()))
+    } else {
+        device
+            .connection_cache
+            .get_connection_v4(key)
+            .map(|conn| (conn.get_id(), conn.get_details().clone()))
+    };
+    details.unwrap_or_default()
+}
+
+// Checked before the cache is looked at, so the kill switch also covers connections that were
+// accepted before it was turned on. Blocked connections are never added to the cache and user space
+// is not asked about them.
+fn kill_switch_blocks(device: &Device, ale_data: &AleLayerData) -> bool {
+    device.kill_switch_blocks(
+        egress_interface_index(device, ale_data),
+        ale_data.local_ip,
+        ale_data.remote_ip,
+    )
+}
+
+// Returns the interface the connection uses. An outbound connection of a process with an egress
+// route does not leave on the interface the stack picked for it, but on the route interface (see
+// `packet_util::Egress`).
+fn egress_interface_index(device: &Device, ale_data: &AleLayerData) -> u32 {
+    if ale_data.is_outbound() && !ale_data.is_loopback() {
+        if let Some(route) = device
+            .egress_policy
+            .read_lock()
+            .find(ale_data.process_id, ale_data.is_ipv6)
+        {
+            return route.interface_index;
+        }
+    }
+    return ale_data.interface_index;
+}
+
+// Adds the connection to the cache and returns its id, which goes into the events of the
+// connection. 0 if it could not be added.
+fn add_connection(
+    device: &Device,
+    key: &Key,
+    ale_data: &AleLayerData,
+    verdict: Option<Verdict>,
+    details: ConnectionDetails,
+) -> u64 {
+    // The egress route is picked once, for the lifetime of the connection. Loopback traffic never
+    // leaves the machine, there is nothing to route.
+    let egress = if ale_data.is_loopback() {
+        None
+    } else {
+        device
+            .egress_policy
+            .read_lock()
+            .find(ale_data.process_id, ale_data.is_ipv6)
+            .map(ConnectionEgress::new)
+    };
+
     if ale_data.is_ipv6 {
-        if let Ok(conn) = ConnectionV6::from_key(key, ale_data.process_id, ale_data.direction) {
+        if let Ok(mut conn) = ConnectionV6::from_key(key, ale_data.process_id, ale_data.direction) {
             if let Some(verdict) = verdict {
                 conn.set_verdict(verdict);
             }
-            device.connection_cache.add_v6(conn);
+            conn.egress = egress;
+            conn.details = details;
+            let id = device.connection_cache.add_v6(conn);
+            device.evict_if_full();
+            return id;
         } else {
             crate::err!("failed to add ipv6 connection");
         }
     } else {
-        if let Ok(conn) = ConnectionV4::from_key(key, ale_data.process_id, ale_data.direction) {
+        if let Ok(mut conn) = ConnectionV4::from_key(key, ale_data.process_id, ale_data.direction) {
             if let Some(verdict) = verdict {
                 conn.set_verdict(verdict);
             }
-            device.connection_cache.add_v4(conn);
+            conn.egress = egress;
+            conn.details = details;
+            let id = device.connection_cache.add_v4(conn);
+            device.evict_if_full();
+            return id;
         } else {
             crate::err!("failed to add ipv4 connection");
         }
     }
+    return 0;
 }
 
 // Pends the outbound connect operation, capturing the packet so it can be reinjected after the
@@ -577,6 +906,8 @@ pub fn endpoint_closure_v4(data: CalloutData) {
                 conn.bandwidth_usage.rx_packets.load(Ordering::SeqCst),
                 conn.bandwidth_usage.tx_bytes.load(Ordering::SeqCst),
                 conn.bandwidth_usage.tx_packets.load(Ordering::SeqCst),
+                EndReason::EndpointClosure as u8,
+                &conn.get_metadata(),
             );
             let _ = device.event_queue.push(info);
         }
@@ -621,6 +952,8 @@ pub fn endpoint_closure_v6(data: CalloutData) {
                     conn.bandwidth_usage.rx_packets.load(Ordering::SeqCst),
                     conn.bandwidth_usage.tx_bytes.load(Ordering::SeqCst),
                     conn.bandwidth_usage.tx_packets.load(Ordering::SeqCst),
+                    EndReason::EndpointClosure as u8,
+                    &conn.get_metadata(),
                 );
                 let _ = device.event_queue.push(info);
             }
@@ -659,6 +992,8 @@ pub fn ale_resource_monitor(data: CalloutData) {
                         conn.bandwidth_usage.rx_packets.load(Ordering::SeqCst),
                         conn.bandwidth_usage.tx_bytes.load(Ordering::SeqCst),
                         conn.bandwidth_usage.tx_packets.load(Ordering::SeqCst),
+                        EndReason::ResourceRelease as u8,
+                        &conn.get_metadata(),
                     );
                     let _ = device.event_queue.push(info);
                 }
@@ -690,6 +1025,8 @@ pub fn ale_resource_monitor(data: CalloutData) {
                         conn.bandwidth_usage.rx_packets.load(Ordering::SeqCst),
                         conn.bandwidth_usage.tx_bytes.load(Ordering::SeqCst),
                         conn.bandwidth_usage.tx_packets.load(Ordering::SeqCst),
+                        EndReason::ResourceRelease as u8,
+                        &conn.get_metadata(),
                     );
                     let _ = device.event_queue.push(info);
                 }
@@ -721,6 +1058,8 @@ pub fn ale_resource_monitor(data: CalloutData) {
                         conn.bandwidth_usage.rx_packets.load(Ordering::SeqCst),
                         conn.bandwidth_usage.tx_bytes.load(Ordering::SeqCst),
                         conn.bandwidth_usage.tx_packets.load(Ordering::SeqCst),
+                        EndReason::ResourceRelease as u8,
+                        &conn.get_metadata(),
                     );
                     let _ = device.event_queue.push(info);
                 }
@@ -752,6 +1091,8 @@ pub fn ale_resource_monitor(data: CalloutData) {
                         conn.bandwidth_usage.rx_packets.load(Ordering::SeqCst),
                         conn.bandwidth_usage.tx_bytes.load(Ordering::SeqCst),
                         conn.bandwidth_usage.tx_packets.load(Ordering::SeqCst),
+                        EndReason::ResourceRelease as u8,
+                        &conn.get_metadata(),
                     );
                     let _ = device.event_queue.push(info);
                 }
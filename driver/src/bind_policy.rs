@@ -0,0 +1,219 @@
+//! Source address pinning for the ALE bind redirect layers.
+//!
+//! User space pushes rules that select a process, or every process started from an application, and
+//! the local address its sockets have to bind to. The bind redirect callouts look the owner of each
+//! bind request up here and rewrite the request when a rule matches (see
+//! `ale_callouts::ale_bind_redirect`). Used for split tunnelling: the selected applications end up
+//! bound to the tunnel adapter address (or kept off it) no matter what they asked for.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use smoltcp::wire::IpAddress;
+
+/// What a rule applies to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BindTarget {
+    /// A single process.
+    Process(u64),
+    /// Every process started from this image. The path is in the normalised form sent in connection
+    /// events (`c:\windows\...`, see `process_path.rs`) and compared without case, like Windows
+    /// does for paths.
+    App(String),
+}
+
+impl BindTarget {
+    fn matches(&self, process_id: u64, app_path: Option<&str>) -> bool {
+        match self {
+            BindTarget::Process(pid) => *pid == process_id,
+            BindTarget::App(path) => match app_path {
+                Some(app_path) => path.eq_ignore_ascii_case(app_path),
+                None => false,
+            },
+        }
+    }
+}
+
+/// Pins the sockets of `target` to `address`. A rule only applies to sockets of its own address
+/// family.
+#[derive(Clone, Debug)]
+pub struct BindRule {
+    pub target: BindTarget,
+    pub address: IpAddress,
+}
+
+pub struct BindPolicy {
+    rules: Vec<BindRule>,
+}
+
+impl BindPolicy {
+    pub const fn new() -> Self {
+        Self { rules: Vec::new() }
+    }
+
+    /// Adds the rule. Replaces the rule with the same target and address family, if there is one.
+    pub fn set(&mut self, rule: BindRule) {
+        let ipv6 = is_ipv6(&rule.address);
+        match self
+            .rules
+            .iter_mut()
+            .find(|r| r.target == rule.target && is_ipv6(&r.address) == ipv6)
+        {
+            Some(existing) => existing.address = rule.address,
+            None => self.rules.push(rule),
+        }
+    }
+
+    /// Removes the rule with the given target and address family. Returns false if there was none.
+    pub fn remove(&mut self, target: &BindTarget, ipv6: bool) -> bool {
+        let len = self.rules.len();
+        self.rules
+            .retain(|r| !(r.target == *target && is_ipv6(&r.address) == ipv6));
+        return self.rules.len() != len;
+    }
+
+    pub fn clear(&mut self) {
+        self.rules.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns the address the bind request of a socket has to be rewritten to, or `None` if it
+    /// should be left as it is.
+    ///
+    /// A process rule wins over an application rule, so a single instance can be pinned
+    /// differently from the rest of its application. Binds to loopback are never moved: the
+    /// socket is talking to this machine and would stop working on any other address. Neither are
+    /// binds that already use the pinned address, there is nothing to rewrite.
+    pub fn find(
+        &self,
+        process_id: u64,
+        app_path: Option<&str>,
+        requested: IpAddress,
+    ) -> Option<IpAddress> {
+        let loopback = match requested {
+            IpAddress::Ipv4(address) => address.is_loopback(),
+            IpAddress::Ipv6(address) => address.is_loopback(),
+        };
+        if loopback {
+            return None;
+        }
+        let ipv6 = is_ipv6(&requested);
+        let same_family = |r: &&BindRule| is_ipv6(&r.address) == ipv6;
+
+        let rule = self
+            .rules
+            .iter()
+            .filter(same_family)
+            .find(|r| {
+                matches!(r.target, BindTarget::Process(_)) && r.target.matches(process_id, None)
+            })
+            .or_else(|| {
+                self.rules
+                    .iter()
+                    .filter(same_family)
+                    .find(|r| r.target.matches(process_id, app_path))
+            })?;
+
+        if rule.address == requested {
+            return None;
+        }
+        return Some(rule.address);
+    }
+}
+
+fn is_ipv6(address: &IpAddress) -> bool {
+    matches!(address, IpAddress::Ipv6(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BindPolicy, BindRule, BindTarget};
+    use alloc::string::ToString;
+    use smoltcp::wire::{IpAddress, Ipv4Address, Ipv6Address};
+
+    const TUNNEL_V4: IpAddress = IpAddress::Ipv4(Ipv4Address::new(10, 8, 0, 2));
+    const LAN_V4: IpAddress = IpAddress::Ipv4(Ipv4Address::new(192, 168, 1, 20));
+    const ANY_V4: IpAddress = IpAddress::Ipv4(Ipv4Address::UNSPECIFIED);
+    const ANY_V6: IpAddress = IpAddress::Ipv6(Ipv6Address::UNSPECIFIED);
+
+    fn app_rule(path: &str, address: IpAddress) -> BindRule {
+        BindRule {
+            target: BindTarget::App(path.to_string()),
+            address,
+        }
+    }
+
+    fn process_rule(pid: u64, address: IpAddress) -> BindRule {
+        BindRule {
+            target: BindTarget::Process(pid),
+            address,
+        }
+    }
+
+    #[test]
+    fn empty_policy_matches_nothing() {
+        let policy = BindPolicy::new();
+        assert!(policy.is_empty());
+        assert_eq!(policy.find(1, Some("C:\\app.exe"), ANY_V4), None);
+    }
+
+    #[test]
+    fn process_rule_matches_pid() {
+        let mut policy = BindPolicy::new();
+        policy.set(process_rule(42, TUNNEL_V4));
+        assert_eq!(policy.find(42, None, ANY_V4), Some(TUNNEL_V4));
+        assert_eq!(policy.find(43, None, ANY_V4), None);
+    }
+
+    #[test]
+    fn app_rule_ignores_case() {
+        let mut policy = BindPolicy::new();
+        policy.set(app_rule("\\device\\harddiskvolume3\\app.exe", TUNNEL_V4));
+        assert_eq!(
+            policy.find(7, Some("\\Device\\HarddiskVolume3\\App.exe"), ANY_V4),
+            Some(TUNNEL_V4)
+        );
+        assert_eq!(policy.find(7, Some("\\device\\other.exe"), ANY_V4), None);
+        assert_eq!(policy.find(7, None, ANY_V4), None);
+    }
+
+    #[test]
+    fn process_rule_wins_over_app_rule() {
+        let mut policy = BindPolicy::new();
+        policy.set(app_rule("app.exe", TUNNEL_V4));
+        policy.set(process_rule(5, LAN_V4));
+        assert_eq!(policy.find(5, Some("app.exe"), ANY_V4), Some(LAN_V4));
+        assert_eq!(policy.find(6, Some("app.exe"), ANY_V4), Some(TUNNEL_V4));
+    }
+
+    #[test]
+    fn rules_only_apply_to_their_address_family() {
+        let mut policy = BindPolicy::new();
+        policy.set(process_rule(5, TUNNEL_V4));
+        assert_eq!(policy.find(5, None, ANY_V6), None);
+    }
+
+    #[test]
+    fn loopback_and_same_address_are_left_alone() {
+        let mut policy = BindPolicy::new();
+        policy.set(process_rule(5, TUNNEL_V4));
+        let loopback = IpAddress::Ipv4(Ipv4Address::LOCALHOST);
+        assert_eq!(policy.find(5, None, loopback), None);
+        assert_eq!(policy.find(5, None, TUNNEL_V4), None);
+        assert_eq!(policy.find(5, None, LAN_V4), Some(TUNNEL_V4));
+    }
+
+    #[test]
+    fn set_replaces_and_remove_drops() {
+        let mut policy = BindPolicy::new();
+        policy.set(process_rule(5, TUNNEL_V4));
+        policy.set(process_rule(5, LAN_V4));
+        assert_eq!(policy.find(5, None, ANY_V4), Some(LAN_V4));
+
+        assert!(!policy.remove(&BindTarget::Process(5), true));
+        assert!(policy.remove(&BindTarget::Process(5), false));
+        assert!(policy.is_empty());
+    }
+}
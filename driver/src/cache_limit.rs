@@ -0,0 +1,215 @@
+//! Upper bound of the connection cache.
+//!
+//! A port scan or a UDP flood creates a connection for every packet that does not match an
+//! existing one. The cache counts its entries and the memory they hold, and once the count goes
+//! over the maximum (set with `SetCacheLimit`) it evicts a batch of them: ended connections first,
+//! then the ones that have not been accessed for the longest time. Evicting a batch at a time
+//! keeps the walk over the cache rare while the flood lasts.
+
+use alloc::collections::BinaryHeap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::connection::{Connection, Key};
+
+pub const DEFAULT_MAX_ENTRIES: usize = 100_000;
+
+/// Approximate memory held by a cache entry: the connection in its `Arc`, the slot in the port
+/// array and the strings of its details.
+pub fn entry_size<T: Connection>(conn: &T) -> usize {
+    let details = conn.get_details();
+    let strings = details.process_path.as_ref().map_or(0, |s| s.capacity())
+        + details.user_sid.as_ref().map_or(0, |s| s.capacity());
+    // Strong and weak count of the Arc.
+    size_of::<T>() + 2 * size_of::<usize>() + size_of::<Arc<T>>() + strings
+}
+
+/// Entry count and memory of the cache. Lock free, updated on every insert and removal.
+pub struct CacheUsage {
+    entries: AtomicUsize,
+    bytes: AtomicUsize,
+    evicted: AtomicU64,
+}
+
+impl CacheUsage {
+    pub const fn new() -> Self {
+        Self {
+            entries: AtomicUsize::new(0),
+            bytes: AtomicUsize::new(0),
+            evicted: AtomicU64::new(0),
+        }
+    }
+
+    pub fn added<T: Connection>(&self, conn: &T) {
+        self.entries.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(entry_size(conn), Ordering::Relaxed);
+    }
+
+    pub fn removed<T: Connection>(&self, conn: &T) {
+        self.entries.fetch_sub(1, Ordering::Relaxed);
+        self.bytes.fetch_sub(entry_size(conn), Ordering::Relaxed);
+    }
+
+    pub fn evicted<T: Connection>(&self, conn: &T) {
+        self.removed(conn);
+        self.evicted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn entries(&self) -> usize {
+        self.entries.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Entries evicted since the driver started.
+    pub fn evicted_count(&self) -> u64 {
+        self.evicted.load(Ordering::Relaxed)
+    }
+}
+
+/// Number of entries to evict: none while the count is within `max_entries`, otherwise enough to
+/// get a sixteenth below it. A maximum of 0 means no limit.
+pub fn eviction_count(entries: usize, max_entries: usize) -> usize {
+    if max_entries == 0 || entries <= max_entries {
+        return 0;
+    }
+    return entries - (max_entries - max_entries / 16);
+}
+
+// Ordered by eviction preference: ended before active, then least recently accessed.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Candidate {
+    active: bool,
+    last_accessed: u64,
+    key: Key,
+}
+
+/// Keeps the `count` best candidates for eviction out of all the connections it is offered, so
+/// picking them takes memory for the victims only and not for the whole cache.
+pub struct VictimSelector {
+    // Max-heap: the worst of the kept candidates is on top, and is dropped for a better one.
+    heap: BinaryHeap<Candidate>,
+    count: usize,
+}
+
+impl VictimSelector {
+    pub fn new(count: usize) -> Self {
+        Self {
+            heap: BinaryHeap::with_capacity(count + 1),
+            count,
+        }
+    }
+
+    pub fn offer<T: Connection>(&mut self, conn: &T) {
+        if self.count == 0 {
+            return;
+        }
+        self.heap.push(Candidate {
+            active: !conn.has_ended(),
+            last_accessed: conn.get_last_accessed_time(),
+            key: conn.get_key(),
+        });
+        if self.heap.len() > self.count {
+            self.heap.pop();
+        }
+    }
+
+    /// Keys of the connections to evict.
+    pub fn into_keys(self) -> Vec<Key> {
+        self.heap
+            .into_iter()
+            .map(|candidate| candidate.key)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{entry_size, eviction_count, CacheUsage, VictimSelector};
+    use crate::connection::{Connection, ConnectionV4, Direction, Key};
+    use alloc::string::String;
+    use alloc::vec::Vec;
+    use smoltcp::wire::{IpAddress, IpProtocol, Ipv4Address};
+
+    fn key(local_port: u16) -> Key {
+        Key {
+            protocol: IpProtocol::Udp,
+            local_address: IpAddress::Ipv4(Ipv4Address::new(10, 0, 0, 2)),
+            local_port,
+            remote_address: IpAddress::Ipv4(Ipv4Address::new(1, 1, 1, 1)),
+            remote_port: 53,
+        }
+    }
+
+    fn connection(local_port: u16, last_accessed: u64, ended: bool) -> ConnectionV4 {
+        let conn = ConnectionV4::from_key(&key(local_port), 100, Direction::Outbound).unwrap();
+        conn.set_last_accessed_time(last_accessed);
+        if ended {
+            conn.end(last_accessed);
+        }
+        conn
+    }
+
+    #[test]
+    fn eviction_count_leaves_room() {
+        assert_eq!(eviction_count(1600, 1600), 0);
+        assert_eq!(eviction_count(1601, 1600), 101);
+        assert_eq!(eviction_count(5000, 1600), 3500);
+        // No limit.
+        assert_eq!(eviction_count(5000, 0), 0);
+    }
+
+    #[test]
+    fn ended_go_first_then_least_recently_accessed() {
+        let conns = [
+            connection(1, 500, false),
+            connection(2, 900, true),
+            connection(3, 100, false),
+            connection(4, 300, false),
+            connection(5, 800, true),
+        ];
+
+        let mut selector = VictimSelector::new(3);
+        for conn in conns.iter() {
+            selector.offer(conn);
+        }
+        let mut ports: Vec<u16> = selector
+            .into_keys()
+            .iter()
+            .map(|key| key.local_port)
+            .collect();
+        ports.sort();
+        assert_eq!(ports, [2, 3, 5]);
+    }
+
+    #[test]
+    fn nothing_to_evict() {
+        let mut selector = VictimSelector::new(0);
+        selector.offer(&connection(1, 0, true));
+        assert!(selector.into_keys().is_empty());
+    }
+
+    #[test]
+    fn usage_accounting() {
+        let usage = CacheUsage::new();
+        let plain = connection(1, 0, false);
+        let mut detailed = connection(2, 0, false);
+        detailed.details.process_path = Some(String::from("C:\\Windows\\System32\\svchost.exe"));
+        assert!(entry_size(&detailed) > entry_size(&plain));
+
+        usage.added(&plain);
+        usage.added(&detailed);
+        assert_eq!(usage.entries(), 2);
+        assert_eq!(usage.bytes(), entry_size(&plain) + entry_size(&detailed));
+
+        usage.evicted(&detailed);
+        usage.removed(&plain);
+        assert_eq!(usage.entries(), 0);
+        assert_eq!(usage.bytes(), 0);
+        assert_eq!(usage.evicted_count(), 1);
+    }
+}
@@ -0,0 +1,231 @@
+//! How long the connection cache keeps its entries.
+//!
+//! An ended connection stays in the cache for a while so late packets of it still find their
+//! verdict, and a connection that saw no traffic is eventually reported as ended and dropped. Both
+//! durations are set separately for TCP and UDP with the `SetCacheTimeouts` command: a DNS query
+//! over UDP is done in a second, an idle TCP session can stay open for hours.
+//!
+//! The current time is read through a `Clock`, so the cleanup can be driven by a fake one.
+
+use core::time::Duration;
+
+use protocol::command::SetCacheTimeouts;
+use smoltcp::wire::IpProtocol;
+
+use crate::connection::Connection;
+
+pub trait Clock {
+    /// Current time in milliseconds.
+    fn now_ms(&self) -> u64;
+}
+
+/// The system time, used by the driver.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> u64 {
+        wdk::utils::get_system_timestamp_ms()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct ProtocolTimeouts {
+    // Time an ended connection stays in the cache.
+    pub ended_retention_ms: u64,
+    // Time without traffic after which a connection is reported as ended.
+    pub idle_expiry_ms: u64,
+}
+
+#[derive(Clone, Copy)]
+pub struct CacheTimeouts {
+    pub tcp: ProtocolTimeouts,
+    pub udp: ProtocolTimeouts,
+}
+
+/// What the cleanup does with a cache entry.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Expiry {
+    Keep,
+    // Ended long enough ago, removed without an event (it was sent when the connection ended).
+    Remove,
+    // No traffic for too long, removed and reported as ended.
+    Idle,
+}
+
+impl CacheTimeouts {
+    pub const fn new() -> Self {
+        const DEFAULT: ProtocolTimeouts = ProtocolTimeouts {
+            ended_retention_ms: Duration::from_secs(60).as_millis() as u64,
+            idle_expiry_ms: Duration::from_secs(120).as_millis() as u64,
+        };
+        Self {
+            tcp: DEFAULT,
+            udp: DEFAULT,
+        }
+    }
+
+    /// Applies the command. A value of 0 keeps the current one.
+    pub fn update(&mut self, command: &SetCacheTimeouts) {
+        fn set(value: &mut u64, secs: u32) {
+            if secs != 0 {
+                *value = Duration::from_secs(secs as u64).as_millis() as u64;
+            }
+        }
+        set(
+            &mut self.tcp.ended_retention_ms,
+            command.tcp_ended_retention_secs,
+        );
+        set(&mut self.tcp.idle_expiry_ms, command.tcp_idle_expiry_secs);
+        set(
+            &mut self.udp.ended_retention_ms,
+            command.udp_ended_retention_secs,
+        );
+        set(&mut self.udp.idle_expiry_ms, command.udp_idle_expiry_secs);
+    }
+
+    /// The flows of the protocols without ports (see `flow_table.rs`) are connectionless like UDP,
+    /// so they use the UDP timeouts.
+    pub fn for_protocol(&self, protocol: IpProtocol) -> &ProtocolTimeouts {
+        if protocol == IpProtocol::Tcp {
+            &self.tcp
+        } else {
+            &self.udp
+        }
+    }
+
+    pub fn expiry<T: Connection>(&self, conn: &T, now: u64) -> Expiry {
+        let timeouts = self.for_protocol(conn.get_protocol());
+        // An ended connection was reported when it ended, it only waits for its retention.
+        if conn.has_ended() {
+            if conn.get_end_time() < now.saturating_sub(timeouts.ended_retention_ms) {
+                return Expiry::Remove;
+            }
+            return Expiry::Keep;
+        }
+        if conn.get_last_accessed_time() < now.saturating_sub(timeouts.idle_expiry_ms) {
+            return Expiry::Idle;
+        }
+        return Expiry::Keep;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheTimeouts, Clock, Expiry};
+    use crate::connection::{Connection, ConnectionV4, Direction, Key};
+    use core::cell::Cell;
+    use protocol::command::SetCacheTimeouts;
+    use smoltcp::wire::{IpAddress, IpProtocol, Ipv4Address};
+
+    struct FakeClock {
+        now: Cell<u64>,
+    }
+
+    impl FakeClock {
+        fn new() -> Self {
+            Self {
+                now: Cell::new(1_000_000),
+            }
+        }
+
+        fn advance_secs(&self, secs: u64) {
+            self.now.set(self.now.get() + secs * 1000);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now_ms(&self) -> u64 {
+            self.now.get()
+        }
+    }
+
+    fn connection(protocol: IpProtocol, clock: &FakeClock) -> ConnectionV4 {
+        let key = Key {
+            protocol,
+            local_address: IpAddress::Ipv4(Ipv4Address::new(10, 0, 0, 2)),
+            local_port: 50000,
+            remote_address: IpAddress::Ipv4(Ipv4Address::new(1, 1, 1, 1)),
+            remote_port: 53,
+        };
+        let conn = ConnectionV4::from_key(&key, 100, Direction::Outbound).unwrap();
+        conn.set_last_accessed_time(clock.now_ms());
+        conn
+    }
+
+    fn set(
+        tcp_ended_retention_secs: u32,
+        tcp_idle_expiry_secs: u32,
+        udp_ended_retention_secs: u32,
+        udp_idle_expiry_secs: u32,
+    ) -> SetCacheTimeouts {
+        SetCacheTimeouts {
+            tcp_ended_retention_secs,
+            tcp_idle_expiry_secs,
+            udp_ended_retention_secs,
+            udp_idle_expiry_secs,
+        }
+    }
+
+    #[test]
+    fn default_timeouts() {
+        let timeouts = CacheTimeouts::new();
+        let clock = FakeClock::new();
+        let conn = connection(IpProtocol::Tcp, &clock);
+
+        clock.advance_secs(120);
+        assert_eq!(timeouts.expiry(&conn, clock.now_ms()), Expiry::Keep);
+        clock.advance_secs(1);
+        assert_eq!(timeouts.expiry(&conn, clock.now_ms()), Expiry::Idle);
+
+        // Traffic keeps the connection alive.
+        conn.set_last_accessed_time(clock.now_ms());
+        assert_eq!(timeouts.expiry(&conn, clock.now_ms()), Expiry::Keep);
+
+        // An ended connection is never reported as idle, and goes after a minute.
+        conn.end(clock.now_ms());
+        clock.advance_secs(60);
+        assert_eq!(timeouts.expiry(&conn, clock.now_ms()), Expiry::Keep);
+        clock.advance_secs(1);
+        assert_eq!(timeouts.expiry(&conn, clock.now_ms()), Expiry::Remove);
+    }
+
+    #[test]
+    fn timeouts_per_protocol() {
+        let mut timeouts = CacheTimeouts::new();
+        timeouts.update(&set(60, 3600, 5, 30));
+        let clock = FakeClock::new();
+        let tcp = connection(IpProtocol::Tcp, &clock);
+        let udp = connection(IpProtocol::Udp, &clock);
+
+        clock.advance_secs(31);
+        assert_eq!(timeouts.expiry(&tcp, clock.now_ms()), Expiry::Keep);
+        assert_eq!(timeouts.expiry(&udp, clock.now_ms()), Expiry::Idle);
+
+        tcp.end(clock.now_ms());
+        udp.end(clock.now_ms());
+        clock.advance_secs(6);
+        assert_eq!(timeouts.expiry(&tcp, clock.now_ms()), Expiry::Keep);
+        assert_eq!(timeouts.expiry(&udp, clock.now_ms()), Expiry::Remove);
+        clock.advance_secs(55);
+        assert_eq!(timeouts.expiry(&tcp, clock.now_ms()), Expiry::Remove);
+    }
+
+    #[test]
+    fn zero_keeps_current_value() {
+        let mut timeouts = CacheTimeouts::new();
+        timeouts.update(&set(0, 3600, 0, 0));
+        assert_eq!(timeouts.tcp.ended_retention_ms, 60_000);
+        assert_eq!(timeouts.tcp.idle_expiry_ms, 3_600_000);
+        assert_eq!(timeouts.udp.ended_retention_ms, 60_000);
+        assert_eq!(timeouts.udp.idle_expiry_ms, 120_000);
+    }
+
+    #[test]
+    fn early_clock_does_not_underflow() {
+        let timeouts = CacheTimeouts::new();
+        let clock = FakeClock::new();
+        clock.now.set(0);
+        let conn = connection(IpProtocol::Udp, &clock);
+        assert_eq!(timeouts.expiry(&conn, clock.now_ms()), Expiry::Keep);
+    }
+}
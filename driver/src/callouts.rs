@@ -68,6 +68,26 @@ pub fn get_callout_vec() -> Vec<Callout> {
             ale_callouts::endpoint_closure_v6,
         ),
         // -----------------------------------------
+        // ALE bind redirect layers
+        Callout::new(
+            "AleBindRedirectV4",
+            "ALE layer for redirecting the local address of ipv4 sockets",
+            0x5f3a6c2e_8d41_4b7a_9e0c_2b7d1f94a6e3,
+            Layer::AleBindRedirectV4,
+            consts::FWP_ACTION_CALLOUT_TERMINATING,
+            FilterType::NonResettable,
+            ale_callouts::ale_bind_redirect_v4,
+        ),
+        Callout::new(
+            "AleBindRedirectV6",
+            "ALE layer for redirecting the local address of ipv6 sockets",
+            0xc81e4d97_3a5b_4f26_8b1d_6e0a92c7f354,
+            Layer::AleBindRedirectV6,
+            consts::FWP_ACTION_CALLOUT_TERMINATING,
+            FilterType::NonResettable,
+            ale_callouts::ale_bind_redirect_v6,
+        ),
+        // -----------------------------------------
         // ALE resource assignment and release.
         // Callout::new(
         //     "AleResourceAssignmentV4",
@@ -1,12 +1,17 @@
 use alloc::string::{String, ToString};
 use core::{
     fmt::{Debug, Display},
-    sync::atomic::{AtomicU64, AtomicU8, Ordering},
+    sync::atomic::{AtomicU64, Ordering},
 };
-use num::FromPrimitive;
 use num_derive::FromPrimitive;
+use protocol::info::ConnectionMetadata;
 use smoltcp::wire::{IpAddress, IpProtocol, Ipv4Address, Ipv6Address};
 
+use crate::egress_policy::ConnectionEgress;
+use crate::tcp_reset::TcpSequence;
+use crate::tcp_state::TcpStateTracker;
+use crate::verdict_state::VerdictState;
+
 pub static PM_DNS_PORT: u16 = 53;
 pub static PM_SPN_PORT: u16 = 717;
 
@@ -90,6 +95,23 @@ impl Debug for Direction {
     }
 }
 
+// Why a connection ended. Sent with the end event. Make sure this in sync with the Go version.
+#[derive(Copy, Clone, FromPrimitive)]
+#[repr(u8)]
+#[rustfmt::skip]
+pub enum EndReason {
+    EndpointClosure = 0, // The socket was closed.
+    ResourceRelease = 1, // The local port was released, or its assignment was discarded.
+    IdleTimeout     = 2, // Nothing was seen for a while, the connection was dropped from the cache.
+    ProcessExit     = 3,
+    ClearCache      = 4,
+    Shutdown        = 5,
+    Evicted         = 6, // The cache was full, see `cache_limit.rs`.
+    KilledByCommand = 7, // Reset with a `KillConnection` command, see `tcp_reset.rs`.
+    TcpClosed       = 8, // Both ends sent a FIN, see `tcp_state.rs`.
+    TcpReset        = 9, // One end sent a RST.
+}
+
 pub trait Connection {
     fn redirect_info(&self) -> Option<RedirectInfo> {
         let redirect_address = if self.is_ipv6() {
@@ -136,6 +158,19 @@ pub trait Connection {
     fn equals(&self, key: &Key) -> bool;
     /// Returns true if the connection is equal to the given key for redirecting. The key is considered equal if the remote port and address are equal.
     fn redirect_equals(&self, key: &Key) -> bool;
+    /// Returns true if the key is the connection as it is seen on its egress interface: the same
+    /// ports and remote address, with the route source address as the local address.
+    fn egress_equals(&self, key: &Key) -> bool {
+        let Some(egress) = self.get_egress() else {
+            return false;
+        };
+        key.local_port == self.get_local_port()
+            && key.remote_port == self.get_remote_port()
+            && key.local_address == egress.route.source_address
+            && key.remote_address == self.get_remote_address()
+    }
+    /// Returns the egress interface override of the connection.
+    fn get_egress(&self) -> Option<&ConnectionEgress>;
     /// Returns the protocol of the connection.
     fn get_protocol(&self) -> IpProtocol;
     /// Returns the verdict of the connection.
@@ -154,6 +189,18 @@ pub trait Connection {
     fn get_direction(&self) -> Direction;
     // Returns the process id of the connection.
     fn get_process_id(&self) -> u64;
+    /// Returns the details captured when the connection was added.
+    fn get_details(&self) -> &ConnectionDetails;
+    /// Returns the sequence numbers seen on a TCP connection (see `tcp_reset.rs`).
+    fn get_tcp_sequence(&self) -> &TcpSequence;
+    /// Returns the state of a TCP connection (see `tcp_state.rs`).
+    fn get_tcp_state(&self) -> &TcpStateTracker;
+    /// Returns the id the cache gave the connection. 0 until it is added.
+    fn get_id(&self) -> u64;
+    /// Returns the metadata block sent with the events of the connection.
+    fn get_metadata(&self) -> ConnectionMetadata<'_> {
+        self.get_details().as_metadata(self.get_id())
+    }
     /// Ends the connection.
     fn end(&self, timestamp: u64);
     /// Returns true if the connection has ended.
@@ -166,7 +213,13 @@ pub trait Connection {
     fn get_last_accessed_time(&self) -> u64;
     /// Sets the timestamp when the connection was last accessed.
     fn set_last_accessed_time(&self, timestamp: u64);
-    fn set_verdict(&self, verdict: Verdict);
+    /// Sets a verdict that does not expire.
+    fn set_verdict(&self, verdict: Verdict) {
+        self.set_verdict_until(verdict, 0);
+    }
+    /// Sets a verdict that reverts to `Undecided` at `expiry_ms` (see `verdict_state.rs`), 0 for
+    /// never.
+    fn set_verdict_until(&self, verdict: Verdict, expiry_ms: u64);
 
     fn get_bandwidth_usage(&self) -> &BandwidthUsage;
 
@@ -195,6 +248,37 @@ pub trait Connection {
     }
 }
 
+/// Details about the owner of a connection. Captured once, when the connection is added, and sent
+/// with its events.
+#[derive(Clone, Default)]
+pub struct ConnectionDetails {
+    /// Image path of the process, normalised (see `process_path.rs`).
+    pub process_path: Option<String>,
+    /// SID of the user the process runs as, in its string form (`S-1-5-21-...`).
+    pub user_sid: Option<String>,
+    /// Process that started the owning process. 0 if it started before the driver.
+    pub parent_process_id: u64,
+    /// Interface the connection uses: the egress route interface if the process has one.
+    pub interface_index: u32,
+    pub sub_interface_index: u32,
+    /// Network compartment of the connection (1 is the default compartment).
+    pub compartment_id: u32,
+}
+
+impl ConnectionDetails {
+    pub fn as_metadata(&self, connection_id: u64) -> ConnectionMetadata<'_> {
+        ConnectionMetadata {
+            connection_id,
+            process_path: self.process_path.as_deref().unwrap_or(""),
+            user_sid: self.user_sid.as_deref().unwrap_or(""),
+            interface_index: self.interface_index,
+            sub_interface_index: self.sub_interface_index,
+            compartment_id: self.compartment_id,
+            parent_process_id: self.parent_process_id,
+        }
+    }
+}
+
 pub struct BandwidthUsage {
     pub rx_bytes: AtomicU64,
     pub rx_packets: AtomicU64,
@@ -234,12 +318,20 @@ pub struct ConnectionV4 {
     pub(crate) local_port: u16,
     pub(crate) remote_address: Ipv4Address,
     pub(crate) remote_port: u16,
-    pub(crate) verdict: AtomicU8,
+    pub(crate) verdict: VerdictState,
     pub(crate) last_accessed_timestamp: AtomicU64,
     pub(crate) bandwidth_usage: BandwidthUsage,
     pub(crate) process_id: u64,
     pub(crate) end_timestamp: AtomicU64,
     pub(crate) direction: Direction,
+    pub(crate) egress: Option<ConnectionEgress>,
+    pub(crate) details: ConnectionDetails,
+    /// Unique for the lifetime of the driver, assigned by the cache. Used by user space to refer
+    /// to the connection without its 5-tuple, which can be reused.
+    pub(crate) id: u64,
+    /// Sequence numbers of the last TCP segments, to reset the connection with.
+    pub(crate) tcp_sequence: TcpSequence,
+    pub(crate) tcp_state: TcpStateTracker,
 }
 
 pub struct ConnectionV6 {
@@ -248,12 +340,20 @@ pub struct ConnectionV6 {
     pub(crate) local_port: u16,
     pub(crate) remote_address: Ipv6Address,
     pub(crate) remote_port: u16,
-    pub(crate) verdict: AtomicU8,
+    pub(crate) verdict: VerdictState,
     pub(crate) last_accessed_timestamp: AtomicU64,
     pub(crate) bandwidth_usage: BandwidthUsage,
     pub(crate) process_id: u64,
     pub(crate) end_timestamp: AtomicU64,
     pub(crate) direction: Direction,
+    pub(crate) egress: Option<ConnectionEgress>,
+    pub(crate) details: ConnectionDetails,
+    /// Unique for the lifetime of the driver, assigned by the cache. Used by user space to refer
+    /// to the connection without its 5-tuple, which can be reused.
+    pub(crate) id: u64,
+    /// Sequence numbers of the last TCP segments, to reset the connection with.
+    pub(crate) tcp_sequence: TcpSequence,
+    pub(crate) tcp_state: TcpStateTracker,
 }
 
 #[derive(Debug)]
@@ -285,7 +385,7 @@ impl ConnectionV4 {
             local_port: key.local_port,
             remote_address,
             remote_port: key.remote_port,
-            verdict: AtomicU8::new(Verdict::Undecided as u8),
+            verdict: VerdictState::new(Verdict::Undecided),
             last_accessed_timestamp: AtomicU64::new(timestamp),
             bandwidth_usage: BandwidthUsage {
                 rx_bytes: AtomicU64::new(0),
@@ -296,6 +396,11 @@ impl ConnectionV4 {
             process_id,
             direction,
             end_timestamp: AtomicU64::new(0),
+            egress: None,
+            details: ConnectionDetails::default(),
+            id: 0,
+            tcp_sequence: TcpSequence::new(),
+            tcp_state: TcpStateTracker::new(),
         })
     }
 }
@@ -320,8 +425,8 @@ impl Connection for ConnectionV4 {
     }
 
     fn redirect_equals(&self, key: &Key) -> bool {
-        match FromPrimitive::from_u8(self.verdict.load(Ordering::SeqCst)) {
-            Some(Verdict::RedirectNameServer) => {
+        match self.get_verdict() {
+            Verdict::RedirectNameServer => {
                 if key.remote_port != PM_DNS_PORT {
                     return false;
                 }
@@ -331,7 +436,7 @@ impl Connection for ConnectionV4 {
                     IpAddress::Ipv6(_) => false,
                 }
             }
-            Some(Verdict::RedirectTunnel) => {
+            Verdict::RedirectTunnel => {
                 if key.remote_port != PM_SPN_PORT {
                     return false;
                 }
@@ -346,8 +451,7 @@ impl Connection for ConnectionV4 {
     }
 
     fn get_verdict(&self) -> Verdict {
-        use num_traits::FromPrimitive;
-        Verdict::from_u8(self.verdict.load(Ordering::SeqCst)).unwrap_or(Verdict::Undecided)
+        self.verdict.get(wdk::utils::get_system_timestamp_ms)
     }
 
     fn get_local_address(&self) -> IpAddress {
@@ -378,6 +482,26 @@ impl Connection for ConnectionV4 {
         self.direction
     }
 
+    fn get_egress(&self) -> Option<&ConnectionEgress> {
+        self.egress.as_ref()
+    }
+
+    fn get_details(&self) -> &ConnectionDetails {
+        &self.details
+    }
+
+    fn get_tcp_sequence(&self) -> &TcpSequence {
+        &self.tcp_sequence
+    }
+
+    fn get_tcp_state(&self) -> &TcpStateTracker {
+        &self.tcp_state
+    }
+
+    fn get_id(&self) -> u64 {
+        self.id
+    }
+
     fn end(&self, timestamp: u64) {
         self.end_timestamp.store(timestamp, Ordering::SeqCst);
     }
@@ -395,8 +519,8 @@ impl Connection for ConnectionV4 {
             .store(timestamp, Ordering::SeqCst);
     }
 
-    fn set_verdict(&self, verdict: Verdict) {
-        self.verdict.store(verdict as u8, Ordering::SeqCst);
+    fn set_verdict_until(&self, verdict: Verdict, expiry_ms: u64) {
+        self.verdict.set(verdict, expiry_ms);
     }
 
     fn get_bandwidth_usage(&self) -> &BandwidthUsage {
@@ -412,7 +536,7 @@ impl Clone for ConnectionV4 {
             local_port: self.local_port,
             remote_address: self.remote_address,
             remote_port: self.remote_port,
-            verdict: AtomicU8::new(self.verdict.load(Ordering::SeqCst)),
+            verdict: self.verdict.clone(),
             bandwidth_usage: self.bandwidth_usage.clone(),
             last_accessed_timestamp: AtomicU64::new(
                 self.last_accessed_timestamp.load(Ordering::SeqCst),
@@ -420,6 +544,11 @@ impl Clone for ConnectionV4 {
             process_id: self.process_id,
             end_timestamp: AtomicU64::new(self.end_timestamp.load(Ordering::SeqCst)),
             direction: self.direction,
+            egress: self.egress.clone(),
+            details: self.details.clone(),
+            id: self.id,
+            tcp_sequence: self.tcp_sequence.clone(),
+            tcp_state: self.tcp_state.clone(),
         }
     }
 }
@@ -442,7 +571,7 @@ impl ConnectionV6 {
             local_port: key.local_port,
             remote_address,
             remote_port: key.remote_port,
-            verdict: AtomicU8::new(Verdict::Undecided as u8),
+            verdict: VerdictState::new(Verdict::Undecided),
             last_accessed_timestamp: AtomicU64::new(timestamp),
             bandwidth_usage: BandwidthUsage {
                 rx_bytes: AtomicU64::new(0),
@@ -453,6 +582,11 @@ impl ConnectionV6 {
             process_id,
             direction,
             end_timestamp: AtomicU64::new(0),
+            egress: None,
+            details: ConnectionDetails::default(),
+            id: 0,
+            tcp_sequence: TcpSequence::new(),
+            tcp_state: TcpStateTracker::new(),
         })
     }
 }
@@ -477,8 +611,8 @@ impl Connection for ConnectionV6 {
     }
 
     fn redirect_equals(&self, key: &Key) -> bool {
-        match Verdict::from_u8(self.verdict.load(Ordering::SeqCst)) {
-            Some(Verdict::RedirectNameServer) => {
+        match self.get_verdict() {
+            Verdict::RedirectNameServer => {
                 if key.remote_port != PM_DNS_PORT {
                     return false;
                 }
@@ -488,7 +622,7 @@ impl Connection for ConnectionV6 {
                     IpAddress::Ipv6(a) => a.is_loopback(),
                 }
             }
-            Some(Verdict::RedirectTunnel) => {
+            Verdict::RedirectTunnel => {
                 if key.remote_port != PM_SPN_PORT {
                     return false;
                 }
@@ -503,8 +637,7 @@ impl Connection for ConnectionV6 {
     }
 
     fn get_verdict(&self) -> Verdict {
-        use num_traits::FromPrimitive;
-        Verdict::from_u8(self.verdict.load(Ordering::SeqCst)).unwrap_or(Verdict::Undecided)
+        self.verdict.get(wdk::utils::get_system_timestamp_ms)
     }
 
     fn get_local_address(&self) -> IpAddress {
@@ -535,6 +668,26 @@ impl Connection for ConnectionV6 {
         self.direction
     }
 
+    fn get_egress(&self) -> Option<&ConnectionEgress> {
+        self.egress.as_ref()
+    }
+
+    fn get_details(&self) -> &ConnectionDetails {
+        &self.details
+    }
+
+    fn get_tcp_sequence(&self) -> &TcpSequence {
+        &self.tcp_sequence
+    }
+
+    fn get_tcp_state(&self) -> &TcpStateTracker {
+        &self.tcp_state
+    }
+
+    fn get_id(&self) -> u64 {
+        self.id
+    }
+
     fn end(&self, timestamp: u64) {
         self.end_timestamp.store(timestamp, Ordering::SeqCst);
     }
@@ -552,8 +705,8 @@ impl Connection for ConnectionV6 {
             .store(timestamp, Ordering::SeqCst);
     }
 
-    fn set_verdict(&self, verdict: Verdict) {
-        self.verdict.store(verdict as u8, Ordering::SeqCst);
+    fn set_verdict_until(&self, verdict: Verdict, expiry_ms: u64) {
+        self.verdict.set(verdict, expiry_ms);
     }
 
     fn get_bandwidth_usage(&self) -> &BandwidthUsage {
@@ -569,7 +722,7 @@ impl Clone for ConnectionV6 {
             local_port: self.local_port,
             remote_address: self.remote_address,
             remote_port: self.remote_port,
-            verdict: AtomicU8::new(self.verdict.load(Ordering::SeqCst)),
+            verdict: self.verdict.clone(),
             bandwidth_usage: self.bandwidth_usage.clone(),
             last_accessed_timestamp: AtomicU64::new(
                 self.last_accessed_timestamp.load(Ordering::SeqCst),
@@ -577,6 +730,11 @@ impl Clone for ConnectionV6 {
             process_id: self.process_id,
             end_timestamp: AtomicU64::new(self.end_timestamp.load(Ordering::SeqCst)),
             direction: self.direction,
+            egress: self.egress.clone(),
+            details: self.details.clone(),
+            id: self.id,
+            tcp_sequence: self.tcp_sequence.clone(),
+            tcp_state: self.tcp_state.clone(),
         }
     }
 }
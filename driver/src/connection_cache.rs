@@ -1,82 +1,91 @@
 use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
-use core::sync::atomic::Ordering;
-use core::time::Duration;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 
+use crate::cache_limit::{self, CacheUsage, VictimSelector};
+use crate::cache_timeouts::{CacheTimeouts, Clock, Expiry};
 use crate::connection::{Connection, ConnectionV4, ConnectionV6, Key, RedirectInfo, Verdict};
+use crate::connection_filter::{self, ConnectionFilter};
+use crate::epoch::{self, Collector};
+use crate::flow_table::{self, FlowTable};
 use crate::mpsc_queue::MpscQueue;
-use crate::rcu_port::{ConnectionArray, RCUPort};
+use crate::port_bucket;
+use crate::port_table::PortTable;
+use crate::process_index::ProcessIndex;
+use crate::rcu_port::{free_array, ConnectionArray, RCUPort};
 use smoltcp::wire::IpProtocol;
+use wdk::process::ProcessEvent;
+use wdk::rw_spin_lock::Mutex;
 
-// 0-65535 must be valid ports. 0 is not a valid port number but its kept for future proofing for special cases.
-const PORT_COUT: usize = u16::MAX as usize + 1;
-type PortArray<T> = [RCUPort<T>; PORT_COUT];
-
-// Selects the correct per-port slot from the tcp/udp arrays.
+// Selects the correct per-port slot from the tcp/udp tables. None if nothing was ever added on the
+// port's block.
 fn get_port<'a, T: Connection>(
-    tcp: &'a PortArray<T>,
-    udp: &'a PortArray<T>,
+    tcp: &'a PortTable<T>,
+    udp: &'a PortTable<T>,
     protocol: IpProtocol,
     local_port: u16,
 ) -> Option<&'a RCUPort<T>> {
     match protocol {
-        IpProtocol::Tcp => Some(&tcp[local_port as usize]),
-        IpProtocol::Udp => Some(&udp[local_port as usize]),
+        IpProtocol::Tcp => tcp.get(local_port),
+        IpProtocol::Udp => udp.get(local_port),
         _ => None,
     }
 }
 
-fn alloc_port_array<T: Connection>() -> Box<PortArray<T>> {
-    // RCUPort<T> is valid when zeroed:
-    //   - AtomicPtr is valid as null
-    //   - Mutex<()> / RwSpinLock uses i32 which is valid at 0
-    // alloc_zeroed is used to allocate directly on the heap.
-    let layout = core::alloc::Layout::new::<PortArray<T>>();
-    unsafe {
-        let ptr = alloc::alloc::alloc_zeroed(layout) as *mut PortArray<T>;
-        Box::from_raw(ptr)
+// Same as `get_port`, allocating the port's block if needed. Only for adding connections.
+fn get_port_or_insert<'a, T: Connection>(
+    tcp: &'a PortTable<T>,
+    udp: &'a PortTable<T>,
+    protocol: IpProtocol,
+    local_port: u16,
+) -> Option<&'a RCUPort<T>> {
+    match protocol {
+        IpProtocol::Tcp => tcp.get_or_insert(local_port),
+        IpProtocol::Udp => udp.get_or_insert(local_port),
+        _ => None,
     }
 }
 
+// Removes all connections and returns the ones that had not ended yet.
 fn ports_clear<T: Connection>(
-    tcp: &PortArray<T>,
-    udp: &PortArray<T>,
+    tcp: &PortTable<T>,
+    udp: &PortTable<T>,
     queue: &MpscQueue<ConnectionArray<T>>,
-) {
-    // Free all tcp connections
-    for port in tcp.iter() {
-        if !port.is_empty() {
-            port.lock().publish(None, queue);
-        }
-    }
-
-    // Free all udp connections
-    for port in udp.iter() {
+    collector: &Collector,
+    usage: &CacheUsage,
+) -> Vec<Arc<T>> {
+    let mut active = Vec::new();
+    for port in tcp.iter().chain(udp.iter()) {
         if !port.is_empty() {
-            port.lock().publish(None, queue);
+            let port_guard = port.lock();
+            if let Some(snap) = port_guard.snapshot() {
+                for conn in snap.iter() {
+                    usage.removed(conn.as_ref());
+                }
+                active.extend(snap.iter().filter(|conn| !conn.has_ended()).cloned());
+            }
+            port_guard.publish(None, queue, collector);
         }
     }
+    active
 }
 
 // get_connection generic function for getting a connection.
 fn get_connection<T: Connection>(
-    tcp: &PortArray<T>,
-    udp: &PortArray<T>,
+    tcp: &PortTable<T>,
+    udp: &PortTable<T>,
+    collector: &Collector,
     key: &Key,
 ) -> Option<Arc<T>> {
     // Get the connection array port.
-    let port = get_port(tcp, udp, key.protocol, key.local_port)?.read();
-    let snap = port.get()?;
-    // Iterate over all connection and find the connection.
-    for conn in snap.iter() {
-        if conn.equals(key) || conn.redirect_equals(key) {
-            // Update last accessed.
-            conn.set_last_accessed_time(wdk::utils::get_system_timestamp_ms());
-            return Some(conn.clone());
-        }
-    }
-    None
+    let guard = collector.pin();
+    let snap = get_port(tcp, udp, key.protocol, key.local_port)?.read(&guard)?;
+    let conn = port_bucket::find(snap, key)?;
+    // Update last accessed.
+    conn.set_last_accessed_time(wdk::utils::get_system_timestamp_ms());
+    Some(conn.clone())
 }
 
 // Adds a connection to its port. If an equal one is already present, its
@@ -86,15 +95,17 @@ fn get_connection<T: Connection>(
 // concurrent adds of the same connection collapse to a single entry. This
 // matters because `get_connection` + add is not atomic and the packet layer
 // runs on multiple CPUs, so two callers can miss the same connection and race
-// to insert it.
+// to insert it. Returns the id of the connection that is in the cache, and true if that is `new`.
 fn add_connection<T: Connection>(
-    tcp: &PortArray<T>,
-    udp: &PortArray<T>,
+    tcp: &PortTable<T>,
+    udp: &PortTable<T>,
     queue: &MpscQueue<ConnectionArray<T>>,
+    collector: &Collector,
+    usage: &CacheUsage,
     new: T,
-) {
-    let Some(port) = get_port(tcp, udp, new.get_protocol(), new.get_local_port()) else {
-        return;
+) -> (u64, bool) {
+    let Some(port) = get_port_or_insert(tcp, udp, new.get_protocol(), new.get_local_port()) else {
+        return (0, false);
     };
 
     // Identity key, taken before `new` is moved into the Arc.
@@ -104,62 +115,86 @@ fn add_connection<T: Connection>(
     // Lock for writing.
     let port_lock = port.lock();
 
-    // Copy the current port connection array. If the connection is already
-    // present, fold the new observation into it instead of inserting a duplicate.
-    let mut new_vec = match port_lock.snapshot() {
-        Some(snap) => {
-            // Check if connection is already in.
-            for conn in snap.iter() {
-                if conn.equals(&key) {
-                    // Refresh last-accessed and accumulate bandwidth so no traffic
-                    // accounting is lost. Identity, verdict and process id are left
-                    // untouched — the existing connection stays authoritative.
-                    conn.set_last_accessed_time(new_arc.get_last_accessed_time());
-                    conn.get_bandwidth_usage()
-                        .add_from(new_arc.get_bandwidth_usage());
-                    return;
-                }
-            }
+    // If the connection is already present, fold the new observation into it instead of
+    // inserting a duplicate.
+    let snap = port_lock.snapshot().unwrap_or(&[]);
+    if let Some(conn) = port_bucket::find_exact(snap, &key) {
+        // Refresh last-accessed and accumulate bandwidth so no traffic
+        // accounting is lost. Identity, verdict and process id are left
+        // untouched — the existing connection stays authoritative.
+        conn.set_last_accessed_time(new_arc.get_last_accessed_time());
+        conn.get_bandwidth_usage()
+            .add_from(new_arc.get_bandwidth_usage());
+        return (conn.get_id(), false);
+    }
 
-            let mut v = Vec::with_capacity(snap.len() + 1);
-            v.extend(snap.iter().cloned());
-            v
-        }
-        None => Vec::with_capacity(1),
-    };
+    // Copy the current port connection array with the new connection, and publish.
+    let id = new_arc.get_id();
+    usage.added(new_arc.as_ref());
+    let new_vec = port_bucket::insert(snap, new_arc);
+    port_lock.publish(Some(new_vec.into_boxed_slice()), queue, collector);
+    (id, true)
+}
 
-    // Add the new connection and publish.
-    new_vec.push(new_arc);
-    port_lock.publish(Some(new_vec.into_boxed_slice()), queue);
+// Removes the connection matching `key` from its port and returns it.
+fn remove_connection<T: Connection>(
+    tcp: &PortTable<T>,
+    udp: &PortTable<T>,
+    queue: &MpscQueue<ConnectionArray<T>>,
+    collector: &Collector,
+    key: &Key,
+) -> Option<Arc<T>> {
+    let port = get_port(tcp, udp, key.protocol, key.local_port)?;
+    let port_guard = port.lock();
+    let snap = port_guard.snapshot()?;
+    let removed = port_bucket::find_exact(snap, key)?.clone();
+    // Keeps the order, and so the layout of the bucket.
+    let survivors: Vec<Arc<T>> = snap
+        .iter()
+        .filter(|conn| !Arc::ptr_eq(conn, &removed))
+        .cloned()
+        .collect();
+    port_guard.publish(
+        if survivors.is_empty() {
+            None
+        } else {
+            Some(survivors.into_boxed_slice())
+        },
+        queue,
+        collector,
+    );
+    Some(removed)
 }
 
-// Marks the connection matching `key` as ended and returns it. Read-only guard.
+// Marks the connection matching `key` as ended and returns it. None if it ended already, so it is
+// only reported once. Read-only guard.
 fn end_connection<T: Connection>(
-    tcp: &PortArray<T>,
-    udp: &PortArray<T>,
+    tcp: &PortTable<T>,
+    udp: &PortTable<T>,
+    collector: &Collector,
     key: &Key,
 ) -> Option<Arc<T>> {
-    let port = get_port(tcp, udp, key.protocol, key.local_port)?.read();
-    let snap = port.get()?;
-    for conn in snap.iter() {
-        if conn.equals(key) {
-            conn.end(wdk::utils::get_system_timestamp_ms());
-            return Some(conn.clone());
-        }
+    let guard = collector.pin();
+    let snap = get_port(tcp, udp, key.protocol, key.local_port)?.read(&guard)?;
+    let conn = port_bucket::find_exact(snap, key)?;
+    if conn.has_ended() {
+        return None;
     }
-    None
+    conn.end(wdk::utils::get_system_timestamp_ms());
+    Some(conn.clone())
 }
 
 // Marks every active connection on the given (protocol, port) as ended and
 // returns them. Read-only guard.
 fn end_all_on_port<T: Connection>(
-    tcp: &PortArray<T>,
-    udp: &PortArray<T>,
+    tcp: &PortTable<T>,
+    udp: &PortTable<T>,
+    collector: &Collector,
     protocol: IpProtocol,
     local_port: u16,
 ) -> Option<Vec<Arc<T>>> {
-    let port = get_port(tcp, udp, protocol, local_port)?.read();
-    let snap = port.get()?;
+    let guard = collector.pin();
+    let snap = get_port(tcp, udp, protocol, local_port)?.read(&guard)?;
     let now = wdk::utils::get_system_timestamp_ms();
     let mut ended = Vec::new();
     for conn in snap.iter() {
@@ -171,48 +206,80 @@ fn end_all_on_port<T: Connection>(
     Some(ended)
 }
 
-// Sets the verdict on the connection matching `key`, returning any redirect
-// info. Read-only guard.
+// Sets the verdict on the connection matching `key` until `expiry_ms` (0 for good), returning any
+// redirect info. Read-only guard.
 fn set_connection_verdict<T: Connection>(
-    tcp: &PortArray<T>,
-    udp: &PortArray<T>,
+    tcp: &PortTable<T>,
+    udp: &PortTable<T>,
+    collector: &Collector,
     key: &Key,
     verdict: Verdict,
+    expiry_ms: u64,
 ) -> Option<RedirectInfo> {
-    let port = get_port(tcp, udp, key.protocol, key.local_port)?.read();
-    let snap = port.get()?;
-    for conn in snap.iter() {
-        if conn.equals(key) {
-            conn.set_verdict(verdict);
-            return conn.redirect_info();
-        }
-    }
-    None
+    let guard = collector.pin();
+    let snap = get_port(tcp, udp, key.protocol, key.local_port)?.read(&guard)?;
+    let conn = port_bucket::find_exact(snap, key)?;
+    conn.set_verdict_until(verdict, expiry_ms);
+    conn.redirect_info()
+}
+
+// Same as `set_connection_verdict`, for the connection with the given id. The key only selects the
+// port: an ended connection with the same key can still be in there. Read-only guard.
+fn set_connection_verdict_by_id<T: Connection>(
+    tcp: &PortTable<T>,
+    udp: &PortTable<T>,
+    collector: &Collector,
+    key: &Key,
+    id: u64,
+    verdict: Verdict,
+    expiry_ms: u64,
+) -> Option<RedirectInfo> {
+    let guard = collector.pin();
+    let snap = get_port(tcp, udp, key.protocol, key.local_port)?.read(&guard)?;
+    let conn = port_bucket::find_by_id(snap, key, id)?;
+    conn.set_verdict_until(verdict, expiry_ms);
+    conn.redirect_info()
+}
+
+// Returns the connection matching `key`, without refreshing its last-accessed time. Read-only
+// guard.
+fn find_connection<T: Connection>(
+    tcp: &PortTable<T>,
+    udp: &PortTable<T>,
+    collector: &Collector,
+    key: &Key,
+) -> Option<Arc<T>> {
+    let guard = collector.pin();
+    let snap = get_port(tcp, udp, key.protocol, key.local_port)?.read(&guard)?;
+    port_bucket::find_exact(snap, key).cloned()
 }
 
 // Returns the verdict of the connection matching `key`, including redirect
 // matches. Refreshes the last-accessed time. Read-only guard.
 fn find_verdict<T: Connection>(
-    tcp: &PortArray<T>,
-    udp: &PortArray<T>,
+    tcp: &PortTable<T>,
+    udp: &PortTable<T>,
+    collector: &Collector,
     key: &Key,
 ) -> Option<Verdict> {
-    let port = get_port(tcp, udp, key.protocol, key.local_port)?.read();
-    let snap = port.get()?;
-    for conn in snap.iter() {
-        if conn.equals(key) || conn.redirect_equals(key) {
-            conn.set_last_accessed_time(wdk::utils::get_system_timestamp_ms());
-            return Some(conn.get_verdict());
-        }
-    }
-    None
+    let guard = collector.pin();
+    let snap = get_port(tcp, udp, key.protocol, key.local_port)?.read(&guard)?;
+    let conn = port_bucket::find(snap, key)?;
+    conn.set_last_accessed_time(wdk::utils::get_system_timestamp_ms());
+    Some(conn.get_verdict())
 }
 
 // ports_walk generic function for waling over all connections.
-fn ports_walk<T: Connection, F: FnMut(&T)>(tcp: &PortArray<T>, udp: &PortArray<T>, mut iter: F) {
+fn ports_walk<T: Connection, F: FnMut(&T)>(
+    tcp: &PortTable<T>,
+    udp: &PortTable<T>,
+    collector: &Collector,
+    mut iter: F,
+) {
+    // One pin for the whole walk: it only holds back the arrays replaced while it runs.
+    let guard = collector.pin();
     for port in tcp.iter().chain(udp.iter()) {
-        let guard = port.read();
-        if let Some(snap) = guard.get() {
+        if let Some(snap) = port.read(&guard) {
             for conn in snap.iter() {
                 iter(conn.as_ref());
             }
@@ -221,20 +288,15 @@ fn ports_walk<T: Connection, F: FnMut(&T)>(tcp: &PortArray<T>, udp: &PortArray<T
 }
 
 fn ports_clean_ended<T: Connection>(
-    tcp: &PortArray<T>,
-    udp: &PortArray<T>,
+    tcp: &PortTable<T>,
+    udp: &PortTable<T>,
     removed_connections: &mut Vec<Arc<T>>,
     queue: &MpscQueue<ConnectionArray<T>>,
+    collector: &Collector,
+    timeouts: &CacheTimeouts,
+    now: u64,
+    usage: &CacheUsage,
 ) {
-    // Durations
-    const TWO_MINUTES: u64 = Duration::from_secs(120).as_millis() as u64;
-    const ONE_MINUTE: u64 = Duration::from_secs(60).as_millis() as u64;
-    const SECOND: u64 = Duration::from_secs(1).as_millis() as u64;
-
-    let now = wdk::utils::get_system_timestamp_ms();
-    let before_two_minutes = now - TWO_MINUTES;
-    let before_one_minute = now - ONE_MINUTE;
-
     // Remove all ended or stale connections.
     for port in tcp.iter().chain(udp.iter()) {
         let mut any_removed = false;
@@ -249,16 +311,20 @@ fn ports_clean_ended<T: Connection>(
 
         // Check for ended connections and build a new list.
         for conn in snap.iter() {
-            if conn.has_ended() && conn.get_end_time() < before_one_minute {
-                any_removed = true;
-                continue;
-            }
-            if removed_connections.capacity() > removed_connections.len()
-                && conn.get_last_accessed_time() < before_two_minutes
-            {
-                removed_connections.push(conn.clone());
-                any_removed = true;
-                continue;
+            match timeouts.expiry(conn.as_ref(), now) {
+                Expiry::Remove => {
+                    usage.removed(conn.as_ref());
+                    any_removed = true;
+                    continue;
+                }
+                // Idle connections are reported as ended (with their final counters).
+                Expiry::Idle if removed_connections.capacity() > removed_connections.len() => {
+                    usage.removed(conn.as_ref());
+                    removed_connections.push(conn.clone());
+                    any_removed = true;
+                    continue;
+                }
+                _ => {}
             }
             survivors.push(conn.clone());
         }
@@ -272,59 +338,112 @@ fn ports_clean_ended<T: Connection>(
                     Some(survivors.into_boxed_slice())
                 },
                 queue,
+                collector,
             );
         }
     }
 
-    // Clean unused and unlinked connection arrays.
-    let now = wdk::utils::get_system_timestamp_ms();
-    loop {
-        let mut continue_loop = false;
-        match queue.peek() {
-            Some(conn_array) => 'conn_match: {
-                // Check for active readers
-                let readers = conn_array.readers.load(Ordering::SeqCst);
-                if readers != 0 {
-                    break 'conn_match;
-                }
+    // Free the unlinked connection arrays no reader can see anymore. Publishing does it too, this
+    // catches the arrays that were still visible then.
+    collector.reclaim(queue, free_array);
+}
 
-                let time = conn_array.unlinked_timestamp.load(Ordering::SeqCst);
-                // Prevent overflow
-                if time > now {
-                    break 'conn_match;
-                }
+// Same as `add_connection`, for a protocol without ports.
+fn add_flow<T: Connection>(flows: &Mutex<FlowTable<T>>, usage: &CacheUsage, new: T) -> (u64, bool) {
+    let (flow, inserted) = flows.write_lock().insert(Arc::new(new));
+    if inserted {
+        usage.added(flow.as_ref());
+    }
+    (flow.get_id(), inserted)
+}
 
-                // Check if enough time has passed since the un-linking.
-                if now - time < SECOND {
-                    break 'conn_match;
-                }
+// Same as `get_connection`, for a protocol without ports.
+fn get_flow<T: Connection>(flows: &Mutex<FlowTable<T>>, key: &Key) -> Option<Arc<T>> {
+    let flows = flows.read_lock();
+    let flow = flows.get(key)?;
+    flow.set_last_accessed_time(wdk::utils::get_system_timestamp_ms());
+    Some(flow.clone())
+}
 
-                // Array is safe to free.
-                let array = queue.pop();
-                unsafe {
-                    drop(Box::from_raw(array));
-                }
+// Same as `set_connection_verdict_by_id`, for a protocol without ports. Without an id only the key
+// is checked.
+fn set_flow_verdict<T: Connection>(
+    flows: &Mutex<FlowTable<T>>,
+    key: &Key,
+    id: Option<u64>,
+    verdict: Verdict,
+    expiry_ms: u64,
+) -> Option<RedirectInfo> {
+    let flows = flows.read_lock();
+    let flow = flows.get(key).filter(|flow| match id {
+        Some(id) => flow.get_id() == id,
+        None => true,
+    })?;
+    flow.set_verdict_until(verdict, expiry_ms);
+    flow.redirect_info()
+}
 
-                // Continue to next.
-                continue_loop = true;
+// Same as `ports_clean_ended`. Flows never end on their own, they are only reported once idle.
+fn flows_clean_ended<T: Connection>(
+    flows: &Mutex<FlowTable<T>>,
+    removed_connections: &mut Vec<Arc<T>>,
+    timeouts: &CacheTimeouts,
+    now: u64,
+    usage: &CacheUsage,
+) {
+    flows
+        .write_lock()
+        .retain(|flow| match timeouts.expiry(flow.as_ref(), now) {
+            Expiry::Remove => {
+                usage.removed(flow.as_ref());
+                false
             }
-            None => (),
-        }
+            Expiry::Idle if removed_connections.capacity() > removed_connections.len() => {
+                usage.removed(flow.as_ref());
+                removed_connections.push(flow.clone());
+                false
+            }
+            _ => true,
+        });
+}
 
-        if !continue_loop {
-            // No more arrays to free.
-            break;
-        }
+// Same as `ports_clear`.
+fn flows_clear<T: Connection>(flows: &Mutex<FlowTable<T>>, usage: &CacheUsage) -> Vec<Arc<T>> {
+    let mut all = flows.write_lock().take_all();
+    for flow in all.iter() {
+        usage.removed(flow.as_ref());
+    }
+    all.retain(|flow| !flow.has_ended());
+    all
+}
+
+// Sets the verdict of the connection if it is active and matches the filter. Counts it in
+// `result`, and flags it there if the ALE layer has to classify it again.
+fn update_matching<T: Connection>(
+    conn: &T,
+    filter: &ConnectionFilter,
+    verdict: Verdict,
+    result: &mut (usize, bool),
+) {
+    if conn.has_ended() || !filter.matches(conn) {
+        return;
     }
+    result.1 |= connection_filter::needs_reauthorization(conn.get_verdict(), verdict);
+    conn.set_verdict(verdict);
+    result.0 += 1;
 }
 
 // ConnectionCache holds the state of all active connections.
 pub struct ConnectionCache {
     // Connection states
-    tcp_v4: Box<PortArray<ConnectionV4>>,
-    udp_v4: Box<PortArray<ConnectionV4>>,
-    tcp_v6: Box<PortArray<ConnectionV6>>,
-    udp_v6: Box<PortArray<ConnectionV6>>,
+    tcp_v4: Box<PortTable<ConnectionV4>>,
+    udp_v4: Box<PortTable<ConnectionV4>>,
+    tcp_v6: Box<PortTable<ConnectionV6>>,
+    udp_v6: Box<PortTable<ConnectionV6>>,
+
+    // Flows of the protocols without ports (see flow_table.rs).
+    flows_v4: Mutex<FlowTable<ConnectionV4>>,
+    flows_v6: Mutex<FlowTable<ConnectionV6>>,
 
     // Holds ended connection that need to be send as an event to user space.
     tmp_ended_connections_buffer_v4: Vec<Arc<ConnectionV4>>,
@@ -333,75 +452,488 @@ pub struct ConnectionCache {
     // Holds unlinked connections arrays.
     unlinked_ports_v4: MpscQueue<ConnectionArray<ConnectionV4>>,
     unlinked_ports_v6: MpscQueue<ConnectionArray<ConnectionV6>>,
+    // Readers pin it while they look at a port, the unlinked arrays are freed once they can not.
+    collector: Collector,
+
+    // Active connections of every process, so they can be ended when the process exits.
+    process_index: Mutex<ProcessIndex>,
+
+    // Key of every active connection by its id, for the commands that refer to a connection by id.
+    id_index: Mutex<BTreeMap<u64, Key>>,
+    // Next connection id. 0 is never given out.
+    next_id: AtomicU64,
+
+    // How long ended and idle connections are kept, per protocol.
+    timeouts: CacheTimeouts,
+
+    // Entry count and memory, and the count over which entries are evicted (0 for no limit).
+    usage: CacheUsage,
+    max_entries: AtomicUsize,
+    // Set while an eviction runs, the inserts that race with it do not start another one.
+    evicting: AtomicBool,
 }
 
 impl ConnectionCache {
     pub fn new() -> Self {
         // Initialize all the arrays.
         Self {
-            tcp_v4: alloc_port_array(),
-            udp_v4: alloc_port_array(),
-            tcp_v6: alloc_port_array(),
-            udp_v6: alloc_port_array(),
+            tcp_v4: PortTable::new(),
+            udp_v4: PortTable::new(),
+            tcp_v6: PortTable::new(),
+            udp_v6: PortTable::new(),
+            flows_v4: Mutex::new(FlowTable::new()),
+            flows_v6: Mutex::new(FlowTable::new()),
             tmp_ended_connections_buffer_v4: Vec::with_capacity(100),
             tmp_ended_connections_buffer_v6: Vec::with_capacity(100),
             unlinked_ports_v4: MpscQueue::new(),
             unlinked_ports_v6: MpscQueue::new(),
+            collector: Collector::new(epoch::READER_SLOTS),
+            process_index: Mutex::new(ProcessIndex::new()),
+            id_index: Mutex::new(BTreeMap::new()),
+            next_id: AtomicU64::new(1),
+            timeouts: CacheTimeouts::new(),
+            usage: CacheUsage::new(),
+            max_entries: AtomicUsize::new(cache_limit::DEFAULT_MAX_ENTRIES),
+            evicting: AtomicBool::new(false),
+        }
+    }
+
+    // Adds the connection with a new id. Returns the id of the connection that is in the cache
+    // for its key: the new one, or the one that was already there. 0 if it can not be cached.
+    pub fn add_v4(&self, mut new: ConnectionV4) -> u64 {
+        new.id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (process_id, key) = (new.get_process_id(), new.get_key());
+        if !flow_table::has_ports(key.protocol) {
+            let (id, inserted) = add_flow(&self.flows_v4, &self.usage, new);
+            if inserted {
+                self.index(process_id, id, key);
+            }
+            return id;
+        }
+        let (id, inserted) = add_connection(
+            &self.tcp_v4,
+            &self.udp_v4,
+            &self.unlinked_ports_v4,
+            &self.collector,
+            &self.usage,
+            new,
+        );
+        if inserted {
+            self.index(process_id, id, key);
+        }
+        id
+    }
+
+    // Same as `add_v4`.
+    pub fn add_v6(&self, mut new: ConnectionV6) -> u64 {
+        new.id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (process_id, key) = (new.get_process_id(), new.get_key());
+        if !flow_table::has_ports(key.protocol) {
+            let (id, inserted) = add_flow(&self.flows_v6, &self.usage, new);
+            if inserted {
+                self.index(process_id, id, key);
+            }
+            return id;
         }
+        let (id, inserted) = add_connection(
+            &self.tcp_v6,
+            &self.udp_v6,
+            &self.unlinked_ports_v6,
+            &self.collector,
+            &self.usage,
+            new,
+        );
+        if inserted {
+            self.index(process_id, id, key);
+        }
+        id
     }
 
-    pub fn add_v4(&self, new: ConnectionV4) {
-        add_connection(&self.tcp_v4, &self.udp_v4, &self.unlinked_ports_v4, new);
+    // Adds the connection with its verdict, or sets that verdict on the one already in the cache
+    // for its key. Returns any redirect info, like `update_connection`.
+    pub fn upsert_v4(&self, new: ConnectionV4) -> Option<RedirectInfo> {
+        let verdict = new.get_verdict();
+        let id = self.add_v4(new);
+        self.update_connection_by_id(id, verdict)?.1
     }
 
-    pub fn add_v6(&self, new: ConnectionV6) {
-        add_connection(&self.tcp_v6, &self.udp_v6, &self.unlinked_ports_v6, new);
+    // Same as `upsert_v4`.
+    pub fn upsert_v6(&self, new: ConnectionV6) -> Option<RedirectInfo> {
+        let verdict = new.get_verdict();
+        let id = self.add_v6(new);
+        self.update_connection_by_id(id, verdict)?.1
+    }
+
+    fn index(&self, process_id: u64, id: u64, key: Key) {
+        self.process_index.write_lock().add(process_id, key);
+        self.id_index.write_lock().insert(id, key);
     }
 
     pub fn end_v4(&self, key: Key) -> Option<Arc<ConnectionV4>> {
-        end_connection(&self.tcp_v4, &self.udp_v4, &key)
+        let conn = end_connection(&self.tcp_v4, &self.udp_v4, &self.collector, &key)?;
+        self.unindex(core::iter::once(conn.as_ref()));
+        Some(conn)
     }
 
     pub fn end_v6(&self, key: Key) -> Option<Arc<ConnectionV6>> {
-        end_connection(&self.tcp_v6, &self.udp_v6, &key)
+        let conn = end_connection(&self.tcp_v6, &self.udp_v6, &self.collector, &key)?;
+        self.unindex(core::iter::once(conn.as_ref()));
+        Some(conn)
     }
 
     pub fn end_all_on_port_v4(&self, key: (IpProtocol, u16)) -> Option<Vec<Arc<ConnectionV4>>> {
-        end_all_on_port(&self.tcp_v4, &self.udp_v4, key.0, key.1)
+        let conns = end_all_on_port(&self.tcp_v4, &self.udp_v4, &self.collector, key.0, key.1)?;
+        self.unindex(conns.iter().map(|conn| conn.as_ref()));
+        Some(conns)
     }
 
     pub fn end_all_on_port_v6(&self, key: (IpProtocol, u16)) -> Option<Vec<Arc<ConnectionV6>>> {
-        end_all_on_port(&self.tcp_v6, &self.udp_v6, key.0, key.1)
+        let conns = end_all_on_port(&self.tcp_v6, &self.udp_v6, &self.collector, key.0, key.1)?;
+        self.unindex(conns.iter().map(|conn| conn.as_ref()));
+        Some(conns)
+    }
+
+    // Ends every connection of a process that exited and returns them. Other process events are
+    // ignored.
+    pub fn handle_process_event(
+        &self,
+        event: ProcessEvent,
+    ) -> (Vec<Arc<ConnectionV4>>, Vec<Arc<ConnectionV6>>) {
+        let mut ended_v6 = Vec::new();
+        let ended_v4 = self
+            .process_index
+            .write_lock()
+            .handle_event(event, |key: &Key| {
+                if key.is_ipv6() {
+                    if let Some(conn) =
+                        end_connection(&self.tcp_v6, &self.udp_v6, &self.collector, key)
+                    {
+                        ended_v6.push(conn);
+                    }
+                    return None;
+                }
+                end_connection(&self.tcp_v4, &self.udp_v4, &self.collector, key)
+            });
+        // The process index has dropped them already.
+        let mut id_index = self.id_index.write_lock();
+        for conn in ended_v4.iter() {
+            id_index.remove(&conn.get_id());
+        }
+        for conn in ended_v6.iter() {
+            id_index.remove(&conn.get_id());
+        }
+        (ended_v4, ended_v6)
+    }
+
+    // Removes connections that ended, or are about to be dropped, from the indexes.
+    fn unindex<'a, T: Connection + 'a>(&self, conns: impl Iterator<Item = &'a T>) {
+        let mut process_index = self.process_index.write_lock();
+        let mut id_index = self.id_index.write_lock();
+        for conn in conns {
+            process_index.remove(conn.get_process_id(), &conn.get_key());
+            id_index.remove(&conn.get_id());
+        }
     }
 
     pub fn update_connection(&self, key: Key, verdict: Verdict) -> Option<RedirectInfo> {
+        self.update_connection_until(key, verdict, 0)
+    }
+
+    // Same as `update_connection`, for a verdict that reverts to Undecided at `expiry_ms`.
+    pub fn update_connection_until(
+        &self,
+        key: Key,
+        verdict: Verdict,
+        expiry_ms: u64,
+    ) -> Option<RedirectInfo> {
+        if !flow_table::has_ports(key.protocol) {
+            return if key.is_ipv6() {
+                set_flow_verdict(&self.flows_v6, &key, None, verdict, expiry_ms)
+            } else {
+                set_flow_verdict(&self.flows_v4, &key, None, verdict, expiry_ms)
+            };
+        }
         if key.is_ipv6() {
-            set_connection_verdict(&self.tcp_v6, &self.udp_v6, &key, verdict)
+            set_connection_verdict(
+                &self.tcp_v6,
+                &self.udp_v6,
+                &self.collector,
+                &key,
+                verdict,
+                expiry_ms,
+            )
         } else {
-            set_connection_verdict(&self.tcp_v4, &self.udp_v4, &key, verdict)
+            set_connection_verdict(
+                &self.tcp_v4,
+                &self.udp_v4,
+                &self.collector,
+                &key,
+                verdict,
+                expiry_ms,
+            )
+        }
+    }
+
+    // Returns the key of the active connection with the given id.
+    pub fn get_key_by_id(&self, id: u64) -> Option<Key> {
+        self.id_index.read_lock().get(&id).copied()
+    }
+
+    // Sets the verdict of the active connection with the given id. Returns its key and any redirect
+    // info, None if there is no such connection.
+    pub fn update_connection_by_id(
+        &self,
+        id: u64,
+        verdict: Verdict,
+    ) -> Option<(Key, Option<RedirectInfo>)> {
+        self.update_connection_by_id_until(id, verdict, 0)
+    }
+
+    // Same as `update_connection_by_id`, for a verdict that reverts to Undecided at `expiry_ms`.
+    pub fn update_connection_by_id_until(
+        &self,
+        id: u64,
+        verdict: Verdict,
+        expiry_ms: u64,
+    ) -> Option<(Key, Option<RedirectInfo>)> {
+        let key = *self.id_index.read_lock().get(&id)?;
+        let redirect_info = if !flow_table::has_ports(key.protocol) {
+            if key.is_ipv6() {
+                set_flow_verdict(&self.flows_v6, &key, Some(id), verdict, expiry_ms)
+            } else {
+                set_flow_verdict(&self.flows_v4, &key, Some(id), verdict, expiry_ms)
+            }
+        } else if key.is_ipv6() {
+            set_connection_verdict_by_id(
+                &self.tcp_v6,
+                &self.udp_v6,
+                &self.collector,
+                &key,
+                id,
+                verdict,
+                expiry_ms,
+            )
+        } else {
+            set_connection_verdict_by_id(
+                &self.tcp_v4,
+                &self.udp_v4,
+                &self.collector,
+                &key,
+                id,
+                verdict,
+                expiry_ms,
+            )
+        };
+        Some((key, redirect_info))
+    }
+
+    // Returns the active connections of the process, from the process index.
+    pub fn get_process_connections(
+        &self,
+        process_id: u64,
+    ) -> (Vec<Arc<ConnectionV4>>, Vec<Arc<ConnectionV6>>) {
+        let keys = self.process_index.read_lock().keys(process_id);
+        let mut conns_v4 = Vec::new();
+        let mut conns_v6 = Vec::new();
+        for key in keys.iter() {
+            if key.is_ipv6() {
+                conns_v6.extend(find_connection(
+                    &self.tcp_v6,
+                    &self.udp_v6,
+                    &self.collector,
+                    key,
+                ));
+            } else {
+                conns_v4.extend(find_connection(
+                    &self.tcp_v4,
+                    &self.udp_v4,
+                    &self.collector,
+                    key,
+                ));
+            }
+        }
+        (conns_v4, conns_v6)
+    }
+
+    // Sets the verdict of every active connection of the process. Returns how many there were, and
+    // whether the ALE layer has to classify any of them again, like `update_matching_v4`.
+    pub fn update_process_connections(&self, process_id: u64, verdict: Verdict) -> (usize, bool) {
+        let filter = ConnectionFilter::for_process(process_id);
+        let (conns_v4, conns_v6) = self.get_process_connections(process_id);
+        let mut result = (0, false);
+        for conn in conns_v4.iter() {
+            update_matching(conn.as_ref(), &filter, verdict, &mut result);
+        }
+        for conn in conns_v6.iter() {
+            update_matching(conn.as_ref(), &filter, verdict, &mut result);
+        }
+        result
+    }
+
+    // Sets the verdict of every active IPv4 connection the filter matches. Returns the number of
+    // matches, and whether the ALE layer has to classify any of them again (see
+    // `connection_filter::needs_reauthorization`).
+    pub fn update_matching_v4(&self, filter: &ConnectionFilter, verdict: Verdict) -> (usize, bool) {
+        let mut result = (0, false);
+        match filter.process_id {
+            // The process index saves the walk over every port.
+            Some(process_id) => {
+                for conn in self.get_process_connections(process_id).0.iter() {
+                    update_matching(conn.as_ref(), filter, verdict, &mut result);
+                }
+            }
+            None => self.walk_over_connections_v4(|conn: &ConnectionV4| {
+                update_matching(conn, filter, verdict, &mut result)
+            }),
+        }
+        result
+    }
+
+    // Same as `update_matching_v4`.
+    pub fn update_matching_v6(&self, filter: &ConnectionFilter, verdict: Verdict) -> (usize, bool) {
+        let mut result = (0, false);
+        match filter.process_id {
+            Some(process_id) => {
+                for conn in self.get_process_connections(process_id).1.iter() {
+                    update_matching(conn.as_ref(), filter, verdict, &mut result);
+                }
+            }
+            None => self.walk_over_connections_v6(|conn: &ConnectionV6| {
+                update_matching(conn, filter, verdict, &mut result)
+            }),
         }
+        result
+    }
+
+    pub fn set_timeouts(&mut self, command: &protocol::command::SetCacheTimeouts) {
+        self.timeouts.update(command);
+    }
+
+    // Sets the entry count over which connections are evicted. 0 removes the limit.
+    pub fn set_max_entries(&self, max_entries: usize) {
+        self.max_entries.store(max_entries, Ordering::Relaxed);
+    }
+
+    // Evicts a batch of connections if the cache is over its limit: ended ones first, then the
+    // least recently accessed. Returns the evicted connections that had not ended, so they can be
+    // reported. None if nothing was evicted, or if another eviction is running.
+    pub fn evict_if_full(&self) -> Option<(Vec<Arc<ConnectionV4>>, Vec<Arc<ConnectionV6>>)> {
+        let count = cache_limit::eviction_count(
+            self.usage.entries(),
+            self.max_entries.load(Ordering::Relaxed),
+        );
+        if count == 0 || self.evicting.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+
+        let mut selector = VictimSelector::new(count);
+        self.walk_over_connections_v4(|conn: &ConnectionV4| selector.offer(conn));
+        self.walk_over_connections_v6(|conn: &ConnectionV6| selector.offer(conn));
+
+        let mut active_v4 = Vec::new();
+        let mut active_v6 = Vec::new();
+        for key in selector.into_keys() {
+            if key.is_ipv6() {
+                let removed = if flow_table::has_ports(key.protocol) {
+                    remove_connection(
+                        &self.tcp_v6,
+                        &self.udp_v6,
+                        &self.unlinked_ports_v6,
+                        &self.collector,
+                        &key,
+                    )
+                } else {
+                    self.flows_v6.write_lock().remove(&key)
+                };
+                if let Some(conn) = removed {
+                    self.usage.evicted(conn.as_ref());
+                    if !conn.has_ended() {
+                        active_v6.push(conn);
+                    }
+                }
+            } else {
+                let removed = if flow_table::has_ports(key.protocol) {
+                    remove_connection(
+                        &self.tcp_v4,
+                        &self.udp_v4,
+                        &self.unlinked_ports_v4,
+                        &self.collector,
+                        &key,
+                    )
+                } else {
+                    self.flows_v4.write_lock().remove(&key)
+                };
+                if let Some(conn) = removed {
+                    self.usage.evicted(conn.as_ref());
+                    if !conn.has_ended() {
+                        active_v4.push(conn);
+                    }
+                }
+            }
+        }
+        // Ended connections were dropped from the indexes when they ended.
+        self.unindex(active_v4.iter().map(|conn| conn.as_ref()));
+        self.unindex(active_v6.iter().map(|conn| conn.as_ref()));
+
+        self.evicting.store(false, Ordering::SeqCst);
+        Some((active_v4, active_v6))
     }
 
     // clean_ended_connections is not thread safe and should be called from one place only.
     pub fn clean_ended_connections<'a>(
         &'a mut self,
+        clock: &impl Clock,
     ) -> (
         &'a mut Vec<Arc<ConnectionV4>>,
         &'a mut Vec<Arc<ConnectionV6>>,
     ) {
         self.tmp_ended_connections_buffer_v4.clear();
         self.tmp_ended_connections_buffer_v6.clear();
+        let now = clock.now_ms();
         ports_clean_ended(
             &self.tcp_v4,
             &self.udp_v4,
             &mut self.tmp_ended_connections_buffer_v4,
             &self.unlinked_ports_v4,
+            &self.collector,
+            &self.timeouts,
+            now,
+            &self.usage,
         );
         ports_clean_ended(
             &self.tcp_v6,
             &self.udp_v6,
             &mut self.tmp_ended_connections_buffer_v6,
             &self.unlinked_ports_v6,
+            &self.collector,
+            &self.timeouts,
+            now,
+            &self.usage,
+        );
+        flows_clean_ended(
+            &self.flows_v4,
+            &mut self.tmp_ended_connections_buffer_v4,
+            &self.timeouts,
+            now,
+            &self.usage,
+        );
+        flows_clean_ended(
+            &self.flows_v6,
+            &mut self.tmp_ended_connections_buffer_v6,
+            &self.timeouts,
+            now,
+            &self.usage,
+        );
+        // The stale connections are removed without being ended.
+        self.unindex(
+            self.tmp_ended_connections_buffer_v4
+                .iter()
+                .map(|conn| conn.as_ref()),
+        );
+        self.unindex(
+            self.tmp_ended_connections_buffer_v6
+                .iter()
+                .map(|conn| conn.as_ref()),
         );
         return (
             &mut self.tmp_ended_connections_buffer_v4,
@@ -410,20 +942,36 @@ impl ConnectionCache {
     }
 
     pub fn get_verdict(&self, key: &Key) -> Option<Verdict> {
+        if !flow_table::has_ports(key.protocol) {
+            let flow = if key.is_ipv6() {
+                get_flow(&self.flows_v6, key).map(|flow| flow.get_verdict())
+            } else {
+                get_flow(&self.flows_v4, key).map(|flow| flow.get_verdict())
+            };
+            return flow;
+        }
         if key.is_ipv6() {
-            find_verdict(&self.tcp_v6, &self.udp_v6, key)
+            find_verdict(&self.tcp_v6, &self.udp_v6, &self.collector, key)
         } else {
-            find_verdict(&self.tcp_v4, &self.udp_v4, key)
+            find_verdict(&self.tcp_v4, &self.udp_v4, &self.collector, key)
         }
     }
-    // walk_over_connections_v4 walks over all IPv4 connections. Lock free.
-    pub fn walk_over_connections_v4<F: FnMut(&ConnectionV4)>(&self, iter: F) {
-        ports_walk(&self.tcp_v4, &self.udp_v4, iter);
+    // walk_over_connections_v4 walks over all IPv4 connections. Lock free, except for the flows of
+    // the protocols without ports.
+    pub fn walk_over_connections_v4<F: FnMut(&ConnectionV4)>(&self, mut iter: F) {
+        ports_walk(&self.tcp_v4, &self.udp_v4, &self.collector, &mut iter);
+        for flow in self.flows_v4.read_lock().iter() {
+            iter(flow.as_ref());
+        }
     }
 
-    // walk_over_connections_v6 walks over all IPv6 connections. Lock free.
-    pub fn walk_over_connections_v6<F: FnMut(&ConnectionV6)>(&self, iter: F) {
-        ports_walk(&self.tcp_v6, &self.udp_v6, iter);
+    // walk_over_connections_v6 walks over all IPv6 connections. Lock free, except for the flows of
+    // the protocols without ports.
+    pub fn walk_over_connections_v6<F: FnMut(&ConnectionV6)>(&self, mut iter: F) {
+        ports_walk(&self.tcp_v6, &self.udp_v6, &self.collector, &mut iter);
+        for flow in self.flows_v6.read_lock().iter() {
+            iter(flow.as_ref());
+        }
     }
 
     // get_unlinked_queue_counts returns stats of all the unlinked connection arrays. Lock free.
@@ -434,18 +982,37 @@ impl ConnectionCache {
         )
     }
 
-    // get_entries_count returns stats for all the connections count. Lock free.
+    // get_usage returns the entry count, the memory they hold in bytes, the limit and the number
+    // of evicted entries. Lock free.
+    pub fn get_usage(&self) -> (usize, usize, usize, u64) {
+        (
+            self.usage.entries(),
+            self.usage.bytes(),
+            self.max_entries.load(Ordering::Relaxed),
+            self.usage.evicted_count(),
+        )
+    }
+
+    // get_port_tables_bytes returns the memory held by the port tables. Lock free.
+    pub fn get_port_tables_bytes(&self) -> usize {
+        self.tcp_v4.allocated_bytes()
+            + self.udp_v4.allocated_bytes()
+            + self.tcp_v6.allocated_bytes()
+            + self.udp_v6.allocated_bytes()
+    }
+
+    // get_entries_count returns stats for all the connections count.
     pub fn get_entries_count(&self) -> (usize, usize) {
         let mut active = 0usize;
         let mut ended = 0usize;
-        ports_walk(&self.tcp_v4, &self.udp_v4, |conn: &ConnectionV4| {
+        self.walk_over_connections_v4(|conn: &ConnectionV4| {
             if conn.has_ended() {
                 ended += 1;
             } else {
                 active += 1;
             }
         });
-        ports_walk(&self.tcp_v6, &self.udp_v6, |conn: &ConnectionV6| {
+        self.walk_over_connections_v6(|conn: &ConnectionV6| {
             if conn.has_ended() {
                 ended += 1;
             } else {
@@ -457,43 +1024,55 @@ impl ConnectionCache {
 
     // get_connection_v4 returns a connection by key. Lock free.
     pub fn get_connection_v4(&self, key: &Key) -> Option<Arc<ConnectionV4>> {
-        get_connection(&self.tcp_v4, &self.udp_v4, key)
+        if !flow_table::has_ports(key.protocol) {
+            return get_flow(&self.flows_v4, key);
+        }
+        get_connection(&self.tcp_v4, &self.udp_v4, &self.collector, key)
     }
 
     // get_connection_v6 returns a connection by key. Lock free.
     pub fn get_connection_v6(&self, key: &Key) -> Option<Arc<ConnectionV6>> {
-        get_connection(&self.tcp_v6, &self.udp_v6, key)
+        if !flow_table::has_ports(key.protocol) {
+            return get_flow(&self.flows_v6, key);
+        }
+        get_connection(&self.tcp_v6, &self.udp_v6, &self.collector, key)
     }
 
-    // Clears the connection cache.
-    pub fn clear(&self) {
-        ports_clear(&self.tcp_v4, &self.udp_v4, &self.unlinked_ports_v4);
-        ports_clear(&self.tcp_v6, &self.udp_v6, &self.unlinked_ports_v6);
+    // Clears the connection cache. Returns the connections that had not ended yet, so they can be
+    // reported.
+    pub fn clear(&self) -> (Vec<Arc<ConnectionV4>>, Vec<Arc<ConnectionV6>>) {
+        let mut active_v4 = ports_clear(
+            &self.tcp_v4,
+            &self.udp_v4,
+            &self.unlinked_ports_v4,
+            &self.collector,
+            &self.usage,
+        );
+        active_v4.extend(flows_clear(&self.flows_v4, &self.usage));
+        let mut active_v6 = ports_clear(
+            &self.tcp_v6,
+            &self.udp_v6,
+            &self.unlinked_ports_v6,
+            &self.collector,
+            &self.usage,
+        );
+        active_v6.extend(flows_clear(&self.flows_v6, &self.usage));
+        self.process_index.write_lock().clear();
+        self.id_index.write_lock().clear();
+        (active_v4, active_v6)
     }
 }
 
 impl Drop for ConnectionCache {
     fn drop(&mut self) {
         // Clear the cache
-        self.clear();
-        // Free all unlinked connection arrays
-        loop {
-            let array = self.unlinked_ports_v4.pop();
-            if array.is_null() {
-                break;
-            }
-            unsafe {
-                drop(Box::from_raw(array));
-            }
+        _ = self.clear();
+        // Free all unlinked connection arrays, there are no readers left.
+        while !self.unlinked_ports_v4.is_empty() {
+            free_array(self.unlinked_ports_v4.pop());
         }
-        loop {
-            let array = self.unlinked_ports_v6.pop();
-            if array.is_null() {
-                break;
-            }
-            unsafe {
-                drop(Box::from_raw(array));
-            }
+        while !self.unlinked_ports_v6.is_empty() {
+            free_array(self.unlinked_ports_v6.pop());
         }
     }
 }
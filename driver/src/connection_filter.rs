@@ -0,0 +1,218 @@
+//! Matching of cache entries for the bulk verdict updates (`BulkUpdateV4/V6` commands).
+//!
+//! Revoking access to a host used to take one `UpdateV4/V6` per 5-tuple. A filter sets the
+//! verdict of every connection that matches all of its fields instead; a field that is not given
+//! matches anything.
+//!
+//! A changed verdict only reaches the ALE layer when the connection is classified again, which
+//! takes a filter reset. `needs_reauthorization` tells whether a change is one the ALE layer would
+//! decide differently, so a bulk update resets the filters once, and only when it has to.
+
+use alloc::string::String;
+use core::ops::RangeInclusive;
+use protocol::command::{BulkUpdateV4, BulkUpdateV6};
+use smoltcp::wire::{IpCidr, IpProtocol, Ipv4Address, Ipv4Cidr, Ipv6Address, Ipv6Cidr};
+
+use crate::connection::{Connection, Verdict};
+
+pub struct ConnectionFilter {
+    pub protocol: Option<IpProtocol>,
+    pub local_port: Option<u16>,
+    pub remote: Option<IpCidr>,
+    pub remote_ports: Option<RangeInclusive<u16>>,
+    pub process_id: Option<u64>,
+}
+
+impl ConnectionFilter {
+    pub fn from_bulk_update_v4(update: &BulkUpdateV4) -> Result<Self, String> {
+        let prefix_len = update.remote_prefix_len;
+        if prefix_len > 32 {
+            return Err(alloc::format!("invalid ipv4 prefix length {}", prefix_len));
+        }
+        let remote = Ipv4Cidr::new(Ipv4Address::from_octets(update.remote_address), prefix_len);
+        return Ok(Self::new(
+            update.protocol,
+            update.local_port,
+            (prefix_len != 0).then_some(IpCidr::Ipv4(remote)),
+            update.remote_port_min..=update.remote_port_max,
+            update.process_id,
+        ));
+    }
+
+    pub fn from_bulk_update_v6(update: &BulkUpdateV6) -> Result<Self, String> {
+        let prefix_len = update.remote_prefix_len;
+        if prefix_len > 128 {
+            return Err(alloc::format!("invalid ipv6 prefix length {}", prefix_len));
+        }
+        let remote = Ipv6Cidr::new(Ipv6Address::from_octets(update.remote_address), prefix_len);
+        return Ok(Self::new(
+            update.protocol,
+            update.local_port,
+            (prefix_len != 0).then_some(IpCidr::Ipv6(remote)),
+            update.remote_port_min..=update.remote_port_max,
+            update.process_id,
+        ));
+    }
+
+    /// Matches every connection of the process, for `UpdateByProcess`.
+    pub fn for_process(process_id: u64) -> Self {
+        Self::new(0, 0, None, 0..=0, process_id)
+    }
+
+    // The wire format uses 0 for "any", see `BulkUpdateV4`.
+    fn new(
+        protocol: u8,
+        local_port: u16,
+        remote: Option<IpCidr>,
+        remote_ports: RangeInclusive<u16>,
+        process_id: u64,
+    ) -> Self {
+        Self {
+            protocol: (protocol != 0).then(|| IpProtocol::from(protocol)),
+            local_port: (local_port != 0).then_some(local_port),
+            remote,
+            remote_ports: (*remote_ports.end() != 0).then_some(remote_ports),
+            process_id: (process_id != 0).then_some(process_id),
+        }
+    }
+
+    /// Reports whether the connection matches every field of the filter.
+    pub fn matches<T: Connection>(&self, conn: &T) -> bool {
+        self.protocol.is_none_or(|p| p == conn.get_protocol())
+            && self.local_port.is_none_or(|p| p == conn.get_local_port())
+            && self
+                .remote
+                .is_none_or(|cidr| cidr.contains_addr(&conn.get_remote_address()))
+            && self
+                .remote_ports
+                .as_ref()
+                .is_none_or(|ports| ports.contains(&conn.get_remote_port()))
+            && self.process_id.is_none_or(|p| p == conn.get_process_id())
+    }
+}
+
+/// Reports whether changing a connection from `old` to `new` changes what the ALE layer does with
+/// it: permit, block, or block and absorb (see `ale_callouts.rs`). Every other verdict is applied
+/// in the packet layer, which sees the change on the next packet.
+pub fn needs_reauthorization(old: Verdict, new: Verdict) -> bool {
+    fn ale_action(verdict: Verdict) -> u8 {
+        match verdict {
+            Verdict::PermanentBlock | Verdict::Undeterminable | Verdict::Failed => 1,
+            Verdict::PermanentDrop => 2,
+            _ => 0,
+        }
+    }
+    ale_action(old) != ale_action(new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{needs_reauthorization, ConnectionFilter};
+    use crate::connection::{ConnectionV4, Direction, Key, Verdict};
+    use protocol::command::BulkUpdateV4;
+    use smoltcp::wire::{IpAddress, IpProtocol, Ipv4Address};
+
+    fn conn(
+        protocol: IpProtocol,
+        local_port: u16,
+        remote: [u8; 4],
+        remote_port: u16,
+    ) -> ConnectionV4 {
+        let key = Key {
+            protocol,
+            local_address: IpAddress::Ipv4(Ipv4Address::new(192, 168, 1, 2)),
+            local_port,
+            remote_address: IpAddress::Ipv4(Ipv4Address::from_octets(remote)),
+            remote_port,
+        };
+        ConnectionV4::from_key(&key, 4321, Direction::Outbound).unwrap()
+    }
+
+    fn update() -> BulkUpdateV4 {
+        BulkUpdateV4 {
+            protocol: 0,
+            local_port: 0,
+            remote_address: [0; 4],
+            remote_prefix_len: 0,
+            remote_port_min: 0,
+            remote_port_max: 0,
+            process_id: 0,
+            verdict: 0,
+        }
+    }
+
+    #[test]
+    fn zero_fields_match_anything() {
+        let filter = ConnectionFilter::from_bulk_update_v4(&update()).unwrap();
+        assert!(filter.matches(&conn(IpProtocol::Tcp, 50000, [1, 1, 1, 1], 443)));
+        assert!(filter.matches(&conn(IpProtocol::Udp, 53, [10, 0, 0, 1], 53)));
+
+        let process = ConnectionFilter::for_process(4321);
+        assert!(process.matches(&conn(IpProtocol::Udp, 53, [10, 0, 0, 1], 53)));
+        assert!(!ConnectionFilter::for_process(1).matches(&conn(IpProtocol::Tcp, 1, [1; 4], 1)));
+    }
+
+    #[test]
+    fn every_given_field_must_match() {
+        let filter = ConnectionFilter::from_bulk_update_v4(&BulkUpdateV4 {
+            protocol: 6,
+            remote_address: [10, 1, 0, 0],
+            remote_prefix_len: 16,
+            remote_port_min: 80,
+            remote_port_max: 443,
+            process_id: 4321,
+            ..update()
+        })
+        .unwrap();
+        assert!(filter.matches(&conn(IpProtocol::Tcp, 50000, [10, 1, 2, 3], 80)));
+        assert!(filter.matches(&conn(IpProtocol::Tcp, 50001, [10, 1, 255, 1], 443)));
+        // Another protocol, outside of the prefix, outside of the port range.
+        assert!(!filter.matches(&conn(IpProtocol::Udp, 50000, [10, 1, 2, 3], 80)));
+        assert!(!filter.matches(&conn(IpProtocol::Tcp, 50000, [10, 2, 0, 1], 80)));
+        assert!(!filter.matches(&conn(IpProtocol::Tcp, 50000, [10, 1, 2, 3], 8080)));
+
+        let other_process = ConnectionFilter::from_bulk_update_v4(&BulkUpdateV4 {
+            process_id: 1,
+            ..update()
+        })
+        .unwrap();
+        assert!(!other_process.matches(&conn(IpProtocol::Tcp, 50000, [10, 1, 2, 3], 80)));
+
+        let local_port = ConnectionFilter::from_bulk_update_v4(&BulkUpdateV4 {
+            local_port: 50000,
+            ..update()
+        })
+        .unwrap();
+        assert!(local_port.matches(&conn(IpProtocol::Tcp, 50000, [1, 1, 1, 1], 80)));
+        assert!(!local_port.matches(&conn(IpProtocol::Tcp, 50001, [1, 1, 1, 1], 80)));
+
+        assert!(ConnectionFilter::from_bulk_update_v4(&BulkUpdateV4 {
+            remote_prefix_len: 33,
+            ..update()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn reauthorization_only_when_the_ale_action_changes() {
+        assert!(needs_reauthorization(
+            Verdict::PermanentAccept,
+            Verdict::PermanentBlock
+        ));
+        assert!(needs_reauthorization(
+            Verdict::PermanentBlock,
+            Verdict::PermanentDrop
+        ));
+        assert!(needs_reauthorization(Verdict::Failed, Verdict::Accept));
+        // Both permitted by the ALE layer, or both blocked.
+        assert!(!needs_reauthorization(
+            Verdict::Undecided,
+            Verdict::PermanentAccept
+        ));
+        assert!(!needs_reauthorization(Verdict::Accept, Verdict::Block));
+        assert!(!needs_reauthorization(
+            Verdict::Undeterminable,
+            Verdict::PermanentBlock
+        ));
+    }
+}
@@ -1,6 +1,8 @@
 use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
 
 use alloc::string::String;
+use alloc::vec::Vec;
 use num_traits::FromPrimitive;
 use protocol::{command::CommandType, info::Info};
 use smoltcp::wire::{IpAddress, IpProtocol, Ipv4Address, Ipv6Address};
@@ -14,18 +16,34 @@ use wdk::{
     },
     ioqueue::{self, IOQueue},
     irp_helpers::{ReadRequest, WriteRequest},
+    periodic::PeriodicWork,
+    process::{Process, ProcessEvent, ProcessNotify},
+    rw_spin_lock::Mutex,
 };
 
 use crate::{
     array_holder::ArrayHolder,
+    bind_policy::{BindPolicy, BindRule, BindTarget},
+    cache_timeouts::SystemClock,
     callouts,
-    connection::{Connection, ConnectionV4, ConnectionV6, Key},
+    connection::{Connection, ConnectionV4, ConnectionV6, Direction, EndReason, Key},
     connection_cache::ConnectionCache,
-    dbg, err,
+    connection_filter::ConnectionFilter,
+    dbg,
+    egress_policy::{ConnectionEgress, EgressPolicy, EgressRoute},
+    err,
     filter_reset_queue::{FilterResetQueue, PendingReset},
     id_cache::IdCache,
-    info, logger,
-    packet_util::Redirect,
+    info,
+    kill_switch::KillSwitch,
+    logger,
+    packet_util::{needs_egress, Egress, Redirect},
+    process_path::VolumeMap,
+    process_table::ProcessTable,
+    tcp_reset,
+    tcp_state::TcpState,
+    trusted_processes::TrustedProcesses,
+    verdict_state,
 };
 
 pub enum Packet {
@@ -42,14 +60,46 @@ pub struct Device {
     pub(crate) connection_cache: ConnectionCache,
     pub(crate) injector: Injector,
     pub(crate) network_allocator: NetworkAllocator,
+    /// Source address rules applied by the bind redirect callouts.
+    pub(crate) bind_policy: Mutex<BindPolicy>,
+    /// Egress interface overrides, picked up by new connections.
+    pub(crate) egress_policy: Mutex<EgressPolicy>,
+    /// Blocks everything that does not go through the tunnel interface while enabled.
+    pub(crate) kill_switch: Mutex<KillSwitch>,
+    /// Processes whose connections are accepted without asking user space.
+    pub(crate) trusted_processes: Mutex<TrustedProcesses<Process>>,
     /// Connections that were deferred without a completion handle and are waiting for the filter
     /// reset that releases them. See `reset_filters_and_inject`.
     filter_reset_queue: FilterResetQueue,
     /// Set once the teardown has begun. Callouts check it and stop pending operations, because
     /// from that point on nothing is left to answer a pend.
     shutdown_started: AtomicBool,
+    /// Monitor-only mode: connections are tracked and reported, but traffic is never held.
+    monitor_only: AtomicBool,
+    /// TCP state tracking options (see `tcp_state.rs`): send state change events, drop the packets
+    /// that do not fit the state of their connection.
+    tcp_state_events: AtomicBool,
+    tcp_strict: AtomicBool,
+    /// Drive letters of the volumes, used to normalise process paths.
+    volume_map: Mutex<VolumeMap>,
+    /// Set when a process path on a volume without a known drive letter was seen. The volume map
+    /// is refreshed on the next process creation, which runs at PASSIVE_LEVEL.
+    volume_map_stale: AtomicBool,
+    /// Parent of every process created since the driver started, fed by the process notifications.
+    pub(crate) process_table: Mutex<ProcessTable>,
+    /// Process create/exit notifications. None if the registration failed, connections of exited
+    /// processes then only age out.
+    process_notify: Option<ProcessNotify>,
+    /// Runs the cache cleanup every `CLEANUP_PERIOD`, so the cache does not depend on user space
+    /// sending `CleanEndedConnections`. None if it could not be started.
+    periodic_cleanup: Option<PeriodicWork>,
+    /// Set while a cleanup runs. The timer and the command never sweep the cache at the same time.
+    cleanup_running: AtomicBool,
 }
 
+// How often the driver cleans the connection cache on its own.
+const CLEANUP_PERIOD: Duration = Duration::from_secs(10);
+
 impl Device {
     /// Initialize all members of the device. Memory is handled by windows.
     /// Make sure everything is initialized here.
@@ -64,6 +114,28 @@ impl Device {
             return Err(err);
         }
 
+        // Events that come in before the device is stored are dropped, there are no connections
+        // yet at that point.
+        let process_notify = match ProcessNotify::register(process_event) {
+            Ok(notify) => Some(notify),
+            Err(err) => {
+                err!("{}", err);
+                None
+            }
+        };
+
+        // Calls that come in before the device is stored do nothing.
+        let periodic_cleanup = match PeriodicWork::start(driver, CLEANUP_PERIOD, periodic_cleanup) {
+            Ok(work) => Some(work),
+            Err(err) => {
+                err!("{}", err);
+                None
+            }
+        };
+
+        let mut volume_map = VolumeMap::new();
+        volume_map.set(query_volumes());
+
         Ok(Self {
             filter_engine,
             read_leftover: ArrayHolder::default(),
@@ -72,8 +144,21 @@ impl Device {
             connection_cache: ConnectionCache::new(),
             injector: Injector::new(),
             network_allocator: NetworkAllocator::new(),
+            bind_policy: Mutex::new(BindPolicy::new()),
+            egress_policy: Mutex::new(EgressPolicy::new()),
+            kill_switch: Mutex::new(KillSwitch::new()),
+            trusted_processes: Mutex::new(TrustedProcesses::new()),
             filter_reset_queue: FilterResetQueue::new(),
             shutdown_started: AtomicBool::new(false),
+            monitor_only: AtomicBool::new(false),
+            tcp_state_events: AtomicBool::new(false),
+            tcp_strict: AtomicBool::new(false),
+            volume_map: Mutex::new(volume_map),
+            volume_map_stale: AtomicBool::new(false),
+            process_table: Mutex::new(ProcessTable::new()),
+            process_notify,
+            periodic_cleanup,
+            cleanup_running: AtomicBool::new(false),
         })
     }
 
@@ -84,6 +169,19 @@ impl Device {
         self.shutdown_started.load(Ordering::SeqCst)
     }
 
+    /// Reports whether the driver runs in monitor-only mode. The callouts then permit everything
+    /// right away (the kill switch excepted) instead of pending or absorbing it, while still
+    /// adding cache entries, counting bandwidth and sending info-only events.
+    pub fn is_monitor_only(&self) -> bool {
+        self.monitor_only.load(Ordering::Relaxed)
+    }
+
+    /// Reports whether the packet layer drops the TCP packets that do not fit the state of their
+    /// connection.
+    pub fn is_tcp_strict(&self) -> bool {
+        self.tcp_strict.load(Ordering::Relaxed)
+    }
+
     /// Cleanup is called just before drop.
     // pub fn cleanup(&mut self) {}
 
@@ -166,45 +264,12 @@ impl Device {
             CommandType::Verdict => {
                 let verdict = protocol::command::parse_verdict(buffer);
                 wdk::dbg!("Verdict command");
-                // Received verdict decision for a specific connection.
-                if let Some((key, mut packet)) = self.packet_cache.pop_id(verdict.id) {
-                    if let Some(verdict) = FromPrimitive::from_u8(verdict.verdict) {
-                        dbg!("Verdict received {}: {}", key, verdict);
-                        // Add verdict in the cache.
-                        let redirect_info = self.connection_cache.update_connection(key, verdict);
-
-                        match verdict {
-                            crate::connection::Verdict::Accept
-                            | crate::connection::Verdict::PermanentAccept => {
-                                if let Err(err) = self.inject_packet(packet, false) {
-                                    err!("failed to inject packet: {} key={}", err, key);
-                                } else {
-                                    dbg!("packet injected: {}", key);
-                                }
-                            }
-                            crate::connection::Verdict::RedirectNameServer
-                            | crate::connection::Verdict::RedirectTunnel => {
-                                if let Some(redirect_info) = redirect_info {
-                                    if let Err(err) = packet.redirect(redirect_info) {
-                                        err!("failed to redirect packet: {} key={}", err, key);
-                                    }
-                                    if let Err(err) = self.inject_packet(packet, false) {
-                                        err!("failed to inject packet: {} key={}", err, key);
-                                    }
-                                }
-                            }
-                            _ => {
-                                if let Err(err) = self.inject_packet(packet, true) {
-                                    err!("failed to inject packet: {} key={}", err, key);
-                                }
-                            }
-                        }
-                    };
-                } else {
-                    // Id was not in the packet cache.
-                    let id = verdict.id;
-                    err!("Verdict invalid id: {}", id);
-                }
+                self.packet_verdict(verdict.id, verdict.verdict, 0);
+            }
+            CommandType::TimedVerdict => {
+                let verdict = protocol::command::parse_timed_verdict(buffer);
+                wdk::dbg!("TimedVerdict command");
+                self.packet_verdict(verdict.id, verdict.verdict, verdict.ttl_secs);
             }
             CommandType::UpdateV4 => {
                 let update = protocol::command::parse_update_v4(buffer);
@@ -250,9 +315,238 @@ impl Device {
                     err!("invalid verdict value: {}", update.verdict);
                 }
             }
+            CommandType::UpsertV4 => {
+                let upsert = protocol::command::parse_upsert_v4(buffer);
+                let key = Key {
+                    protocol: IpProtocol::from(upsert.protocol),
+                    local_address: IpAddress::Ipv4(Ipv4Address::from_octets(upsert.local_address)),
+                    local_port: upsert.local_port,
+                    remote_address: IpAddress::Ipv4(Ipv4Address::from_octets(
+                        upsert.remote_address,
+                    )),
+                    remote_port: upsert.remote_port,
+                };
+                let process_id = upsert.process_id;
+                match (
+                    FromPrimitive::from_u8(upsert.verdict),
+                    FromPrimitive::from_u8(upsert.direction),
+                ) {
+                    (Some(verdict), Some(direction)) => {
+                        dbg!("Upsert received {}: {} PID: {}", key, verdict, process_id);
+                        match ConnectionV4::from_key(&key, process_id, direction) {
+                            Ok(mut conn) => {
+                                conn.set_verdict(verdict);
+                                conn.egress = self.upsert_egress(&key, process_id);
+                                _classify_defer = self.connection_cache.upsert_v4(conn);
+                                self.evict_if_full();
+                            }
+                            Err(err) => err!("failed to upsert connection {}: {}", key, err),
+                        }
+                    }
+                    _ => err!(
+                        "invalid upsert value: verdict={} direction={}",
+                        upsert.verdict,
+                        upsert.direction
+                    ),
+                }
+            }
+            CommandType::UpsertV6 => {
+                let upsert = protocol::command::parse_upsert_v6(buffer);
+                let key = Key {
+                    protocol: IpProtocol::from(upsert.protocol),
+                    local_address: IpAddress::Ipv6(Ipv6Address::from_octets(upsert.local_address)),
+                    local_port: upsert.local_port,
+                    remote_address: IpAddress::Ipv6(Ipv6Address::from_octets(
+                        upsert.remote_address,
+                    )),
+                    remote_port: upsert.remote_port,
+                };
+                let process_id = upsert.process_id;
+                match (
+                    FromPrimitive::from_u8(upsert.verdict),
+                    FromPrimitive::from_u8(upsert.direction),
+                ) {
+                    (Some(verdict), Some(direction)) => {
+                        dbg!("Upsert received {}: {} PID: {}", key, verdict, process_id);
+                        match ConnectionV6::from_key(&key, process_id, direction) {
+                            Ok(mut conn) => {
+                                conn.set_verdict(verdict);
+                                conn.egress = self.upsert_egress(&key, process_id);
+                                _classify_defer = self.connection_cache.upsert_v6(conn);
+                                self.evict_if_full();
+                            }
+                            Err(err) => err!("failed to upsert connection {}: {}", key, err),
+                        }
+                    }
+                    _ => err!(
+                        "invalid upsert value: verdict={} direction={}",
+                        upsert.verdict,
+                        upsert.direction
+                    ),
+                }
+            }
+            CommandType::BulkUpdateV4 => {
+                let update = protocol::command::parse_bulk_update_v4(buffer);
+                let (matched, reauthorize) = match (
+                    FromPrimitive::from_u8(update.verdict),
+                    ConnectionFilter::from_bulk_update_v4(update),
+                ) {
+                    (Some(verdict), Ok(filter)) => {
+                        let result = self.connection_cache.update_matching_v4(&filter, verdict);
+                        dbg!(
+                            "Bulk verdict update received {:?}: {} ({} connections)",
+                            update,
+                            verdict,
+                            result.0
+                        );
+                        result
+                    }
+                    (None, _) => {
+                        err!("invalid verdict value: {}", update.verdict);
+                        (0, false)
+                    }
+                    (_, Err(err)) => {
+                        err!("invalid bulk update: {}", err);
+                        (0, false)
+                    }
+                };
+                // Always answered, so user space is not left waiting for the count.
+                _ = self
+                    .event_queue
+                    .push(protocol::info::bulk_update_result_info(matched as u64));
+                // One reset for all the connections, through the same queue as the other resets.
+                if reauthorize {
+                    self.reset_filters_and_inject(None);
+                }
+            }
+            CommandType::BulkUpdateV6 => {
+                let update = protocol::command::parse_bulk_update_v6(buffer);
+                let (matched, reauthorize) = match (
+                    FromPrimitive::from_u8(update.verdict),
+                    ConnectionFilter::from_bulk_update_v6(update),
+                ) {
+                    (Some(verdict), Ok(filter)) => {
+                        let result = self.connection_cache.update_matching_v6(&filter, verdict);
+                        dbg!(
+                            "Bulk verdict update received {:?}: {} ({} connections)",
+                            update,
+                            verdict,
+                            result.0
+                        );
+                        result
+                    }
+                    (None, _) => {
+                        err!("invalid verdict value: {}", update.verdict);
+                        (0, false)
+                    }
+                    (_, Err(err)) => {
+                        err!("invalid bulk update: {}", err);
+                        (0, false)
+                    }
+                };
+                // Always answered, so user space is not left waiting for the count.
+                _ = self
+                    .event_queue
+                    .push(protocol::info::bulk_update_result_info(matched as u64));
+                // One reset for all the connections, through the same queue as the other resets.
+                if reauthorize {
+                    self.reset_filters_and_inject(None);
+                }
+            }
+            CommandType::TimedUpdateById => {
+                let update = protocol::command::parse_timed_update_by_id(buffer);
+                if let Some(verdict) = FromPrimitive::from_u8(update.verdict) {
+                    let connection_id = update.connection_id;
+                    let ttl_secs = update.ttl_secs;
+                    let expiry_ms = verdict_state::expiry_ms(
+                        verdict,
+                        ttl_secs,
+                        wdk::utils::get_system_timestamp_ms(),
+                    );
+                    match self.connection_cache.update_connection_by_id_until(
+                        connection_id,
+                        verdict,
+                        expiry_ms,
+                    ) {
+                        Some((key, redirect_info)) => {
+                            dbg!(
+                                "Verdict update received {}: {} TTL: {}s",
+                                key,
+                                verdict,
+                                ttl_secs
+                            );
+                            _classify_defer = redirect_info;
+                        }
+                        None => err!("update for unknown connection id: {}", connection_id),
+                    }
+                } else {
+                    err!("invalid verdict value: {}", update.verdict);
+                }
+            }
+            CommandType::KillConnection => {
+                let kill = protocol::command::parse_kill_connection(buffer);
+                let connection_id = kill.connection_id;
+                self.kill_connection(connection_id);
+            }
+            CommandType::UpdateById => {
+                let update = protocol::command::parse_update_by_id(buffer);
+                if let Some(verdict) = FromPrimitive::from_u8(update.verdict) {
+                    let connection_id = update.connection_id;
+                    match self
+                        .connection_cache
+                        .update_connection_by_id(connection_id, verdict)
+                    {
+                        Some((key, redirect_info)) => {
+                            dbg!("Verdict update received {}: {}", key, verdict);
+                            _classify_defer = redirect_info;
+                        }
+                        None => err!("update for unknown connection id: {}", connection_id),
+                    }
+                } else {
+                    err!("invalid verdict value: {}", update.verdict);
+                }
+            }
+            CommandType::UpdateByProcess => {
+                let update = protocol::command::parse_update_by_process(buffer);
+                if let Some(verdict) = FromPrimitive::from_u8(update.verdict) {
+                    let process_id = update.process_id;
+                    let (count, reauthorize) = self
+                        .connection_cache
+                        .update_process_connections(process_id, verdict);
+                    crate::dbg!(
+                        "Verdict update received for process {}: {} ({} connections)",
+                        process_id,
+                        verdict,
+                        count
+                    );
+                    // Connections already decided in the ALE layer only see the new verdict once
+                    // classified again. One reset for all of them, like the bulk updates.
+                    if reauthorize {
+                        self.reset_filters_and_inject(None);
+                    }
+                } else {
+                    err!("invalid verdict value: {}", update.verdict);
+                }
+            }
+            CommandType::GetProcessConnections => {
+                let request = protocol::command::parse_process_connections(buffer);
+                let process_id = request.process_id;
+                wdk::dbg!("GetProcessConnections command");
+                let (conns_v4, conns_v6) =
+                    self.connection_cache.get_process_connections(process_id);
+                for conn in conns_v4.iter() {
+                    _ = self.event_queue.push(update_event_v4(conn));
+                }
+                for conn in conns_v6.iter() {
+                    _ = self.event_queue.push(update_event_v6(conn));
+                }
+                _ = self
+                    .event_queue
+                    .push(protocol::info::connection_update_end_info());
+            }
             CommandType::ClearCache => {
                 wdk::dbg!("ClearCache command");
-                self.connection_cache.clear();
+                self.end_all_connections(EndReason::ClearCache);
                 // Goes through the same queue as the deferred reauthorizations: it is the same
                 // reset, and only one of them can run at a time. Carries no packet of its own.
                 self.reset_filters_and_inject(None);
@@ -268,18 +562,7 @@ impl Device {
                         return;
                     }
 
-                    let info = protocol::info::connection_update_event_v4_info(
-                        conn.protocol.into(),
-                        conn.local_address.octets(),
-                        conn.remote_address.octets(),
-                        conn.local_port,
-                        conn.remote_port,
-                        conn.bandwidth_usage.rx_bytes.load(Ordering::SeqCst),
-                        conn.bandwidth_usage.rx_packets.load(Ordering::SeqCst),
-                        conn.bandwidth_usage.tx_bytes.load(Ordering::SeqCst),
-                        conn.bandwidth_usage.tx_packets.load(Ordering::SeqCst),
-                    );
-                    _ = self.event_queue.push(info);
+                    _ = self.event_queue.push(update_event_v4(conn));
                 };
 
                 let send_event_v6 = |conn: &ConnectionV6| {
@@ -288,18 +571,7 @@ impl Device {
                         return;
                     }
 
-                    let info = protocol::info::connection_update_event_v6_info(
-                        conn.protocol.into(),
-                        conn.local_address.octets(),
-                        conn.remote_address.octets(),
-                        conn.local_port,
-                        conn.remote_port,
-                        conn.bandwidth_usage.rx_bytes.load(Ordering::SeqCst),
-                        conn.bandwidth_usage.rx_packets.load(Ordering::SeqCst),
-                        conn.bandwidth_usage.tx_bytes.load(Ordering::SeqCst),
-                        conn.bandwidth_usage.tx_packets.load(Ordering::SeqCst),
-                    );
-                    _ = self.event_queue.push(info);
+                    _ = self.event_queue.push(update_event_v6(conn));
                 };
 
                 self.connection_cache
@@ -326,6 +598,8 @@ impl Device {
                 let packet_cache_count = self.packet_cache.get_entries_count();
                 let (unlinked_v4, unlinked_v6) = self.connection_cache.get_unlinked_queue_counts();
                 let filter_reset_count = self.filter_reset_queue.get_entries_count();
+                let (entries, bytes, max_entries, evicted) = self.connection_cache.get_usage();
+                let port_tables_bytes = self.connection_cache.get_port_tables_bytes();
 
                 {
                     let mut log_line = protocol::info::log_line(
@@ -339,6 +613,18 @@ impl Device {
                     );
                     logger::add_line(log_line);
                 }
+                {
+                    let mut log_line = protocol::info::log_line(
+                        protocol::info::Severity::Info,
+                        logger::MAX_LOG_LINE_SIZE,
+                    );
+                    _ = write!(
+                        log_line,
+                        "CacheStats: entries={}/{} bytes={} port_tables_bytes={} evicted={}",
+                        entries, max_entries, bytes, port_tables_bytes, evicted
+                    );
+                    logger::add_line(log_line);
+                }
 
                 self.connection_cache
                     .walk_over_connections_v4(|conn: &ConnectionV4| {
@@ -400,49 +686,349 @@ impl Device {
                         logger::add_line(log_line);
                     });
             }
+            CommandType::SetCacheTimeouts => {
+                let timeouts = protocol::command::parse_set_cache_timeouts(buffer);
+                self.connection_cache.set_timeouts(timeouts);
+            }
+            CommandType::SetCacheLimit => {
+                let limit = protocol::command::parse_set_cache_limit(buffer);
+                self.connection_cache
+                    .set_max_entries(limit.max_entries as usize);
+                self.evict_if_full();
+            }
             CommandType::CleanEndedConnections => {
                 wdk::dbg!("CleanEndedConnections command");
-                let (conn_v4, conn_v6) = self.connection_cache.clean_ended_connections();
-
-                // Process ended ipv4 connections
-                for conn in conn_v4.iter() {
-                    let info = protocol::info::connection_end_event_v4_info(
-                        conn.get_process_id(),
-                        conn.get_direction() as u8,
-                        conn.protocol.into(),
-                        conn.local_address.octets(),
-                        conn.remote_address.octets(),
-                        conn.local_port,
-                        conn.remote_port,
-                        conn.bandwidth_usage.rx_bytes.load(Ordering::SeqCst),
-                        conn.bandwidth_usage.rx_packets.load(Ordering::SeqCst),
-                        conn.bandwidth_usage.tx_bytes.load(Ordering::SeqCst),
-                        conn.bandwidth_usage.tx_packets.load(Ordering::SeqCst),
-                    );
-                    _ = self.event_queue.push(info);
-                }
-
-                conn_v4.clear();
-
-                // Process ended ipv6 connections
-                for conn in conn_v6.iter() {
-                    let info = protocol::info::connection_end_event_v6_info(
-                        conn.get_process_id(),
-                        conn.get_direction() as u8,
-                        conn.protocol.into(),
-                        conn.local_address.octets(),
-                        conn.remote_address.octets(),
-                        conn.local_port,
-                        conn.remote_port,
-                        conn.bandwidth_usage.rx_bytes.load(Ordering::SeqCst),
-                        conn.bandwidth_usage.rx_packets.load(Ordering::SeqCst),
-                        conn.bandwidth_usage.tx_bytes.load(Ordering::SeqCst),
-                        conn.bandwidth_usage.tx_packets.load(Ordering::SeqCst),
+                self.clean_ended_connections();
+            }
+            CommandType::SetBindRedirectV4 => {
+                let (rule, app_path) = protocol::command::parse_bind_redirect_v4(buffer);
+                wdk::dbg!("SetBindRedirectV4 command");
+                let address = IpAddress::Ipv4(Ipv4Address::from_octets(rule.local_address));
+                self.set_bind_redirect(rule.process_id, app_path, address);
+            }
+            CommandType::SetBindRedirectV6 => {
+                let (rule, app_path) = protocol::command::parse_bind_redirect_v6(buffer);
+                wdk::dbg!("SetBindRedirectV6 command");
+                let address = IpAddress::Ipv6(Ipv6Address::from_octets(rule.local_address));
+                self.set_bind_redirect(rule.process_id, app_path, address);
+            }
+            CommandType::ClearBindRedirects => {
+                wdk::dbg!("ClearBindRedirects command");
+                self.bind_policy.write_lock().clear();
+            }
+            CommandType::SetEgressRouteV4 => {
+                let route = protocol::command::parse_egress_route_v4(buffer);
+                wdk::dbg!("SetEgressRouteV4 command");
+                let source_address =
+                    IpAddress::Ipv4(Ipv4Address::from_octets(route.source_address));
+                self.set_egress_route(
+                    route.process_id,
+                    EgressRoute {
+                        interface_index: route.interface_index,
+                        sub_interface_index: route.sub_interface_index,
+                        source_address,
+                    },
+                );
+            }
+            CommandType::SetEgressRouteV6 => {
+                let route = protocol::command::parse_egress_route_v6(buffer);
+                wdk::dbg!("SetEgressRouteV6 command");
+                let source_address =
+                    IpAddress::Ipv6(Ipv6Address::from_octets(route.source_address));
+                self.set_egress_route(
+                    route.process_id,
+                    EgressRoute {
+                        interface_index: route.interface_index,
+                        sub_interface_index: route.sub_interface_index,
+                        source_address,
+                    },
+                );
+            }
+            CommandType::ClearEgressRoutes => {
+                wdk::dbg!("ClearEgressRoutes command");
+                self.egress_policy.write_lock().clear();
+            }
+            CommandType::SetKillSwitch => {
+                let kill_switch = protocol::command::parse_kill_switch(buffer);
+                wdk::dbg!("SetKillSwitch command");
+                if kill_switch.enabled != 0 {
+                    let tunnel_interface_index = kill_switch.tunnel_interface_index;
+                    info!(
+                        "kill switch enabled, tunnel interface: {}",
+                        tunnel_interface_index
                     );
-                    _ = self.event_queue.push(info);
+                    self.kill_switch.write_lock().enable(tunnel_interface_index);
+                } else {
+                    info!("kill switch disabled");
+                    self.kill_switch.write_lock().disable();
+                }
+            }
+            CommandType::AddKillSwitchPrefixV4 => {
+                let prefix = protocol::command::parse_kill_switch_prefix_v4(buffer);
+                wdk::dbg!("AddKillSwitchPrefixV4 command");
+                let address = IpAddress::Ipv4(Ipv4Address::from_octets(prefix.address));
+                if let Err(err) = self
+                    .kill_switch
+                    .write_lock()
+                    .allow_prefix(address, prefix.prefix_length)
+                {
+                    err!("failed to allow kill switch prefix: {}", err);
+                }
+            }
+            CommandType::AddKillSwitchPrefixV6 => {
+                let prefix = protocol::command::parse_kill_switch_prefix_v6(buffer);
+                wdk::dbg!("AddKillSwitchPrefixV6 command");
+                let address = IpAddress::Ipv6(Ipv6Address::from_octets(prefix.address));
+                if let Err(err) = self
+                    .kill_switch
+                    .write_lock()
+                    .allow_prefix(address, prefix.prefix_length)
+                {
+                    err!("failed to allow kill switch prefix: {}", err);
+                }
+            }
+            CommandType::ClearKillSwitchPrefixes => {
+                wdk::dbg!("ClearKillSwitchPrefixes command");
+                self.kill_switch.write_lock().clear_prefixes();
+            }
+            CommandType::SetMonitorMode => {
+                let mode = protocol::command::parse_monitor_mode(buffer);
+                wdk::dbg!("SetMonitorMode command");
+                let enabled = mode.enabled != 0;
+                info!("monitor-only mode: {}", enabled);
+                // Operations that are already pended stay pended until user space answers them.
+                self.monitor_only.store(enabled, Ordering::Relaxed);
+            }
+            CommandType::SetTcpTracking => {
+                let tracking = protocol::command::parse_set_tcp_tracking(buffer);
+                let state_events = tracking.state_events != 0;
+                let strict = tracking.strict != 0;
+                info!(
+                    "tcp state events: {}, strict mode: {}",
+                    state_events, strict
+                );
+                self.tcp_state_events.store(state_events, Ordering::Relaxed);
+                self.tcp_strict.store(strict, Ordering::Relaxed);
+            }
+            CommandType::RegisterTrustedProcess => {
+                wdk::dbg!("RegisterTrustedProcess command");
+                // Look the processes up before taking the lock: the lookup needs IRQL <= APC_LEVEL.
+                let mut processes = Vec::new();
+                for process_id in protocol::command::parse_trusted_processes(buffer) {
+                    match Process::lookup(process_id) {
+                        Ok(process) => processes.push(process),
+                        Err(err) => err!("failed to register trusted process: {}", err),
+                    }
+                }
+
+                let mut trusted = self.trusted_processes.write_lock();
+                trusted.remove_exited();
+                for process in processes {
+                    info!("trusted process registered: {}", process.process_id());
+                    trusted.add(process.process_id(), process);
                 }
-                conn_v6.clear();
             }
+            CommandType::ClearTrustedProcesses => {
+                wdk::dbg!("ClearTrustedProcesses command");
+                self.trusted_processes.write_lock().clear();
+            }
+        }
+    }
+
+    /// Reports whether the kill switch blocks traffic between `local` and `remote` on
+    /// `interface_index`. The callouts block such traffic on their own, user space is not asked.
+    pub fn kill_switch_blocks(
+        &self,
+        interface_index: u32,
+        local: IpAddress,
+        remote: IpAddress,
+    ) -> bool {
+        !self
+            .kill_switch
+            .read_lock()
+            .allows(interface_index, local, remote)
+    }
+
+    /// Reports whether the process is registered as trusted. A registration whose process has
+    /// exited is dropped here, the first time it is looked at after the exit.
+    pub fn is_trusted_process(&self, process_id: u64) -> bool {
+        if process_id == 0 {
+            return false;
+        }
+        let exited = match self.trusted_processes.read_lock().find(process_id) {
+            Some(process) => process.has_exited(),
+            None => return false,
+        };
+        if exited {
+            self.trusted_processes.write_lock().remove(process_id);
+            return false;
+        }
+        return true;
+    }
+
+    /// Returns the stable form of a process path (see `process_path.rs`). A path on a volume without
+    /// a known drive letter is returned as it is, and the volume map is refreshed later.
+    pub fn normalize_process_path(&self, path: String) -> String {
+        match self.volume_map.read_lock().normalize(&path) {
+            Some(normalized) => normalized,
+            None => {
+                self.volume_map_stale.store(true, Ordering::Relaxed);
+                path
+            }
+        }
+    }
+
+    /// Ends every connection of a process that exited and sends an end event for each. A trusted
+    /// registration of the process is dropped as well.
+    fn handle_process_event(&self, event: ProcessEvent) {
+        if self.is_shutting_down() {
+            return;
+        }
+        // The callouts can not look up drive letters, they do not run at PASSIVE_LEVEL. This does.
+        if let ProcessEvent::Created { .. } = event {
+            if self.volume_map_stale.swap(false, Ordering::Relaxed) {
+                let volumes = query_volumes();
+                self.volume_map.write_lock().set(volumes);
+            }
+        }
+        if let ProcessEvent::Exited { process_id } = event {
+            self.trusted_processes.write_lock().remove(process_id);
+        }
+        self.process_table.write_lock().handle_event(event);
+
+        let (conn_v4, conn_v6) = self.connection_cache.handle_process_event(event);
+        for conn in conn_v4.iter() {
+            _ = self
+                .event_queue
+                .push(end_event_v4(conn, EndReason::ProcessExit));
+        }
+        for conn in conn_v6.iter() {
+            _ = self
+                .event_queue
+                .push(end_event_v6(conn, EndReason::ProcessExit));
+        }
+    }
+
+    /// Sets or (for an unspecified source address) removes the egress route of a process. Only
+    /// connections added after this pick it up.
+    fn set_egress_route(&mut self, process_id: u64, route: EgressRoute) {
+        let mut policy = self.egress_policy.write_lock();
+        if route.source_address.is_unspecified() {
+            if !policy.remove(process_id, route.is_ipv6()) {
+                dbg!("no egress route to remove for PID: {}", process_id);
+            }
+        } else {
+            policy.set(process_id, route);
+        }
+    }
+
+    /// Applies the verdict of user space to the packet with the given id in the packet cache, and
+    /// stores it with its connection. A permanent verdict with a TTL reverts to undecided once it
+    /// expires (see `verdict_state.rs`), a TTL of 0 never does.
+    fn packet_verdict(&mut self, id: u64, verdict: u8, ttl_secs: u32) {
+        // Received verdict decision for a specific connection.
+        if let Some((key, mut packet)) = self.packet_cache.pop_id(id) {
+            if let Some(verdict) = FromPrimitive::from_u8(verdict) {
+                dbg!("Verdict received {}: {} TTL: {}s", key, verdict, ttl_secs);
+                // Add verdict in the cache.
+                let expiry_ms = verdict_state::expiry_ms(
+                    verdict,
+                    ttl_secs,
+                    wdk::utils::get_system_timestamp_ms(),
+                );
+                let redirect_info = self
+                    .connection_cache
+                    .update_connection_until(key, verdict, expiry_ms);
+
+                match verdict {
+                    crate::connection::Verdict::Accept
+                    | crate::connection::Verdict::PermanentAccept => {
+                        if let Err(err) = self.apply_egress(&key, &mut packet) {
+                            err!("failed to move packet: {} key={}", err, key);
+                        }
+                        if let Err(err) = self.inject_packet(packet, false) {
+                            err!("failed to inject packet: {} key={}", err, key);
+                        } else {
+                            dbg!("packet injected: {}", key);
+                        }
+                    }
+                    crate::connection::Verdict::RedirectNameServer
+                    | crate::connection::Verdict::RedirectTunnel => {
+                        if let Some(redirect_info) = redirect_info {
+                            if let Err(err) = packet.redirect(redirect_info) {
+                                err!("failed to redirect packet: {} key={}", err, key);
+                            }
+                            if let Err(err) = self.inject_packet(packet, false) {
+                                err!("failed to inject packet: {} key={}", err, key);
+                            }
+                        }
+                    }
+                    _ => {
+                        if let Err(err) = self.inject_packet(packet, true) {
+                            err!("failed to inject packet: {} key={}", err, key);
+                        }
+                    }
+                }
+            };
+        } else {
+            // Id was not in the packet cache.
+            err!("Verdict invalid id: {}", id);
+        }
+    }
+
+    /// Moves a packet that was accepted by user space onto the egress interface of its connection,
+    /// if the connection has one.
+    fn apply_egress(&self, key: &Key, packet: &mut Packet) -> Result<(), String> {
+        let Packet::PacketLayer(_, inject_info) = packet else {
+            return Ok(());
+        };
+        let direction = if inject_info.inbound {
+            Direction::Inbound
+        } else {
+            Direction::Outbound
+        };
+
+        if key.is_ipv6() {
+            if let Some(conn) = self.connection_cache.get_connection_v6(key) {
+                if let Some(egress) = conn.get_egress() {
+                    if needs_egress(conn.as_ref(), key, direction) {
+                        return packet.egress(egress, conn.get_local_address());
+                    }
+                }
+            }
+        } else if let Some(conn) = self.connection_cache.get_connection_v4(key) {
+            if let Some(egress) = conn.get_egress() {
+                if needs_egress(conn.as_ref(), key, direction) {
+                    return packet.egress(egress, conn.get_local_address());
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /// Adds, replaces or (for an unspecified address) removes a bind redirect rule. A process id of
+    /// 0 selects every process started from `app_path`.
+    fn set_bind_redirect(&mut self, process_id: u64, app_path: &[u8], address: IpAddress) {
+        let target = if process_id != 0 {
+            BindTarget::Process(process_id)
+        } else {
+            match core::str::from_utf8(app_path) {
+                Ok(path) if !path.is_empty() => BindTarget::App(String::from(path)),
+                _ => {
+                    err!("invalid bind redirect rule: no process id or application path");
+                    return;
+                }
+            }
+        };
+
+        let mut policy = self.bind_policy.write_lock();
+        if address.is_unspecified() {
+            let ipv6 = matches!(address, IpAddress::Ipv6(_));
+            if !policy.remove(&target, ipv6) {
+                dbg!("no bind redirect rule to remove for {:?}", target);
+            }
+        } else {
+            policy.set(BindRule { target, address });
         }
     }
 
@@ -461,6 +1047,14 @@ impl Device {
                 err!("failed to unregister filters on shutdown: {}", err);
             }
 
+            // No more connections are ended on process exit.
+            self.process_notify = None;
+            // Waits for a cleanup that is running.
+            self.periodic_cleanup = None;
+
+            // Best effort: the rundown below discards what user space has not read by then.
+            self.end_all_connections(EndReason::Shutdown);
+
             // End blocking operations from the queue. This will end pending read requests.
             self.event_queue.rundown();
         }
@@ -476,6 +1070,181 @@ impl Device {
         }
     }
 
+    /// Removes expired connections from the cache and sends an end event for the idle ones. Called
+    /// by the periodic cleanup and the `CleanEndedConnections` command; if a cleanup is already
+    /// running, that one does the job and this returns right away.
+    fn clean_ended_connections(&mut self) {
+        if self.cleanup_running.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let (conn_v4, conn_v6) = self.connection_cache.clean_ended_connections(&SystemClock);
+
+        // Process idle ipv4 connections
+        for conn in conn_v4.iter() {
+            _ = self
+                .event_queue
+                .push(end_event_v4(conn, EndReason::IdleTimeout));
+        }
+        conn_v4.clear();
+
+        // Process idle ipv6 connections
+        for conn in conn_v6.iter() {
+            _ = self
+                .event_queue
+                .push(end_event_v6(conn, EndReason::IdleTimeout));
+        }
+        conn_v6.clear();
+
+        self.cleanup_running.store(false, Ordering::SeqCst);
+    }
+
+    /// Egress route of a connection added with an `Upsert` command, picked like in the ALE layer.
+    /// None without a process id.
+    fn upsert_egress(&self, key: &Key, process_id: u64) -> Option<ConnectionEgress> {
+        if process_id == 0 || key.is_loopback() {
+            return None;
+        }
+        self.egress_policy
+            .read_lock()
+            .find(process_id, key.is_ipv6())
+            .map(ConnectionEgress::new)
+    }
+
+    /// Resets the TCP connection with the given id on both ends, and ends it with the
+    /// `KilledByCommand` reason.
+    fn kill_connection(&mut self, id: u64) {
+        let Some(key) = self.connection_cache.get_key_by_id(id) else {
+            err!("kill for unknown connection id: {}", id);
+            return;
+        };
+        if key.protocol != IpProtocol::Tcp {
+            err!("kill for a connection that is not TCP: {}", key);
+            return;
+        }
+        if key.is_ipv6() {
+            if let Some(conn) = self.connection_cache.get_connection_v6(&key) {
+                self.send_resets(conn.as_ref(), &key);
+            }
+        } else if let Some(conn) = self.connection_cache.get_connection_v4(&key) {
+            self.send_resets(conn.as_ref(), &key);
+        }
+        self.end_connection(key, EndReason::KilledByCommand);
+        dbg!("connection killed: {}", key);
+    }
+
+    /// Ends the connection and sends its end event, unless it ended already.
+    fn end_connection(&self, key: Key, reason: EndReason) {
+        if key.is_ipv6() {
+            if let Some(conn) = self.connection_cache.end_v6(key) {
+                _ = self.event_queue.push(end_event_v6(&conn, reason));
+            }
+        } else if let Some(conn) = self.connection_cache.end_v4(key) {
+            _ = self.event_queue.push(end_event_v4(&conn, reason));
+        }
+    }
+
+    /// Called by the packet layer when a TCP connection changes state (see `tcp_state.rs`). Sends
+    /// the state change event if enabled, and ends the connection once both ends sent a FIN or
+    /// one sent a RST.
+    pub(crate) fn tcp_state_changed<T: Connection>(&self, conn: &T, state: TcpState) {
+        if self.tcp_state_events.load(Ordering::Relaxed) {
+            _ = self.event_queue.push(protocol::info::tcp_state_change_info(
+                conn.get_id(),
+                state as u8,
+            ));
+        }
+        match state {
+            TcpState::Closed => self.end_connection(conn.get_key(), EndReason::TcpClosed),
+            TcpState::Reset => self.end_connection(conn.get_key(), EndReason::TcpReset),
+            _ => {}
+        }
+    }
+
+    /// Injects a RST to the remote end and one to the local stack (see `tcp_reset.rs`). The
+    /// connection is blocked first, so nothing sent after the kill gets through.
+    fn send_resets<T: Connection>(&mut self, conn: &T, key: &Key) {
+        conn.set_verdict(crate::connection::Verdict::PermanentBlock);
+
+        // A connection with an egress route is reset on the interface it was first routed to, and
+        // its RST to the remote end is moved to the route like its other packets.
+        let details = conn.get_details();
+        let (interface_index, sub_interface_index) = conn
+            .get_egress()
+            .and_then(|egress| egress.get_origin())
+            .unwrap_or((details.interface_index, details.sub_interface_index));
+
+        for direction in [Direction::Outbound, Direction::Inbound] {
+            let Some((seq, ack)) = conn.get_tcp_sequence().reset_numbers(direction) else {
+                err!("no sequence numbers to reset {} {} with", direction, key);
+                continue;
+            };
+            let nbl = match tcp_reset::build_rst(key, direction, seq, ack)
+                .and_then(|data| NetBufferList::from_packet(data, &self.network_allocator))
+            {
+                Ok(nbl) => nbl,
+                Err(err) => {
+                    err!("failed to build reset packet: {}", err);
+                    continue;
+                }
+            };
+            let mut packet = Packet::PacketLayer(
+                nbl,
+                InjectInfo {
+                    ipv6: key.is_ipv6(),
+                    inbound: matches!(direction, Direction::Inbound),
+                    loopback: key.is_loopback(),
+                    interface_index,
+                    sub_interface_index,
+                    compartment_id: details.compartment_id as i32,
+                    forward: false,
+                },
+            );
+            if let Some(egress) = conn
+                .get_egress()
+                .filter(|_| needs_egress(conn, key, direction))
+            {
+                if let Err(err) = packet.egress(egress, conn.get_local_address()) {
+                    err!("failed to move reset packet: {}", err);
+                    continue;
+                }
+            }
+            if let Err(err) = self.inject_packet(packet, false) {
+                err!("failed to inject reset packet: {}", err);
+            }
+        }
+    }
+
+    /// Evicts connections if the cache is over its limit, and sends an end event for the evicted
+    /// ones that were still active. Called after every insert.
+    pub(crate) fn evict_if_full(&self) {
+        let Some((conn_v4, conn_v6)) = self.connection_cache.evict_if_full() else {
+            return;
+        };
+        for conn in conn_v4.iter() {
+            _ = self
+                .event_queue
+                .push(end_event_v4(conn, EndReason::Evicted));
+        }
+        for conn in conn_v6.iter() {
+            _ = self
+                .event_queue
+                .push(end_event_v6(conn, EndReason::Evicted));
+        }
+    }
+
+    /// Removes every connection from the cache and sends an end event for the ones that were still
+    /// active.
+    fn end_all_connections(&self, reason: EndReason) {
+        let (conn_v4, conn_v6) = self.connection_cache.clear();
+        for conn in conn_v4.iter() {
+            _ = self.event_queue.push(end_event_v4(conn, reason));
+        }
+        for conn in conn_v6.iter() {
+            _ = self.event_queue.push(end_event_v6(conn, reason));
+        }
+    }
+
     /// Completes every operation still held in the packet cache. A pended ALE operation keeps its
     /// IRP alive inside the callout: if the driver unloads while one is still outstanding, that
     /// IRP can never be completed, the thread that owns it never leaves kernel mode, and its
@@ -636,6 +1405,102 @@ impl Device {
     }
 }
 
+// Returns the device name of every volume with a drive letter. Only callable at PASSIVE_LEVEL.
+fn query_volumes() -> Vec<(String, char)> {
+    let mut volumes = Vec::new();
+    for letter in 'A'..='Z' {
+        let link = alloc::format!("\\GLOBAL??\\{}:", letter);
+        if let Ok(device) = wdk::utils::query_symbolic_link(&link) {
+            volumes.push((device, letter));
+        }
+    }
+    return volumes;
+}
+
+// Called by the kernel for every process creation and exit.
+fn process_event(event: ProcessEvent) {
+    let Some(device) = crate::entry::get_device() else {
+        return;
+    };
+    device.handle_process_event(event);
+}
+
+fn periodic_cleanup() {
+    let Some(device) = crate::entry::get_device() else {
+        return;
+    };
+    if device.is_shutting_down() {
+        return;
+    }
+    device.clean_ended_connections();
+}
+
+fn end_event_v4(conn: &ConnectionV4, reason: EndReason) -> Info {
+    protocol::info::connection_end_event_v4_info(
+        conn.get_process_id(),
+        conn.get_direction() as u8,
+        conn.protocol.into(),
+        conn.local_address.octets(),
+        conn.remote_address.octets(),
+        conn.local_port,
+        conn.remote_port,
+        conn.bandwidth_usage.rx_bytes.load(Ordering::SeqCst),
+        conn.bandwidth_usage.rx_packets.load(Ordering::SeqCst),
+        conn.bandwidth_usage.tx_bytes.load(Ordering::SeqCst),
+        conn.bandwidth_usage.tx_packets.load(Ordering::SeqCst),
+        reason as u8,
+        &conn.get_metadata(),
+    )
+}
+
+fn end_event_v6(conn: &ConnectionV6, reason: EndReason) -> Info {
+    protocol::info::connection_end_event_v6_info(
+        conn.get_process_id(),
+        conn.get_direction() as u8,
+        conn.protocol.into(),
+        conn.local_address.octets(),
+        conn.remote_address.octets(),
+        conn.local_port,
+        conn.remote_port,
+        conn.bandwidth_usage.rx_bytes.load(Ordering::SeqCst),
+        conn.bandwidth_usage.rx_packets.load(Ordering::SeqCst),
+        conn.bandwidth_usage.tx_bytes.load(Ordering::SeqCst),
+        conn.bandwidth_usage.tx_packets.load(Ordering::SeqCst),
+        reason as u8,
+        &conn.get_metadata(),
+    )
+}
+
+fn update_event_v4(conn: &ConnectionV4) -> Info {
+    protocol::info::connection_update_event_v4_info(
+        conn.protocol.into(),
+        conn.local_address.octets(),
+        conn.remote_address.octets(),
+        conn.local_port,
+        conn.remote_port,
+        conn.bandwidth_usage.rx_bytes.load(Ordering::SeqCst),
+        conn.bandwidth_usage.rx_packets.load(Ordering::SeqCst),
+        conn.bandwidth_usage.tx_bytes.load(Ordering::SeqCst),
+        conn.bandwidth_usage.tx_packets.load(Ordering::SeqCst),
+        &conn.get_metadata(),
+    )
+}
+
+fn update_event_v6(conn: &ConnectionV6) -> Info {
+    protocol::info::connection_update_event_v6_info(
+        conn.protocol.into(),
+        conn.local_address.octets(),
+        conn.remote_address.octets(),
+        conn.local_port,
+        conn.remote_port,
+        conn.bandwidth_usage.rx_bytes.load(Ordering::SeqCst),
+        conn.bandwidth_usage.rx_packets.load(Ordering::SeqCst),
+        conn.bandwidth_usage.tx_bytes.load(Ordering::SeqCst),
+        conn.bandwidth_usage.tx_packets.load(Ordering::SeqCst),
+        &conn.get_metadata(),
+    )
+}
+
 impl Drop for Device {
     fn drop(&mut self) {
         // The driver can also be unloaded without ever receiving a shutdown command (service stop,
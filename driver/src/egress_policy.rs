@@ -0,0 +1,176 @@
+//! Per-process egress interface overrides (split tunnelling at the packet layer).
+//!
+//! User space pins the traffic of a process to an interface, usually a VPN adapter, together with
+//! the address the traffic has to leave with. The route is looked up once, when the connection is
+//! added to the cache, and stays with the connection: moving a connection that is already running
+//! to another source address would break it.
+//!
+//! Once the connection has an accepting verdict, the packet layer rewrites the local address of its
+//! outbound packets to the route source address and injects them on the route interface. Return
+//! traffic arrives addressed to the source address and is mapped back to the original local address
+//! (see `Connection::egress_equals` and `packet_util::Egress`). It is handed back to the stack on the
+//! interface the connection was routed to before the override: with the strong host model a packet
+//! for the original local address arriving on the route interface would be dropped.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use smoltcp::wire::IpAddress;
+
+/// Where the traffic of a connection leaves the machine.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EgressRoute {
+    pub interface_index: u32,
+    pub sub_interface_index: u32,
+    /// Local address of the packets sent on the interface. Also selects the address family the
+    /// route applies to.
+    pub source_address: IpAddress,
+}
+
+impl EgressRoute {
+    pub fn is_ipv6(&self) -> bool {
+        matches!(self.source_address, IpAddress::Ipv6(_))
+    }
+}
+
+/// Egress state of a connection.
+pub struct ConnectionEgress {
+    pub route: EgressRoute,
+    // Interface (high half) and sub interface (low half) the stack routed the connection to before
+    // the override. 0 until the first outbound packet is seen.
+    origin: AtomicU64,
+}
+
+impl ConnectionEgress {
+    pub fn new(route: EgressRoute) -> Self {
+        Self {
+            route,
+            origin: AtomicU64::new(0),
+        }
+    }
+
+    /// Records the interface an outbound packet was classified on, before it is moved.
+    pub fn set_origin(&self, interface_index: u32, sub_interface_index: u32) {
+        let origin = ((interface_index as u64) << 32) | sub_interface_index as u64;
+        self.origin.store(origin, Ordering::Relaxed);
+    }
+
+    /// Returns the interface and sub interface the connection was originally routed to. `None`
+    /// until an outbound packet of the connection was seen.
+    pub fn get_origin(&self) -> Option<(u32, u32)> {
+        let origin = self.origin.load(Ordering::Relaxed);
+        if origin == 0 {
+            return None;
+        }
+        Some(((origin >> 32) as u32, origin as u32))
+    }
+}
+
+impl Clone for ConnectionEgress {
+    fn clone(&self) -> Self {
+        Self {
+            route: self.route,
+            origin: AtomicU64::new(self.origin.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+pub struct EgressPolicy {
+    routes: Vec<(u64, EgressRoute)>,
+}
+
+impl EgressPolicy {
+    pub const fn new() -> Self {
+        Self { routes: Vec::new() }
+    }
+
+    /// Sets the route of the process for the address family of the route, replacing the previous
+    /// one.
+    pub fn set(&mut self, process_id: u64, route: EgressRoute) {
+        match self
+            .routes
+            .iter_mut()
+            .find(|(pid, r)| *pid == process_id && r.is_ipv6() == route.is_ipv6())
+        {
+            Some((_, existing)) => *existing = route,
+            None => self.routes.push((process_id, route)),
+        }
+    }
+
+    /// Removes the route of the process for the given address family. Returns false if there was
+    /// none.
+    pub fn remove(&mut self, process_id: u64, ipv6: bool) -> bool {
+        let len = self.routes.len();
+        self.routes
+            .retain(|(pid, r)| !(*pid == process_id && r.is_ipv6() == ipv6));
+        return self.routes.len() != len;
+    }
+
+    pub fn clear(&mut self) {
+        self.routes.clear();
+    }
+
+    /// Returns the route for a new connection of the process, if there is one for its address
+    /// family.
+    pub fn find(&self, process_id: u64, ipv6: bool) -> Option<EgressRoute> {
+        self.routes
+            .iter()
+            .find(|(pid, r)| *pid == process_id && r.is_ipv6() == ipv6)
+            .map(|(_, r)| *r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConnectionEgress, EgressPolicy, EgressRoute};
+    use smoltcp::wire::{IpAddress, Ipv4Address, Ipv6Address};
+
+    fn route_v4(interface_index: u32) -> EgressRoute {
+        EgressRoute {
+            interface_index,
+            sub_interface_index: 0,
+            source_address: IpAddress::Ipv4(Ipv4Address::new(10, 8, 0, 2)),
+        }
+    }
+
+    fn route_v6(interface_index: u32) -> EgressRoute {
+        EgressRoute {
+            interface_index,
+            sub_interface_index: 0,
+            source_address: IpAddress::Ipv6(Ipv6Address::new(0xfd00, 0, 0, 0, 0, 0, 0, 2)),
+        }
+    }
+
+    #[test]
+    fn routes_are_per_process_and_family() {
+        let mut policy = EgressPolicy::new();
+        policy.set(5, route_v4(12));
+        assert_eq!(policy.find(5, false), Some(route_v4(12)));
+        assert_eq!(policy.find(5, true), None);
+        assert_eq!(policy.find(6, false), None);
+
+        policy.set(5, route_v6(13));
+        assert_eq!(policy.find(5, false), Some(route_v4(12)));
+        assert_eq!(policy.find(5, true), Some(route_v6(13)));
+    }
+
+    #[test]
+    fn set_replaces_and_remove_drops() {
+        let mut policy = EgressPolicy::new();
+        policy.set(5, route_v4(12));
+        policy.set(5, route_v4(14));
+        assert_eq!(policy.find(5, false), Some(route_v4(14)));
+
+        assert!(!policy.remove(5, true));
+        assert!(policy.remove(5, false));
+        assert_eq!(policy.find(5, false), None);
+    }
+
+    #[test]
+    fn origin_is_unknown_until_set() {
+        let egress = ConnectionEgress::new(route_v4(12));
+        assert_eq!(egress.get_origin(), None);
+        egress.set_origin(7, 1);
+        assert_eq!(egress.get_origin(), Some((7, 1)));
+        assert_eq!(egress.clone().get_origin(), Some((7, 1)));
+    }
+}
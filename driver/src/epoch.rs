@@ -0,0 +1,398 @@
+//! Epoch based reclamation of the connection arrays replaced in an `RCUPort`.
+//!
+//! A reader pins the collector before it loads the array of a port and keeps the returned `Guard`
+//! while it uses the array. Pinning records the global epoch in a free reader slot. A writer that
+//! replaces an array retires the old one with the epoch at that moment. The epoch only moves
+//! forward when every pinned reader has seen the current one, so once it is two past the epoch of
+//! a retired array, every reader that could have loaded that array has unpinned, and it is freed.
+//!
+//! Reclaiming is tried on every publish, not only in the periodic cleanup, so retired arrays do not
+//! pile up in between. A reader that stays pinned only holds back what was retired while it was.
+//!
+//! The atomics come from `sync.rs` so the scheme can be checked with loom.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::mpsc_queue::MpscQueue;
+use crate::sync::{fence, spin_loop, AtomicBool, AtomicU64, Ordering};
+
+/// Reader slots of the collector of the connection cache. A reader holds one only while it looks
+/// at a port, so they are only all taken with this many readers at the same moment.
+pub(crate) const READER_SLOTS: usize = 256;
+
+/// An object that was unlinked and waits to be freed.
+pub(crate) trait Retired {
+    /// The epoch of the collector when the object was unlinked.
+    fn retired_epoch(&self) -> u64;
+}
+
+pub(crate) struct Collector {
+    // Starts at 1, 0 marks a free slot.
+    epoch: AtomicU64,
+    // 0 when free, the epoch the reader pinned at otherwise.
+    slots: Box<[AtomicU64]>,
+    // Set while a caller reclaims. The queues have a single consumer.
+    reclaiming: AtomicBool,
+}
+
+/// A pinned reader. What the reader loaded while pinned is not freed before this is dropped.
+pub(crate) struct Guard<'a> {
+    collector: &'a Collector,
+    slot: usize,
+}
+
+impl Collector {
+    pub(crate) fn new(slots: usize) -> Self {
+        Self {
+            epoch: AtomicU64::new(1),
+            slots: (0..slots)
+                .map(|_| AtomicU64::new(0))
+                .collect::<Vec<AtomicU64>>()
+                .into_boxed_slice(),
+            reclaiming: AtomicBool::new(false),
+        }
+    }
+
+    /// Pins the current epoch. Lock free unless every slot is taken, then it waits for one.
+    pub(crate) fn pin(&self) -> Guard<'_> {
+        loop {
+            let epoch = self.epoch.load(Ordering::SeqCst);
+            for (slot, state) in self.slots.iter().enumerate() {
+                if state
+                    .compare_exchange(0, epoch, Ordering::SeqCst, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    // Orders the slot before the loads of the reader, against the fence of
+                    // `epoch` and `try_advance`: either a writer sees the slot, or the reader
+                    // sees what the writer published.
+                    fence(Ordering::SeqCst);
+                    return Guard {
+                        collector: self,
+                        slot,
+                    };
+                }
+            }
+            spin_loop();
+        }
+    }
+
+    /// The epoch to retire an object with. Only call once the object is unlinked.
+    pub(crate) fn epoch(&self) -> u64 {
+        fence(Ordering::SeqCst);
+        // A read-modify-write, so the advances that come after it also come after the unlink.
+        self.epoch.fetch_add(0, Ordering::SeqCst)
+    }
+
+    // Moves the epoch one forward if every pinned reader is in the current one. Returns the epoch.
+    fn try_advance(&self) -> u64 {
+        let epoch = self.epoch.load(Ordering::SeqCst);
+        fence(Ordering::SeqCst);
+        for state in self.slots.iter() {
+            let pinned = state.load(Ordering::SeqCst);
+            if pinned != 0 && pinned != epoch {
+                return epoch;
+            }
+        }
+        // Fails if another caller moved it first, it has moved either way.
+        _ = self
+            .epoch
+            .compare_exchange(epoch, epoch + 1, Ordering::SeqCst, Ordering::SeqCst);
+        return self.epoch.load(Ordering::SeqCst);
+    }
+
+    /// Reports whether an object retired at `retired` can be freed: no pinned reader can have
+    /// loaded it anymore.
+    pub(crate) fn is_safe(&self, retired: u64) -> bool {
+        self.try_advance() >= retired + 2
+    }
+
+    /// Frees the objects of the queue that no reader can see anymore, oldest first, with `free`.
+    /// Returns how many were freed. If another caller is reclaiming, returns 0 right away.
+    pub(crate) fn reclaim<R: Retired>(
+        &self,
+        queue: &MpscQueue<R>,
+        mut free: impl FnMut(*mut R),
+    ) -> usize {
+        if self.reclaiming.swap(true, Ordering::Acquire) {
+            return 0;
+        }
+        let mut freed = 0;
+        while let Some(retired) = queue.peek() {
+            // Retired in about the order they were unlinked: stop at the first one that is still
+            // visible, the next ones are too or will be soon.
+            if !self.is_safe(retired.retired_epoch()) {
+                break;
+            }
+            free(queue.pop());
+            freed += 1;
+        }
+        self.reclaiming.store(false, Ordering::Release);
+        return freed;
+    }
+}
+
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        self.collector.slots[self.slot].store(0, Ordering::Release);
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::{Collector, Retired};
+    use crate::mpsc_queue::MpscQueue;
+    use alloc::boxed::Box;
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+    use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+    // Never freed during a test. "Freeing" it clears `alive`, so a reader that can still see a
+    // freed object finds out without touching freed memory.
+    struct Object {
+        alive: AtomicBool,
+        retired: AtomicU64,
+    }
+
+    impl Retired for Object {
+        fn retired_epoch(&self) -> u64 {
+            self.retired.load(Ordering::SeqCst)
+        }
+    }
+
+    fn pool(count: usize) -> Vec<*mut Object> {
+        (0..count)
+            .map(|_| {
+                Box::into_raw(Box::new(Object {
+                    alive: AtomicBool::new(true),
+                    retired: AtomicU64::new(0),
+                }))
+            })
+            .collect()
+    }
+
+    fn mark_freed(object: *mut Object) {
+        unsafe { (*object).alive.store(false, Ordering::SeqCst) };
+    }
+
+    #[test]
+    fn retired_object_waits_for_pinned_reader() {
+        let collector = Collector::new(4);
+        let queue = MpscQueue::new();
+        let objects = pool(2);
+        let current = AtomicPtr::new(objects[0]);
+
+        let guard = collector.pin();
+        let seen = current.load(Ordering::SeqCst);
+
+        // A writer replaces the object while the reader is pinned.
+        let old = current.swap(objects[1], Ordering::SeqCst);
+        unsafe { (*old).retired.store(collector.epoch(), Ordering::SeqCst) };
+        queue.push(old);
+        for _ in 0..5 {
+            assert_eq!(collector.reclaim(&queue, mark_freed), 0);
+        }
+        assert!(unsafe { (*seen).alive.load(Ordering::SeqCst) });
+
+        drop(guard);
+        assert_eq!(collector.reclaim(&queue, mark_freed), 1);
+        assert!(!unsafe { (*seen).alive.load(Ordering::SeqCst) });
+        assert!(queue.is_empty());
+
+        for object in objects {
+            unsafe { drop(Box::from_raw(object)) };
+        }
+    }
+
+    #[test]
+    fn reader_pinned_after_retirement_does_not_hold_it_back() {
+        let collector = Collector::new(4);
+        let queue = MpscQueue::new();
+        let objects = pool(1);
+
+        unsafe {
+            (*objects[0])
+                .retired
+                .store(collector.epoch(), Ordering::SeqCst)
+        };
+        queue.push(objects[0]);
+        // Takes two advances, each one pinning a reader in the new epoch.
+        let _first = collector.pin();
+        assert_eq!(collector.reclaim(&queue, mark_freed), 0);
+        let _second = collector.pin();
+        collector.reclaim(&queue, mark_freed);
+        drop(_first);
+        assert_eq!(collector.reclaim(&queue, mark_freed), 1);
+
+        unsafe { drop(Box::from_raw(objects[0])) };
+    }
+
+    #[test]
+    fn all_slots_taken_waits_for_one() {
+        let collector = Arc::new(Collector::new(1));
+        let guard = collector.pin();
+        let waiter = {
+            let collector = collector.clone();
+            std::thread::spawn(move || {
+                let _guard = collector.pin();
+            })
+        };
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        drop(guard);
+        waiter.join().unwrap();
+    }
+
+    // Readers check that the object they loaded is alive for as long as they are pinned, while
+    // writers keep replacing it and reclaiming.
+    #[test]
+    fn stress() {
+        const READERS: usize = 4;
+        const WRITERS: usize = 2;
+        const REPLACEMENTS: usize = 100_000;
+
+        let collector = Arc::new(Collector::new(8));
+        let queue = Arc::new(MpscQueue::new());
+        let objects = pool(REPLACEMENTS + 1);
+        let current = Arc::new(AtomicPtr::new(objects[0]));
+        let next = Arc::new(AtomicUsize::new(1));
+        let done = Arc::new(AtomicBool::new(false));
+        let objects_addr: Arc<Vec<usize>> =
+            Arc::new(objects.iter().map(|object| *object as usize).collect());
+
+        let readers: Vec<_> = (0..READERS)
+            .map(|_| {
+                let (collector, current, done) = (collector.clone(), current.clone(), done.clone());
+                std::thread::spawn(move || {
+                    let mut reads = 0u64;
+                    while !done.load(Ordering::SeqCst) {
+                        let _guard = collector.pin();
+                        let object = current.load(Ordering::SeqCst);
+                        for _ in 0..10 {
+                            assert!(unsafe { (*object).alive.load(Ordering::SeqCst) });
+                            core::hint::spin_loop();
+                        }
+                        reads += 1;
+                    }
+                    reads
+                })
+            })
+            .collect();
+
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|_| {
+                let (collector, queue, current, next, objects) = (
+                    collector.clone(),
+                    queue.clone(),
+                    current.clone(),
+                    next.clone(),
+                    objects_addr.clone(),
+                );
+                std::thread::spawn(move || loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    if index >= objects.len() {
+                        break;
+                    }
+                    let old = current.swap(objects[index] as *mut Object, Ordering::SeqCst);
+                    unsafe { (*old).retired.store(collector.epoch(), Ordering::SeqCst) };
+                    queue.push(old);
+                    collector.reclaim(&queue, mark_freed);
+                })
+            })
+            .collect();
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        done.store(true, Ordering::SeqCst);
+        for reader in readers {
+            assert!(reader.join().unwrap() > 0);
+        }
+
+        // With no reader left everything retired can go.
+        while !queue.is_empty() {
+            collector.reclaim(&queue, mark_freed);
+        }
+        let alive = objects
+            .iter()
+            .filter(|object| unsafe { (***object).alive.load(Ordering::SeqCst) })
+            .count();
+        assert_eq!(alive, 1);
+
+        for object in objects {
+            unsafe { drop(Box::from_raw(object)) };
+        }
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::Collector;
+    use crate::sync::{AtomicBool, Ordering};
+    use loom::sync::atomic::AtomicPtr;
+    use loom::sync::Arc;
+
+    struct Object {
+        alive: AtomicBool,
+    }
+
+    // A reader loads the current object while a writer replaces it and tries to free the old one.
+    // In no interleaving is the object freed while the reader that loaded it is still pinned.
+    #[test]
+    fn no_free_while_pinned() {
+        loom::model(|| {
+            let collector = Arc::new(Collector::new(2));
+            let first = Arc::new(Object {
+                alive: AtomicBool::new(true),
+            });
+            let second = Arc::new(Object {
+                alive: AtomicBool::new(true),
+            });
+            let current = Arc::new(AtomicPtr::new(Arc::as_ptr(&first) as *mut Object));
+
+            let reader = {
+                let (collector, current) = (collector.clone(), current.clone());
+                loom::thread::spawn(move || {
+                    let guard = collector.pin();
+                    let object = unsafe { &*current.load(Ordering::SeqCst) };
+                    assert!(object.alive.load(Ordering::SeqCst));
+                    assert!(object.alive.load(Ordering::SeqCst));
+                    drop(guard);
+                })
+            };
+
+            let old = current.swap(Arc::as_ptr(&second) as *mut Object, Ordering::SeqCst);
+            let retired = collector.epoch();
+            for _ in 0..3 {
+                if collector.is_safe(retired) {
+                    unsafe { (*old).alive.store(false, Ordering::SeqCst) };
+                    break;
+                }
+            }
+
+            reader.join().unwrap();
+        });
+    }
+
+    // A pinned reader holds the epoch back: while it is pinned the epoch moves at most once past
+    // the one it saw after pinning, however the pin and the advances interleave.
+    #[test]
+    fn pinned_reader_holds_epoch_back() {
+        loom::model(|| {
+            let collector = Arc::new(Collector::new(2));
+            let reader = {
+                let collector = collector.clone();
+                loom::thread::spawn(move || {
+                    let guard = collector.pin();
+                    let seen = collector.epoch.load(Ordering::SeqCst);
+                    loom::thread::yield_now();
+                    assert!(collector.epoch.load(Ordering::SeqCst) <= seen + 1);
+                    drop(guard);
+                })
+            };
+            for _ in 0..3 {
+                collector.try_advance();
+            }
+            reader.join().unwrap();
+        });
+    }
+}
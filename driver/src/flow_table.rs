@@ -0,0 +1,179 @@
+//! Connection tracking for the protocols without ports: ICMP, GRE, ESP and the rest.
+//!
+//! The port tables of the cache only hold TCP and UDP. Packets of the other protocols used to skip
+//! the cache, so every one of them went to user space with no verdict memory and no bandwidth
+//! accounting. They are tracked here instead, as flows keyed by protocol and addresses. ICMP echo
+//! requests and replies also carry their identifier in both ports of the key, so every ping session
+//! is its own flow; other ICMP messages and the other protocols have 0 there.
+//!
+//! A flow is a regular connection with the usual verdicts. It is added by the packet layer on its
+//! first packet, since there is no ALE classification for these protocols, and it never ends on
+//! its own: it is reported as ended once it is idle (see `cache_timeouts.rs`).
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use smoltcp::wire::IpProtocol;
+
+use crate::connection::{Connection, Key};
+
+// ICMP echo message types.
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+/// Bytes of the transport header `icmp_echo_id` needs: type, code, checksum and identifier.
+pub const ICMP_ECHO_ID_LEN: usize = 6;
+
+/// Reports whether connections of the protocol are kept in the port tables.
+pub fn has_ports(protocol: IpProtocol) -> bool {
+    matches!(protocol, IpProtocol::Tcp | IpProtocol::Udp)
+}
+
+/// Returns the identifier of an ICMP or ICMPv6 echo request or reply, from the start of its
+/// transport header. None for every other message and protocol.
+pub fn icmp_echo_id(protocol: IpProtocol, header: &[u8]) -> Option<u16> {
+    let echo = match protocol {
+        IpProtocol::Icmp => matches!(header.first(), Some(&(ICMP_ECHO_REQUEST | ICMP_ECHO_REPLY))),
+        IpProtocol::Icmpv6 => {
+            matches!(
+                header.first(),
+                Some(&(ICMPV6_ECHO_REQUEST | ICMPV6_ECHO_REPLY))
+            )
+        }
+        _ => false,
+    };
+    if !echo || header.len() < ICMP_ECHO_ID_LEN {
+        return None;
+    }
+    return Some(u16::from_be_bytes([header[4], header[5]]));
+}
+
+pub struct FlowTable<T: Connection> {
+    flows: BTreeMap<Key, Arc<T>>,
+}
+
+impl<T: Connection> FlowTable<T> {
+    pub const fn new() -> Self {
+        Self {
+            flows: BTreeMap::new(),
+        }
+    }
+
+    pub fn get(&self, key: &Key) -> Option<&Arc<T>> {
+        self.flows.get(key)
+    }
+
+    /// Adds the flow, unless there is one with its key already. Returns the flow that is in the
+    /// table, and true if that is `new`.
+    pub fn insert(&mut self, new: Arc<T>) -> (Arc<T>, bool) {
+        let key = new.get_key();
+        if let Some(existing) = self.flows.get(&key) {
+            return (existing.clone(), false);
+        }
+        self.flows.insert(key, new.clone());
+        return (new, true);
+    }
+
+    pub fn remove(&mut self, key: &Key) -> Option<Arc<T>> {
+        self.flows.remove(key)
+    }
+
+    /// Keeps only the flows for which `keep` returns true.
+    pub fn retain(&mut self, mut keep: impl FnMut(&Arc<T>) -> bool) {
+        self.flows.retain(|_, flow| keep(flow));
+    }
+
+    /// Removes every flow and returns them.
+    pub fn take_all(&mut self) -> Vec<Arc<T>> {
+        core::mem::take(&mut self.flows).into_values().collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<T>> {
+        self.flows.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{has_ports, icmp_echo_id, FlowTable};
+    use crate::connection::{Connection, ConnectionV4, Direction, Key, Verdict};
+    use alloc::sync::Arc;
+    use smoltcp::wire::{IpAddress, IpProtocol, Ipv4Address};
+
+    fn key(protocol: IpProtocol, remote: u8, id: u16) -> Key {
+        Key {
+            protocol,
+            local_address: IpAddress::Ipv4(Ipv4Address::new(10, 0, 0, 2)),
+            local_port: id,
+            remote_address: IpAddress::Ipv4(Ipv4Address::new(1, 1, 1, remote)),
+            remote_port: id,
+        }
+    }
+
+    fn flow(key: &Key) -> Arc<ConnectionV4> {
+        Arc::new(ConnectionV4::from_key(key, 0, Direction::Outbound).unwrap())
+    }
+
+    #[test]
+    fn echo_id_only_for_echo_messages() {
+        // Type, code, checksum, identifier 0x1234, sequence.
+        let request = [8, 0, 0xab, 0xcd, 0x12, 0x34, 0, 1];
+        let reply = [0, 0, 0xab, 0xcd, 0x12, 0x34, 0, 1];
+        let unreachable = [3, 1, 0xab, 0xcd, 0, 0, 0, 0];
+        assert_eq!(icmp_echo_id(IpProtocol::Icmp, &request), Some(0x1234));
+        assert_eq!(icmp_echo_id(IpProtocol::Icmp, &reply), Some(0x1234));
+        assert_eq!(icmp_echo_id(IpProtocol::Icmp, &unreachable), None);
+        assert_eq!(
+            icmp_echo_id(IpProtocol::Icmpv6, &[128, 0, 0, 0, 0x56, 0x78]),
+            Some(0x5678)
+        );
+        // An ICMPv4 type number means nothing to ICMPv6, and the reverse.
+        assert_eq!(icmp_echo_id(IpProtocol::Icmpv6, &request), None);
+        assert_eq!(
+            icmp_echo_id(IpProtocol::Icmp, &[128, 0, 0, 0, 0x56, 0x78]),
+            None
+        );
+        // Truncated, and not ICMP at all.
+        assert_eq!(icmp_echo_id(IpProtocol::Icmp, &request[..5]), None);
+        assert_eq!(icmp_echo_id(IpProtocol::Unknown(47), &request), None);
+
+        assert!(has_ports(IpProtocol::Tcp) && has_ports(IpProtocol::Udp));
+        assert!(!has_ports(IpProtocol::Icmp));
+    }
+
+    #[test]
+    fn flows_are_keyed_by_protocol_addresses_and_id() {
+        let mut table: FlowTable<ConnectionV4> = FlowTable::new();
+        let ping = key(IpProtocol::Icmp, 1, 7);
+        let (first, inserted) = table.insert(flow(&ping));
+        assert!(inserted);
+
+        // A second packet of the same flow finds the first one, verdict included.
+        first.set_verdict(Verdict::PermanentBlock);
+        let (again, inserted) = table.insert(flow(&ping));
+        assert!(!inserted);
+        assert!(Arc::ptr_eq(&first, &again));
+        assert!(matches!(
+            table.get(&ping).unwrap().get_verdict(),
+            Verdict::PermanentBlock
+        ));
+
+        // Another ping session, another host, another protocol: separate flows.
+        for other in [
+            key(IpProtocol::Icmp, 1, 8),
+            key(IpProtocol::Icmp, 2, 7),
+            key(IpProtocol::Unknown(47), 1, 0),
+        ] {
+            assert!(table.get(&other).is_none());
+            assert!(table.insert(flow(&other)).1);
+        }
+        assert_eq!(table.iter().count(), 4);
+
+        table.retain(|flow| flow.get_remote_address() != ping.remote_address);
+        assert_eq!(table.iter().count(), 1);
+        assert!(table.remove(&key(IpProtocol::Icmp, 2, 7)).is_some());
+        assert_eq!(table.take_all().len(), 0);
+    }
+}
@@ -1,7 +1,7 @@
 use core::mem;
 
 use alloc::collections::VecDeque;
-use protocol::info::Info;
+use protocol::info::{ConnectionMetadata, Info};
 use smoltcp::wire::{IpAddress, IpProtocol};
 use wdk::rw_spin_lock::Mutex;
 
@@ -36,10 +36,13 @@ impl IdCache {
         process_id: u64,
         direction: Direction,
         ale_layer: bool,
+        metadata: &ConnectionMetadata,
     ) -> Option<Info> {
         let mut values = self.values.write_lock();
         let id = self.next_id;
-        let info = build_info(&value.0, id, process_id, direction, &value.1, ale_layer);
+        let info = build_info(
+            &value.0, id, process_id, direction, &value.1, ale_layer, metadata,
+        );
         values.push_back(Entry { value, id });
         self.next_id = self.next_id.wrapping_add(1); // Assuming this will not overflow.
 
@@ -98,7 +101,12 @@ fn get_payload<'a>(packet: &'a Packet) -> Option<&'a [u8]> {
 /// connection and its process id, leaving the real packet to be sent and reinjected by the
 /// packet layer. This is the case for inbound loopback and for reauthorized outbound
 /// connections (which cannot be pended).
-pub fn build_info_only(key: &Key, process_id: u64, direction: Direction) -> Option<Info> {
+pub fn build_info_only(
+    key: &Key,
+    process_id: u64,
+    direction: Direction,
+    metadata: &ConnectionMetadata,
+) -> Option<Info> {
     let (local_port, remote_port) = match key.protocol {
         IpProtocol::Tcp | IpProtocol::Udp => (key.local_port, key.remote_port),
         _ => (0, 0),
@@ -117,6 +125,7 @@ pub fn build_info_only(key: &Key, process_id: u64, direction: Direction) -> Opti
                 remote_port,
                 4, // Transport layer
                 &[],
+                metadata,
             ))
         }
         (IpAddress::Ipv4(local_ip), IpAddress::Ipv4(remote_ip)) => {
@@ -131,6 +140,7 @@ pub fn build_info_only(key: &Key, process_id: u64, direction: Direction) -> Opti
                 remote_port,
                 4, // Transport layer
                 &[],
+                metadata,
             ))
         }
         _ => None,
@@ -144,6 +154,7 @@ pub fn build_info(
     direction: Direction,
     packet: &Packet,
     ale_layer: bool,
+    metadata: &ConnectionMetadata,
 ) -> Option<Info> {
     let (local_port, remote_port) = match key.protocol {
         IpProtocol::Tcp | IpProtocol::Udp => (key.local_port, key.remote_port),
@@ -174,6 +185,7 @@ pub fn build_info(
                 remote_port,
                 payload_layer,
                 payload,
+                metadata,
             ))
         }
         (IpAddress::Ipv4(local_ip), IpAddress::Ipv4(remote_ip)) => {
@@ -188,6 +200,7 @@ pub fn build_info(
                 remote_port,
                 payload_layer,
                 payload,
+                metadata,
             ))
         }
         _ => None,
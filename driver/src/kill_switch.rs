@@ -0,0 +1,161 @@
+//! Kill switch tied to the tunnel interface.
+//!
+//! While the kill switch is on, traffic may only use the tunnel interface, stay on this machine
+//! (loopback) or go to one of the allowed remote prefixes (usually the LAN). Everything else is
+//! blocked by the ALE and packet callouts on their own, without asking user space: the point of a
+//! kill switch is that nothing leaks when the user space service is gone, so the decision can not
+//! depend on it. Trusted processes are not exempt either: the VPN client reaches its server
+//! through an allowed prefix, like any other process.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use smoltcp::wire::{IpAddress, IpCidr, Ipv4Cidr, Ipv6Cidr};
+
+pub struct KillSwitch {
+    enabled: bool,
+    tunnel_interface_index: u32,
+    allowed_prefixes: Vec<IpCidr>,
+}
+
+impl KillSwitch {
+    pub const fn new() -> Self {
+        Self {
+            enabled: false,
+            tunnel_interface_index: 0,
+            allowed_prefixes: Vec::new(),
+        }
+    }
+
+    /// Turns the kill switch on, with `tunnel_interface_index` as the only interface traffic may
+    /// leave on. The allowed prefixes are kept.
+    pub fn enable(&mut self, tunnel_interface_index: u32) {
+        self.enabled = true;
+        self.tunnel_interface_index = tunnel_interface_index;
+    }
+
+    pub fn disable(&mut self) {
+        self.enabled = false;
+    }
+
+    /// Allows the traffic to `address`/`prefix_length`. Adding a prefix that is already allowed
+    /// does nothing.
+    pub fn allow_prefix(&mut self, address: IpAddress, prefix_length: u8) -> Result<(), String> {
+        let prefix = match address {
+            IpAddress::Ipv4(address) if prefix_length <= 32 => {
+                IpCidr::Ipv4(Ipv4Cidr::new(address, prefix_length))
+            }
+            IpAddress::Ipv6(address) if prefix_length <= 128 => {
+                IpCidr::Ipv6(Ipv6Cidr::new(address, prefix_length))
+            }
+            _ => {
+                return Err(alloc::format!(
+                    "invalid prefix length {} for {}",
+                    prefix_length,
+                    address
+                ))
+            }
+        };
+        if !self.allowed_prefixes.contains(&prefix) {
+            self.allowed_prefixes.push(prefix);
+        }
+        return Ok(());
+    }
+
+    pub fn clear_prefixes(&mut self) {
+        self.allowed_prefixes.clear();
+    }
+
+    /// Reports whether traffic between `local` and `remote` on `interface_index` may pass.
+    ///
+    /// Always true while the kill switch is off. Loopback traffic never leaves the machine, so it
+    /// is always allowed; an interface index of 0 (unknown) is never the tunnel.
+    pub fn allows(&self, interface_index: u32, local: IpAddress, remote: IpAddress) -> bool {
+        if !self.enabled {
+            return true;
+        }
+        if interface_index != 0 && interface_index == self.tunnel_interface_index {
+            return true;
+        }
+        if is_loopback(local) || is_loopback(remote) {
+            return true;
+        }
+        return self
+            .allowed_prefixes
+            .iter()
+            .any(|prefix| prefix.contains_addr(&remote));
+    }
+}
+
+fn is_loopback(address: IpAddress) -> bool {
+    match address {
+        IpAddress::Ipv4(address) => address.is_loopback(),
+        IpAddress::Ipv6(address) => address.is_loopback(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KillSwitch;
+    use smoltcp::wire::{IpAddress, Ipv4Address, Ipv6Address};
+
+    const TUNNEL: u32 = 12;
+    const PHYSICAL: u32 = 4;
+    const LOCAL_V4: IpAddress = IpAddress::Ipv4(Ipv4Address::new(192, 168, 1, 20));
+    const LAN_V4: IpAddress = IpAddress::Ipv4(Ipv4Address::new(192, 168, 1, 1));
+    const INTERNET_V4: IpAddress = IpAddress::Ipv4(Ipv4Address::new(1, 1, 1, 1));
+    const LOCAL_V6: IpAddress = IpAddress::Ipv6(Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 2));
+    const INTERNET_V6: IpAddress =
+        IpAddress::Ipv6(Ipv6Address::new(0x2606, 0x4700, 0, 0, 0, 0, 0, 0x1111));
+
+    #[test]
+    fn disabled_allows_everything() {
+        let kill_switch = KillSwitch::new();
+        assert!(kill_switch.allows(PHYSICAL, LOCAL_V4, INTERNET_V4));
+    }
+
+    #[test]
+    fn enabled_allows_only_the_tunnel_interface() {
+        let mut kill_switch = KillSwitch::new();
+        kill_switch.enable(TUNNEL);
+        assert!(kill_switch.allows(TUNNEL, LOCAL_V4, INTERNET_V4));
+        assert!(kill_switch.allows(TUNNEL, LOCAL_V6, INTERNET_V6));
+        assert!(!kill_switch.allows(PHYSICAL, LOCAL_V4, INTERNET_V4));
+        assert!(!kill_switch.allows(PHYSICAL, LOCAL_V6, INTERNET_V6));
+        assert!(!kill_switch.allows(0, LOCAL_V4, INTERNET_V4));
+
+        kill_switch.disable();
+        assert!(kill_switch.allows(PHYSICAL, LOCAL_V4, INTERNET_V4));
+    }
+
+    #[test]
+    fn enabled_allows_loopback() {
+        let mut kill_switch = KillSwitch::new();
+        kill_switch.enable(TUNNEL);
+        let loopback_v4 = IpAddress::Ipv4(Ipv4Address::LOCALHOST);
+        let loopback_v6 = IpAddress::Ipv6(Ipv6Address::LOCALHOST);
+        assert!(kill_switch.allows(1, loopback_v4, loopback_v4));
+        assert!(kill_switch.allows(1, loopback_v6, loopback_v6));
+    }
+
+    #[test]
+    fn enabled_allows_the_allowed_prefixes() {
+        let mut kill_switch = KillSwitch::new();
+        kill_switch.enable(TUNNEL);
+        kill_switch
+            .allow_prefix(IpAddress::Ipv4(Ipv4Address::new(192, 168, 1, 0)), 24)
+            .unwrap();
+        assert!(kill_switch.allows(PHYSICAL, LOCAL_V4, LAN_V4));
+        assert!(!kill_switch.allows(PHYSICAL, LOCAL_V4, INTERNET_V4));
+
+        kill_switch.clear_prefixes();
+        assert!(!kill_switch.allows(PHYSICAL, LOCAL_V4, LAN_V4));
+    }
+
+    #[test]
+    fn invalid_prefix_length_is_rejected() {
+        let mut kill_switch = KillSwitch::new();
+        assert!(kill_switch.allow_prefix(LAN_V4, 33).is_err());
+        assert!(kill_switch.allow_prefix(INTERNET_V6, 129).is_err());
+        assert!(kill_switch.allow_prefix(INTERNET_V6, 128).is_ok());
+    }
+}
@@ -6,19 +6,38 @@ extern crate alloc;
 
 mod ale_callouts;
 mod array_holder;
+mod bind_policy;
+mod cache_limit;
+mod cache_timeouts;
 pub mod mpsc_queue;
 mod callouts;
 mod common;
 mod connection;
 mod connection_cache;
+mod connection_filter;
 mod rcu_port;
 mod device;
+mod egress_policy;
 mod entry;
+mod epoch;
 mod filter_reset_queue;
+mod flow_table;
 mod id_cache;
+mod kill_switch;
 pub mod logger;
 mod packet_callouts;
 mod packet_util;
+mod port_bucket;
+mod port_table;
+mod process_index;
+mod process_path;
+mod process_table;
+mod sync;
+mod tcp_reset;
+mod tcp_state;
+mod trusted_processes;
+mod user_sid;
+mod verdict_state;
 
 #[cfg(not(test))]
 use wdk::allocator::WindowsAllocator;
@@ -1,50 +1,77 @@
 use alloc::string::String;
 use alloc::sync::Arc;
+use protocol::info::ConnectionMetadata;
 use smoltcp::wire::{IPV4_HEADER_LEN, IPV6_HEADER_LEN};
 use wdk::filter_engine::callout_data::CalloutData;
 use wdk::filter_engine::layer;
 use wdk::filter_engine::net_buffer::{NetBuffer, NetBufferListIter};
 use wdk::filter_engine::packet::InjectInfo;
 
-use crate::connection::{Connection, ConnectionV4, ConnectionV6, Direction, Key, Verdict};
+use crate::connection::{
+    Connection, ConnectionDetails, ConnectionV4, ConnectionV6, Direction, Key, Verdict,
+};
 use crate::connection_cache::ConnectionCache;
 use crate::device::{Device, Packet};
+use crate::id_cache;
 use crate::packet_util::{
-    get_key_from_nb_v4, get_key_from_nb_v6, recalc_header_checksums, Redirect,
+    get_key_from_nb_v4, get_key_from_nb_v6, needs_egress, recalc_header_checksums, Egress, Redirect,
 };
+use crate::tcp_reset::TcpSegment;
+use crate::tcp_state::TcpTransition;
 use crate::{err, warn};
 
 trait IpVersion: Connection + Sized {
     const HEADER_LEN: u32;
     const IS_IPV6: bool;
-    fn get_key_from_nb(nb: &NetBuffer, direction: Direction) -> Result<Key, String>;
+    fn get_key_from_nb(
+        nb: &NetBuffer,
+        direction: Direction,
+    ) -> Result<(Key, Option<TcpSegment>), String>;
     fn get_connection(cache: &ConnectionCache, key: &Key) -> Option<Arc<Self>>;
+    // Adds the flow of a protocol without ports on its first packet (see flow_table.rs).
+    fn add_flow(cache: &ConnectionCache, key: &Key, direction: Direction) -> Option<Arc<Self>>;
 }
 
 impl IpVersion for ConnectionV4 {
     const HEADER_LEN: u32 = IPV4_HEADER_LEN as u32;
     const IS_IPV6: bool = false;
 
-    fn get_key_from_nb(nb: &NetBuffer, direction: Direction) -> Result<Key, String> {
+    fn get_key_from_nb(
+        nb: &NetBuffer,
+        direction: Direction,
+    ) -> Result<(Key, Option<TcpSegment>), String> {
         get_key_from_nb_v4(nb, direction)
     }
 
     fn get_connection(cache: &ConnectionCache, key: &Key) -> Option<Arc<Self>> {
         cache.get_connection_v4(key)
     }
+
+    fn add_flow(cache: &ConnectionCache, key: &Key, direction: Direction) -> Option<Arc<Self>> {
+        cache.add_v4(Self::from_key(key, 0, direction).ok()?);
+        cache.get_connection_v4(key)
+    }
 }
 
 impl IpVersion for ConnectionV6 {
     const HEADER_LEN: u32 = IPV6_HEADER_LEN as u32;
     const IS_IPV6: bool = true;
 
-    fn get_key_from_nb(nb: &NetBuffer, direction: Direction) -> Result<Key, String> {
+    fn get_key_from_nb(
+        nb: &NetBuffer,
+        direction: Direction,
+    ) -> Result<(Key, Option<TcpSegment>), String> {
         get_key_from_nb_v6(nb, direction)
     }
 
     fn get_connection(cache: &ConnectionCache, key: &Key) -> Option<Arc<Self>> {
         cache.get_connection_v6(key)
     }
+
+    fn add_flow(cache: &ConnectionCache, key: &Key, direction: Direction) -> Option<Arc<Self>> {
+        cache.add_v6(Self::from_key(key, 0, direction).ok()?);
+        cache.get_connection_v6(key)
+    }
 }
 
 // -------- IP packet layers
@@ -177,8 +204,8 @@ fn ip_packet_layer<T: IpVersion>(
             }
 
             // Get key from packet.
-            let key = match T::get_key_from_nb(&nb, direction) {
-                Ok(key) => key,
+            let (key, segment) = match T::get_key_from_nb(&nb, direction) {
+                Ok(parsed) => parsed,
                 Err(err) => {
                     warn!("failed to get key from net buffer: {}", err);
                     return;
@@ -187,6 +214,8 @@ fn ip_packet_layer<T: IpVersion>(
 
             let mut is_tmp_verdict = false;
             let mut process_id = 0;
+            let mut connection_id = 0;
+            let mut details = ConnectionDetails::default();
 
             let packet_size = nb.get_data_length() as u64;
 
@@ -200,16 +229,99 @@ fn ip_packet_layer<T: IpVersion>(
                 if let Some(conn) = T::get_connection(&device.connection_cache, &key) {
                     // Connection object found.
 
+                    // Outbound packets of a connection with an egress route are classified on the
+                    // interface the stack picked, but leave on the route interface.
+                    let egress_interface_index = match direction {
+                        Direction::Outbound => conn
+                            .get_egress()
+                            .filter(|_| needs_egress(conn.as_ref(), &key, direction))
+                            .map(|egress| egress.route.interface_index),
+                        Direction::Inbound => None,
+                    };
+                    if device.kill_switch_blocks(
+                        egress_interface_index.unwrap_or(interface_index),
+                        key.local_address,
+                        key.remote_address,
+                    ) {
+                        data.action_block();
+                        continue;
+                    }
+
                     conn.update_bandwidth_data(packet_size, direction);
+                    let mut out_of_state = false;
+                    if let Some(segment) = &segment {
+                        let sequence = conn.get_tcp_sequence();
+                        match conn.get_tcp_state().advance(direction, segment, sequence) {
+                            TcpTransition::Changed(state) => {
+                                device.tcp_state_changed(conn.as_ref(), state)
+                            }
+                            TcpTransition::Same => {}
+                            TcpTransition::OutOfState => out_of_state = true,
+                        }
+                        // A segment that does not fit could be a blind injection, keep it out.
+                        if !out_of_state {
+                            sequence.record(direction, segment);
+                        }
+                    }
                     process_id = conn.get_process_id();
 
+                    // Monitor-only mode: counted, but never held or changed.
+                    if device.is_monitor_only() {
+                        data.action_permit();
+                        continue;
+                    }
+
+                    // Strict mode: out of state TCP packets are dropped (see tcp_state.rs).
+                    if out_of_state && device.is_tcp_strict() {
+                        data.block_and_absorb();
+                        continue;
+                    }
+
                     // Check if there is action for this connection.
                     match conn.get_verdict() {
                         Verdict::Undecided | Verdict::Accept | Verdict::Block | Verdict::Drop => {
                             // Temporary verdicts have special paths.
-                            is_tmp_verdict = true
+                            is_tmp_verdict = true;
+                            connection_id = conn.get_id();
+                            details = conn.get_details().clone();
+                        }
+                        Verdict::PermanentAccept => {
+                            if let Some(egress) = conn
+                                .get_egress()
+                                .filter(|_| needs_egress(conn.as_ref(), &key, direction))
+                            {
+                                match clone_packet(
+                                    device,
+                                    &nb,
+                                    direction,
+                                    T::IS_IPV6,
+                                    key.is_loopback(),
+                                    interface_index,
+                                    sub_interface_index,
+                                    compartment_id,
+                                ) {
+                                    Ok(mut packet) => {
+                                        match packet.egress(egress, conn.get_local_address()) {
+                                            Ok(()) => {
+                                                if let Err(err) =
+                                                    device.inject_packet(packet, false)
+                                                {
+                                                    err!("failed to inject packet: {}", err);
+                                                }
+                                            }
+                                            Err(err) => err!("failed to move packet: {}", err),
+                                        }
+                                    }
+                                    Err(err) => err!("failed to clone packet: {}", err),
+                                }
+
+                                // The original packet is on the wrong interface (or has the wrong
+                                // address). Block it even if injection failed.
+                                data.block_and_absorb();
+                                continue;
+                            }
+                            data.action_permit()
                         }
-                        Verdict::PermanentAccept => data.action_permit(),
                         Verdict::PermanentBlock => data.action_block(),
                         Verdict::Undeterminable | Verdict::PermanentDrop | Verdict::Failed => {
                             data.block_and_absorb()
@@ -245,6 +357,10 @@ fn ip_packet_layer<T: IpVersion>(
                     // TCP and UDP always need to go through ALE layer first.
                     if matches!(direction, Direction::Inbound) {
                         // If it's an inbound packet and the connection is not found, continue to ALE layer
+                        // (which also applies the kill switch).
+                        data.action_permit();
+                        return;
+                    } else if device.is_monitor_only() {
                         data.action_permit();
                         return;
                     } else {
@@ -254,7 +370,59 @@ fn ip_packet_layer<T: IpVersion>(
                     }
                 }
             } else {
-                // Every other protocol treat as a tmp verdict.
+                // No ALE classification for these protocols, the kill switch is applied to every
+                // packet.
+                if device.kill_switch_blocks(interface_index, key.local_address, key.remote_address)
+                {
+                    data.action_block();
+                    continue;
+                }
+                // The flow is added on its first packet.
+                let flow = match T::get_connection(&device.connection_cache, &key) {
+                    Some(flow) => Some(flow),
+                    None => {
+                        let flow = T::add_flow(&device.connection_cache, &key, direction);
+                        device.evict_if_full();
+                        flow
+                    }
+                };
+                if let Some(flow) = &flow {
+                    flow.update_bandwidth_data(packet_size, direction);
+                }
+                // Monitor-only mode: report the packet without holding it.
+                if device.is_monitor_only() {
+                    if let Some(info) = id_cache::build_info_only(
+                        &key,
+                        0,
+                        direction,
+                        &ConnectionMetadata::default(),
+                    ) {
+                        let _ = device.event_queue.push(info);
+                    }
+                    data.action_permit();
+                    continue;
+                }
+                // Permanent verdicts are applied here, everything else goes to user space.
+                if let Some(flow) = flow {
+                    match flow.get_verdict() {
+                        Verdict::PermanentAccept => {
+                            data.action_permit();
+                            continue;
+                        }
+                        Verdict::PermanentBlock => {
+                            data.action_block();
+                            continue;
+                        }
+                        Verdict::Undeterminable | Verdict::PermanentDrop | Verdict::Failed => {
+                            data.block_and_absorb();
+                            continue;
+                        }
+                        _ => {
+                            connection_id = flow.get_id();
+                            details = flow.get_details().clone();
+                        }
+                    }
+                }
                 is_tmp_verdict = true;
             }
 
@@ -280,9 +448,13 @@ fn ip_packet_layer<T: IpVersion>(
                     }
                 };
 
-                let info = device
-                    .packet_cache
-                    .push((key, packet), process_id, direction, false);
+                let info = device.packet_cache.push(
+                    (key, packet),
+                    process_id,
+                    direction,
+                    false,
+                    &details.as_metadata(connection_id),
+                );
                 // Send to Userspace
                 if let Some(info) = info {
                     let _ = device.event_queue.push(info);
@@ -319,6 +491,7 @@ fn clone_packet(
             interface_index,
             sub_interface_index,
             compartment_id,
+            forward: false,
         },
     ))
 }
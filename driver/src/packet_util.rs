@@ -1,13 +1,16 @@
 use alloc::string::{String, ToString};
 use smoltcp::wire::{
     IpAddress, IpProtocol, Ipv4Address, Ipv4Packet, Ipv6Address, Ipv6Packet, TcpPacket, UdpPacket,
-    IPV4_HEADER_LEN, IPV6_HEADER_LEN,
+    IPV4_HEADER_LEN, IPV6_HEADER_LEN, TCP_HEADER_LEN,
 };
 use wdk::filter_engine::net_buffer::NetBuffer;
 
 use crate::device::Packet;
+use crate::egress_policy::ConnectionEgress;
+use crate::flow_table;
+use crate::tcp_reset::TcpSegment;
 use crate::{
-    connection::{Direction, Key, RedirectInfo},
+    connection::{Connection, Direction, Key, RedirectInfo},
     dbg, err,
 };
 
@@ -58,6 +61,87 @@ impl Redirect for Packet {
     }
 }
 
+/// `Egress` moves the packets of a connection with an egress route (see `egress_policy`).
+pub trait Egress {
+    /// Outbound: rewrites the local address to the route source address and sets the packet up to
+    /// be injected on the route interface. Inbound (return traffic addressed to the route source
+    /// address): rewrites the local address back to `local_address`, the address of the
+    /// connection, and sets the packet up to be injected on the interface the connection was
+    /// originally routed to.
+    fn egress(&mut self, egress: &ConnectionEgress, local_address: IpAddress)
+        -> Result<(), String>;
+}
+
+impl Egress for Packet {
+    fn egress(
+        &mut self,
+        egress: &ConnectionEgress,
+        local_address: IpAddress,
+    ) -> Result<(), String> {
+        let Packet::PacketLayer(nbl, inject_info) = self else {
+            // The ALE layer packet is injected before the packet layer, which will move it.
+            return Ok(());
+        };
+        let Some(data) = nbl.get_data_mut() else {
+            return Err("trying to move immutable NBL".to_string());
+        };
+
+        if inject_info.inbound {
+            set_local_address(data, local_address, true);
+            if let Some((interface_index, sub_interface_index)) = egress.get_origin() {
+                inject_info.interface_index = interface_index;
+                inject_info.sub_interface_index = sub_interface_index;
+            }
+        } else {
+            set_local_address(data, egress.route.source_address, false);
+            egress.set_origin(inject_info.interface_index, inject_info.sub_interface_index);
+            inject_info.interface_index = egress.route.interface_index;
+            inject_info.sub_interface_index = egress.route.sub_interface_index;
+            inject_info.forward = true;
+        }
+        recalc_header_checksums(data, inject_info.ipv6);
+        return Ok(());
+    }
+}
+
+/// Reports whether a packet of the connection has to be moved by its egress route: every outbound
+/// packet, and the inbound packets addressed to the route source address rather than to the
+/// connection.
+pub fn needs_egress<T: Connection>(conn: &T, key: &Key, direction: Direction) -> bool {
+    if conn.get_egress().is_none() || key.is_loopback() {
+        return false;
+    }
+    match direction {
+        Direction::Outbound => true,
+        Direction::Inbound => !conn.equals(key),
+    }
+}
+
+// Sets the local address of the packet: the destination of an inbound packet, the source of an
+// outbound one. Checksums are left to the caller.
+fn set_local_address(packet: &mut [u8], address: IpAddress, inbound: bool) {
+    match address {
+        IpAddress::Ipv4(address) => {
+            if let Ok(mut ip_packet) = Ipv4Packet::new_checked(packet) {
+                if inbound {
+                    ip_packet.set_dst_addr(address);
+                } else {
+                    ip_packet.set_src_addr(address);
+                }
+            }
+        }
+        IpAddress::Ipv6(address) => {
+            if let Ok(mut ip_packet) = Ipv6Packet::new_checked(packet) {
+                if inbound {
+                    ip_packet.set_dst_addr(address);
+                } else {
+                    ip_packet.set_src_addr(address);
+                }
+            }
+        }
+    }
+}
+
 /// Redirects an outbound packet to a specified remote address and port.
 ///
 /// # Arguments
@@ -298,6 +382,36 @@ fn get_ports(packet: &[u8], protocol: smoltcp::wire::IpProtocol) -> (u16, u16) {
     }
 }
 
+/// Returns the ICMP echo identifier of the packet, which goes in both ports of its key (see
+/// `flow_table.rs`). 0 for other messages and protocols, and for a packet too short to carry one.
+fn get_echo_id(nb: &NetBuffer, headers: &mut [u8], l4_offset: usize, protocol: IpProtocol) -> u16 {
+    if !matches!(protocol, IpProtocol::Icmp | IpProtocol::Icmpv6) {
+        return 0;
+    }
+    let needed = l4_offset + flow_table::ICMP_ECHO_ID_LEN;
+    if read_prefix(nb, headers, needed).is_err() {
+        return 0;
+    }
+    flow_table::icmp_echo_id(protocol, &headers[l4_offset..needed]).unwrap_or(0)
+}
+
+/// Returns the sequence numbers of a TCP segment, tracked to reset the connection with (see
+/// `tcp_reset.rs`). None for other protocols and for a segment too short to carry a TCP header.
+fn get_tcp_segment(
+    nb: &NetBuffer,
+    headers: &mut [u8],
+    l4_offset: usize,
+    protocol: IpProtocol,
+) -> Option<TcpSegment> {
+    if protocol != IpProtocol::Tcp {
+        return None;
+    }
+    let needed = l4_offset + TCP_HEADER_LEN;
+    read_prefix(nb, headers, needed).ok()?;
+    let segment_len = (nb.get_data_length() as usize).checked_sub(l4_offset)?;
+    TcpSegment::parse(&headers[l4_offset..needed], segment_len)
+}
+
 /// Upper bound on an IPv4 header: the fixed 20 bytes plus the 40 bytes of
 /// options that the 4-bit IHL field can express.
 const MAX_IPV4_HEADER_LEN: usize = 60;
@@ -315,10 +429,15 @@ fn read_prefix(nb: &NetBuffer, buffer: &mut [u8], len: usize) -> Result<(), ()>
     nb.read_bytes(&mut buffer[..len])
 }
 
-pub fn get_key_from_nb_v4(nb: &NetBuffer, direction: Direction) -> Result<Key, String> {
+/// Extracts the key of an IPv4 net buffer, and the sequence numbers of a TCP segment.
+pub fn get_key_from_nb_v4(
+    nb: &NetBuffer,
+    direction: Direction,
+) -> Result<(Key, Option<TcpSegment>), String> {
     // Buffer large enough for the largest possible IPv4 header, options included, and the
-    // first 4 transport bytes (source + destination ports).
-    let mut headers = [0u8; MAX_IPV4_HEADER_LEN + 4];
+    // transport bytes read: the TCP header, which is longer than the ports or the ICMP echo
+    // identifier.
+    let mut headers = [0u8; MAX_IPV4_HEADER_LEN + TCP_HEADER_LEN];
 
     // Read the fixed part of the header; it carries the IHL that locates the transport header.
     if read_prefix(nb, &mut headers, IPV4_HEADER_LEN).is_err() {
@@ -350,26 +469,29 @@ pub fn get_key_from_nb_v4(nb: &NetBuffer, direction: Direction) -> Result<Key, S
         }
         get_ports(&headers[header_len..needed], protocol)
     } else {
-        (0, 0)
+        let id = get_echo_id(nb, &mut headers, header_len, protocol);
+        (id, id)
     };
+    let segment = get_tcp_segment(nb, &mut headers, header_len, protocol);
 
     // Build key
-    match direction {
-        Direction::Outbound => Ok(Key {
+    let key = match direction {
+        Direction::Outbound => Key {
             protocol,
             local_address: IpAddress::Ipv4(src_addr),
             local_port: src_port,
             remote_address: IpAddress::Ipv4(dst_addr),
             remote_port: dst_port,
-        }),
-        Direction::Inbound => Ok(Key {
+        },
+        Direction::Inbound => Key {
             protocol,
             local_address: IpAddress::Ipv4(dst_addr),
             local_port: dst_port,
             remote_address: IpAddress::Ipv4(src_addr),
             remote_port: src_port,
-        }),
-    }
+        },
+    };
+    Ok((key, segment))
 }
 
 // NOTE: The IPv6 extension-header parsing below is duplicated in the Linux eBPF
@@ -406,13 +528,19 @@ const MAX_IPV6_EXT_HEADER_LEN: usize = 256;
 ///
 /// # Returns
 ///
-/// * `Ok(Key)` - A key containing the protocol, local and remote addresses and ports.
+/// * `Ok((Key, Option<TcpSegment>))` - A key containing the protocol, local and remote addresses
+///   and ports, and the sequence numbers of a TCP segment.
 /// * `Err(String)` - An error message if the function fails to get net_buffer data
 ///   or the packet carries a malformed extension-header chain.
-pub fn get_key_from_nb_v6(nb: &NetBuffer, direction: Direction) -> Result<Key, String> {
+pub fn get_key_from_nb_v6(
+    nb: &NetBuffer,
+    direction: Direction,
+) -> Result<(Key, Option<TcpSegment>), String> {
     // Buffer large enough for the fixed IPv6 header, the bounded extension-header
-    // chain, and the first 4 transport bytes (source + destination ports).
-    let mut headers = [0u8; IPV6_HEADER_LEN + MAX_IPV6_EXT_HEADERS * MAX_IPV6_EXT_HEADER_LEN + 4];
+    // chain, and the transport bytes read: the TCP header, which is longer than the
+    // ports or the ICMP echo identifier.
+    let mut headers =
+        [0u8; IPV6_HEADER_LEN + MAX_IPV6_EXT_HEADERS * MAX_IPV6_EXT_HEADER_LEN + TCP_HEADER_LEN];
 
     // Read the fixed IPv6 header to get the addresses and the first Next Header.
     if read_prefix(nb, &mut headers, IPV6_HEADER_LEN).is_err() {
@@ -474,26 +602,29 @@ pub fn get_key_from_nb_v6(nb: &NetBuffer, direction: Direction) -> Result<Key, S
         }
         get_ports(&headers[l4_offset..needed], protocol)
     } else {
-        (0, 0)
+        let id = get_echo_id(nb, &mut headers, l4_offset, protocol);
+        (id, id)
     };
+    let segment = get_tcp_segment(nb, &mut headers, l4_offset, protocol);
 
     // Build key
-    match direction {
-        Direction::Outbound => Ok(Key {
+    let key = match direction {
+        Direction::Outbound => Key {
             protocol,
             local_address: IpAddress::Ipv6(src_addr),
             local_port: src_port,
             remote_address: IpAddress::Ipv6(dst_addr),
             remote_port: dst_port,
-        }),
-        Direction::Inbound => Ok(Key {
+        },
+        Direction::Inbound => Key {
             protocol,
             local_address: IpAddress::Ipv6(dst_addr),
             local_port: dst_port,
             remote_address: IpAddress::Ipv6(src_addr),
             remote_port: src_port,
-        }),
-    }
+        },
+    };
+    Ok((key, segment))
 }
 
 // Converts a given key into connection information.
@@ -0,0 +1,254 @@
+//! Layout of the connections of one port.
+//!
+//! Most ports have a handful of connections and a linear scan is the fastest way through them. A
+//! busy server port (443 inbound, a local DNS forwarder) can have thousands, so once a bucket
+//! grows past `SORTED_THRESHOLD` its connections are kept sorted by remote address and port, and
+//! lookups are a binary search. Buckets are still copied on write and published as a whole (see
+//! `rcu_port.rs`); the layout only decides where a new connection goes in the copy.
+//!
+//! The layout follows from the length alone: a bucket longer than the threshold is sorted. Removing
+//! connections keeps the order, so a bucket that shrinks stays valid either way.
+//!
+//! Only lookups got cheaper. Every insert still copies the whole bucket, so filling a busy port is
+//! quadratic; this is left as it is on purpose. Republishing only part of a bucket would need
+//! chunks that are published and reclaimed on their own (`rcu_port.rs`, `epoch.rs`), and the copy
+//! is only pointers.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use smoltcp::wire::IpAddress;
+
+use crate::connection::{Connection, Key, PM_DNS_PORT, PM_SPN_PORT};
+
+pub const SORTED_THRESHOLD: usize = 32;
+
+fn remote_of<T: Connection>(conn: &T) -> (IpAddress, u16) {
+    (conn.get_remote_address(), conn.get_remote_port())
+}
+
+/// The connections that can have the remote end of the key: all of them in a small bucket, the
+/// range with that remote address and port in a sorted one.
+fn with_remote<'a, T: Connection>(conns: &'a [Arc<T>], key: &Key) -> &'a [Arc<T>] {
+    if conns.len() <= SORTED_THRESHOLD {
+        return conns;
+    }
+    let remote = (key.remote_address, key.remote_port);
+    let start = conns.partition_point(|conn| remote_of(conn.as_ref()) < remote);
+    let len = conns[start..].partition_point(|conn| remote_of(conn.as_ref()) == remote);
+    return &conns[start..start + len];
+}
+
+/// Returns the connection equal to the key.
+pub fn find_exact<'a, T: Connection>(conns: &'a [Arc<T>], key: &Key) -> Option<&'a Arc<T>> {
+    with_remote(conns, key).iter().find(|conn| conn.equals(key))
+}
+
+/// Returns the connection with the id. The key is the one of the connection.
+pub fn find_by_id<'a, T: Connection>(
+    conns: &'a [Arc<T>],
+    key: &Key,
+    id: u64,
+) -> Option<&'a Arc<T>> {
+    with_remote(conns, key)
+        .iter()
+        .find(|conn| conn.get_id() == id)
+}
+
+/// Returns the connection the key belongs to: equal to it, seen on its egress interface, or
+/// redirected.
+pub fn find<'a, T: Connection>(conns: &'a [Arc<T>], key: &Key) -> Option<&'a Arc<T>> {
+    let found = with_remote(conns, key)
+        .iter()
+        .find(|conn| conn.equals(key) || conn.egress_equals(key));
+    if found.is_some() {
+        return found;
+    }
+    // A redirected connection is seen with the redirect target as its remote end, so it is not in
+    // the range. Only keys with the port of a redirect target can match one (see
+    // `Connection::redirect_equals`).
+    if key.remote_port == PM_DNS_PORT || key.remote_port == PM_SPN_PORT {
+        return conns.iter().find(|conn| conn.redirect_equals(key));
+    }
+    return None;
+}
+
+/// Returns a copy of the bucket with the connection added, in the layout of the new length.
+pub fn insert<T: Connection>(conns: &[Arc<T>], new: Arc<T>) -> Vec<Arc<T>> {
+    let mut copy = Vec::with_capacity(conns.len() + 1);
+    copy.extend(conns.iter().cloned());
+    if copy.len() < SORTED_THRESHOLD {
+        copy.push(new);
+    } else if copy.len() == SORTED_THRESHOLD {
+        // Crosses the threshold: the old bucket can be in any order.
+        copy.push(new);
+        copy.sort_by_key(|conn| remote_of(conn.as_ref()));
+    } else {
+        let remote = remote_of(new.as_ref());
+        let index = copy.partition_point(|conn| remote_of(conn.as_ref()) <= remote);
+        copy.insert(index, new);
+    }
+    return copy;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, find_by_id, find_exact, insert, SORTED_THRESHOLD};
+    use crate::connection::{Connection, ConnectionV4, Direction, Key, Verdict, PM_DNS_PORT};
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+    use smoltcp::wire::{IpAddress, IpProtocol, Ipv4Address};
+
+    fn key(remote: u32, remote_port: u16) -> Key {
+        Key {
+            protocol: IpProtocol::Udp,
+            local_address: IpAddress::Ipv4(Ipv4Address::new(10, 0, 0, 2)),
+            local_port: 443,
+            remote_address: IpAddress::Ipv4(Ipv4Address::from_bits(remote)),
+            remote_port,
+        }
+    }
+
+    fn connection(remote: u32, remote_port: u16, id: u64) -> Arc<ConnectionV4> {
+        let mut conn =
+            ConnectionV4::from_key(&key(remote, remote_port), 100, Direction::Inbound).unwrap();
+        conn.id = id;
+        Arc::new(conn)
+    }
+
+    // Adds connections with scattered remote ends, the way clients of a server port arrive.
+    fn bucket(len: usize) -> Vec<Arc<ConnectionV4>> {
+        let mut conns: Vec<Arc<ConnectionV4>> = Vec::new();
+        for i in 0..len as u32 {
+            let remote = i.wrapping_mul(2_654_435_761);
+            conns = insert(
+                &conns,
+                connection(remote, 1024 + (i % 5000) as u16, i as u64),
+            );
+        }
+        conns
+    }
+
+    fn is_sorted(conns: &[Arc<ConnectionV4>]) -> bool {
+        conns.windows(2).all(|pair| {
+            (pair[0].get_remote_address(), pair[0].remote_port)
+                <= (pair[1].get_remote_address(), pair[1].remote_port)
+        })
+    }
+
+    #[test]
+    fn small_bucket_keeps_insertion_order() {
+        let conns = bucket(SORTED_THRESHOLD);
+        let ids: Vec<u64> = conns.iter().map(|conn| conn.get_id()).collect();
+        assert_eq!(ids, (0..SORTED_THRESHOLD as u64).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn large_bucket_is_sorted_and_searchable() {
+        for len in [SORTED_THRESHOLD + 1, 100, 1000] {
+            let conns = bucket(len);
+            assert_eq!(conns.len(), len);
+            assert!(is_sorted(&conns));
+            for conn in conns.iter() {
+                let key = conn.get_key();
+                assert_eq!(find_exact(&conns, &key).unwrap().get_id(), conn.get_id());
+                assert_eq!(find(&conns, &key).unwrap().get_id(), conn.get_id());
+                assert!(find_by_id(&conns, &key, conn.get_id()).is_some());
+            }
+            assert!(find_exact(&conns, &key(7, 7)).is_none());
+            assert!(find(&conns, &key(7, 7)).is_none());
+        }
+    }
+
+    #[test]
+    fn same_remote_end_on_different_local_addresses() {
+        let mut conns = bucket(100);
+        let mut other = key(5, 5000);
+        other.local_address = IpAddress::Ipv4(Ipv4Address::new(192, 168, 1, 2));
+        let mut conn = ConnectionV4::from_key(&other, 100, Direction::Inbound).unwrap();
+        conn.id = 1000;
+        conns = insert(&conns, Arc::new(conn));
+        conns = insert(&conns, connection(5, 5000, 1001));
+
+        assert_eq!(find_exact(&conns, &other).unwrap().get_id(), 1000);
+        assert_eq!(find_exact(&conns, &key(5, 5000)).unwrap().get_id(), 1001);
+    }
+
+    #[test]
+    fn redirected_connection_is_found() {
+        let conns = bucket(100);
+        let target = &conns[40];
+        target.set_verdict(Verdict::RedirectNameServer);
+
+        // The redirected packets go to the local resolver.
+        let mut redirected = target.get_key();
+        redirected.remote_address = IpAddress::Ipv4(Ipv4Address::LOCALHOST);
+        redirected.remote_port = PM_DNS_PORT;
+        assert_eq!(find(&conns, &redirected).unwrap().get_id(), target.get_id());
+        assert!(find_exact(&conns, &redirected).is_none());
+    }
+
+    // Lookup time in a bucket of each size, linear scan against the layout. Run with
+    // `cargo test port_bucket_lookup -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn port_bucket_lookup() {
+        use std::time::Instant;
+
+        for len in [8, 32, 100, 1000, 10000] {
+            let conns = bucket(len);
+            let keys: Vec<Key> = conns.iter().map(|conn| conn.get_key()).collect();
+            let rounds = 1_000_000 / len + 1;
+
+            let start = Instant::now();
+            for _ in 0..rounds {
+                for key in keys.iter() {
+                    let found = conns.iter().find(|conn| {
+                        conn.equals(key) || conn.redirect_equals(key) || conn.egress_equals(key)
+                    });
+                    assert!(found.is_some());
+                }
+            }
+            let linear = start.elapsed().as_nanos() / (rounds * len) as u128;
+
+            let start = Instant::now();
+            for _ in 0..rounds {
+                for key in keys.iter() {
+                    assert!(find(&conns, key).is_some());
+                }
+            }
+            let layout = start.elapsed().as_nanos() / (rounds * len) as u128;
+
+            std::println!(
+                "{:>5} connections: linear {:>6} ns, layout {:>4} ns per lookup",
+                len,
+                linear,
+                layout
+            );
+        }
+    }
+
+    // Time to add one connection to a bucket of each size: the whole bucket is copied. Run with
+    // `cargo test port_bucket_insert -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn port_bucket_insert() {
+        use std::time::Instant;
+
+        for len in [8, 32, 100, 1000, 10000] {
+            let conns = bucket(len);
+            let new: Vec<Arc<ConnectionV4>> = (0..64u32)
+                .map(|i| connection(i.wrapping_mul(40_503), 7, len as u64 + i as u64))
+                .collect();
+            let rounds = 1_000_000 / len + 1;
+
+            let start = Instant::now();
+            for round in 0..rounds {
+                let copy = insert(&conns, new[round % new.len()].clone());
+                assert_eq!(copy.len(), len + 1);
+            }
+            let per_insert = start.elapsed().as_nanos() / rounds as u128;
+
+            std::println!("{:>5} connections: {:>7} ns per insert", len, per_insert);
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! Sparse table of the per-port slots of the connection cache.
+//!
+//! A flat array of 65 536 `RCUPort`s per protocol and ip version is 1 MiB each, 4 MiB of non-paged
+//! memory allocated up front, most of it for ports that never see a connection. The table is two-level
+//! instead: 1024 pointers to blocks of 64 ports, and a block is only allocated when the first
+//! connection on one of its ports is added.
+//!
+//! A block is never freed before the table is dropped. A reader that got a port from the table can
+//! keep using it while other ports are added, so `RCUPort::read` stays lock-free: looking up a
+//! port is an atomic load of the block pointer and an index.
+
+use alloc::boxed::Box;
+use core::mem::size_of;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::connection::Connection;
+use crate::rcu_port::RCUPort;
+
+// 0-65535 must be valid ports. 0 is not a valid port number but its kept for future proofing for special cases.
+const PORT_COUNT: usize = u16::MAX as usize + 1;
+const BLOCK_SIZE: usize = 64;
+const BLOCK_COUNT: usize = PORT_COUNT / BLOCK_SIZE;
+
+type Block<T> = [RCUPort<T>; BLOCK_SIZE];
+
+pub(crate) struct PortTable<T: Connection> {
+    // Null until a port of the block is first written.
+    blocks: [AtomicPtr<Block<T>>; BLOCK_COUNT],
+}
+
+// Allocates a zeroed `T` directly on the heap, null if the allocation failed. Only for types that
+// are valid when zeroed:
+//   - AtomicPtr is valid as null
+//   - Mutex<()> / RwSpinLock uses i32 which is valid at 0
+fn alloc_zeroed<T>() -> *mut T {
+    let layout = core::alloc::Layout::new::<T>();
+    unsafe { alloc::alloc::alloc_zeroed(layout) as *mut T }
+}
+
+impl<T: Connection> PortTable<T> {
+    pub(crate) fn new() -> Box<Self> {
+        unsafe { Box::from_raw(alloc_zeroed()) }
+    }
+
+    /// Returns the slot of the port, None if no connection was ever added to its block. Lock free.
+    pub(crate) fn get(&self, port: u16) -> Option<&RCUPort<T>> {
+        let (block, index) = split(port);
+        let block = self.blocks[block].load(Ordering::Acquire);
+        if block.is_null() {
+            return None;
+        }
+        return unsafe { Some(&(*block)[index]) };
+    }
+
+    /// Returns the slot of the port, allocating its block if needed. None if the allocation
+    /// failed.
+    pub(crate) fn get_or_insert(&self, port: u16) -> Option<&RCUPort<T>> {
+        let (block, index) = split(port);
+        let slot = &self.blocks[block];
+        let mut current = slot.load(Ordering::Acquire);
+        if current.is_null() {
+            let new = alloc_zeroed::<Block<T>>();
+            if new.is_null() {
+                return None;
+            }
+            match slot.compare_exchange(ptr::null_mut(), new, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => current = new,
+                Err(existing) => {
+                    // Another writer installed the block first.
+                    unsafe { drop(Box::from_raw(new)) };
+                    current = existing;
+                }
+            }
+        }
+        return unsafe { Some(&(*current)[index]) };
+    }
+
+    /// Iterates over the slots of the allocated blocks.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &RCUPort<T>> {
+        self.blocks.iter().flat_map(|block| {
+            let block = block.load(Ordering::Acquire);
+            let ports: &[RCUPort<T>] = if block.is_null() {
+                &[]
+            } else {
+                unsafe { &*block }
+            };
+            ports.iter()
+        })
+    }
+
+    /// Memory held by the table and its blocks, in bytes.
+    pub(crate) fn allocated_bytes(&self) -> usize {
+        let blocks = self
+            .blocks
+            .iter()
+            .filter(|block| !block.load(Ordering::Relaxed).is_null())
+            .count();
+        size_of::<Self>() + blocks * size_of::<Block<T>>()
+    }
+}
+
+impl<T: Connection> Drop for PortTable<T> {
+    fn drop(&mut self) {
+        for block in self.blocks.iter_mut() {
+            let ptr = *block.get_mut();
+            if !ptr.is_null() {
+                // Frees the connection arrays that are still published.
+                unsafe { drop(Box::from_raw(ptr)) };
+            }
+        }
+    }
+}
+
+fn split(port: u16) -> (usize, usize) {
+    (port as usize / BLOCK_SIZE, port as usize % BLOCK_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PortTable, BLOCK_SIZE, PORT_COUNT};
+    use crate::connection::{ConnectionV4, Direction, Key};
+    use crate::epoch::Collector;
+    use crate::mpsc_queue::MpscQueue;
+    use crate::rcu_port::RCUPort;
+    use alloc::boxed::Box;
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use core::mem::size_of;
+    use smoltcp::wire::{IpAddress, IpProtocol, Ipv4Address};
+
+    fn connection(local_port: u16) -> Arc<ConnectionV4> {
+        let key = Key {
+            protocol: IpProtocol::Tcp,
+            local_address: IpAddress::Ipv4(Ipv4Address::new(10, 0, 0, 2)),
+            local_port,
+            remote_address: IpAddress::Ipv4(Ipv4Address::new(1, 1, 1, 1)),
+            remote_port: 443,
+        };
+        Arc::new(ConnectionV4::from_key(&key, 100, Direction::Outbound).unwrap())
+    }
+
+    #[test]
+    fn blocks_are_allocated_on_demand() {
+        let table: Box<PortTable<ConnectionV4>> = PortTable::new();
+        assert!(table.get(443).is_none());
+        assert_eq!(table.iter().count(), 0);
+        assert_eq!(
+            table.allocated_bytes(),
+            size_of::<PortTable<ConnectionV4>>()
+        );
+
+        let port = table.get_or_insert(50000).unwrap();
+        assert!(port.is_empty());
+        // The whole block is there, the others are not.
+        assert!(table.get(50000 - 50000 % BLOCK_SIZE as u16).is_some());
+        assert!(table.get(443).is_none());
+        assert_eq!(table.iter().count(), BLOCK_SIZE);
+
+        // Asking again returns the same slot.
+        let again = table.get_or_insert(50000).unwrap();
+        assert!(core::ptr::eq(port, again));
+    }
+
+    #[test]
+    fn published_connections_are_readable() {
+        let table: Box<PortTable<ConnectionV4>> = PortTable::new();
+        let queue = MpscQueue::new();
+        let collector = Collector::new(4);
+
+        for local_port in [0, 443, 65535] {
+            let port = table.get_or_insert(local_port).unwrap();
+            port.lock().publish(
+                Some(vec![connection(local_port)].into_boxed_slice()),
+                &queue,
+                &collector,
+            );
+        }
+        let guard = collector.pin();
+        for local_port in [0, 443, 65535] {
+            let conns = table.get(local_port).unwrap().read(&guard).unwrap();
+            assert_eq!(conns[0].local_port, local_port);
+        }
+        assert_eq!(table.iter().filter(|port| !port.is_empty()).count(), 3);
+        // Nothing was replaced, nothing to reclaim.
+        assert!(queue.is_empty());
+    }
+
+    // Memory of the four tables of the cache (tcp/udp, v4/v6) compared to the flat arrays they
+    // replace. Run with `cargo test port_table_memory -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn port_table_memory() {
+        let flat = 4 * PORT_COUNT * size_of::<RCUPort<ConnectionV4>>();
+        std::println!("flat arrays: {} KiB", flat / 1024);
+
+        for connections in [0, 50, 1000, 10000, PORT_COUNT] {
+            let tables: [Box<PortTable<ConnectionV4>>; 4] = [
+                PortTable::new(),
+                PortTable::new(),
+                PortTable::new(),
+                PortTable::new(),
+            ];
+            // Ephemeral ports, spread over the tables the way a mix of connections would be.
+            let mut seed: u32 = 1;
+            for i in 0..connections {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let port = if connections == PORT_COUNT {
+                    i as u16
+                } else {
+                    49152 + (seed >> 16) as u16 % 16384
+                };
+                _ = tables[i % 4].get_or_insert(port);
+            }
+            let bytes: usize = tables.iter().map(|table| table.allocated_bytes()).sum();
+            std::println!("{:>6} connections: {} KiB", connections, bytes / 1024);
+        }
+    }
+}
@@ -0,0 +1,230 @@
+//! Index of the active connections of every process.
+//!
+//! Connections normally end through the endpoint closure and resource release callouts, or age out
+//! of the cache. A process that crashes or is killed does not always get there, and its entries
+//! would stay active until they age out. The process notify callback feeds the exit of a process in
+//! here, which hands back the keys of all its connections so they can be ended right away. The
+//! same index answers the commands that act on all the connections of a process, without a walk
+//! over every port.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+use wdk::process::ProcessEvent;
+
+use crate::connection::Key;
+
+pub struct ProcessIndex {
+    connections: BTreeMap<u64, BTreeSet<Key>>,
+}
+
+impl ProcessIndex {
+    pub const fn new() -> Self {
+        Self {
+            connections: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the connection to the process. Process id 0 means the process is not known, those
+    /// connections are not indexed.
+    pub fn add(&mut self, process_id: u64, key: Key) {
+        if process_id == 0 {
+            return;
+        }
+        self.connections.entry(process_id).or_default().insert(key);
+    }
+
+    /// Removes the connection from the process, once it has ended on its own.
+    pub fn remove(&mut self, process_id: u64, key: &Key) {
+        if let Some(keys) = self.connections.get_mut(&process_id) {
+            keys.remove(key);
+            if keys.is_empty() {
+                self.connections.remove(&process_id);
+            }
+        }
+    }
+
+    /// Returns the keys of the connections of the process.
+    pub fn keys(&self, process_id: u64) -> Vec<Key> {
+        match self.connections.get(&process_id) {
+            Some(keys) => keys.iter().copied().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Removes the process and returns the keys of its connections.
+    pub fn take(&mut self, process_id: u64) -> Vec<Key> {
+        match self.connections.remove(&process_id) {
+            Some(keys) => keys.into_iter().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.connections.clear();
+    }
+
+    /// Handles a process notification. On exit, every connection of the process is passed to
+    /// `end_connection`, and the connections it ended are returned so an end event can be sent
+    /// for each. Creation needs nothing from the index.
+    pub fn handle_event<C, F: FnMut(&Key) -> Option<C>>(
+        &mut self,
+        event: ProcessEvent,
+        mut end_connection: F,
+    ) -> Vec<C> {
+        match event {
+            ProcessEvent::Created { .. } => Vec::new(),
+            ProcessEvent::Exited { process_id } => self
+                .take(process_id)
+                .iter()
+                .filter_map(&mut end_connection)
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProcessIndex;
+    use crate::connection::Key;
+    use alloc::collections::BTreeMap;
+    use alloc::vec::Vec;
+    use smoltcp::wire::{IpAddress, IpProtocol, Ipv4Address};
+    use wdk::process::ProcessEvent;
+
+    fn key(local_port: u16) -> Key {
+        Key {
+            protocol: IpProtocol::Tcp,
+            local_address: IpAddress::Ipv4(Ipv4Address::new(10, 0, 0, 2)),
+            local_port,
+            remote_address: IpAddress::Ipv4(Ipv4Address::new(1, 1, 1, 1)),
+            remote_port: 443,
+        }
+    }
+
+    // Stands in for the connection cache: key → (process id, ended).
+    struct FakeCache {
+        entries: BTreeMap<Key, (u64, bool)>,
+    }
+
+    impl FakeCache {
+        fn new() -> Self {
+            Self {
+                entries: BTreeMap::new(),
+            }
+        }
+
+        fn add(&mut self, index: &mut ProcessIndex, process_id: u64, key: Key) {
+            self.entries.insert(key, (process_id, false));
+            index.add(process_id, key);
+        }
+
+        fn end(&mut self, key: &Key) -> Option<(u64, Key)> {
+            let (process_id, ended) = self.entries.get_mut(key)?;
+            if *ended {
+                return None;
+            }
+            *ended = true;
+            return Some((*process_id, *key));
+        }
+
+        fn has_ended(&self, key: &Key) -> bool {
+            self.entries.get(key).unwrap().1
+        }
+    }
+
+    // Stands in for the kernel notify routine: replays a scripted sequence of events.
+    fn notify(
+        index: &mut ProcessIndex,
+        cache: &mut FakeCache,
+        events: &[ProcessEvent],
+    ) -> Vec<(u64, Key)> {
+        let mut ended = Vec::new();
+        for event in events {
+            ended.extend(index.handle_event(*event, |key| cache.end(key)));
+        }
+        return ended;
+    }
+
+    #[test]
+    fn exit_ends_all_connections_of_the_process() {
+        let mut index = ProcessIndex::new();
+        let mut cache = FakeCache::new();
+        cache.add(&mut index, 10, key(1000));
+        cache.add(&mut index, 10, key(1001));
+        cache.add(&mut index, 11, key(1002));
+
+        let ended = notify(
+            &mut index,
+            &mut cache,
+            &[
+                ProcessEvent::Created {
+                    process_id: 12,
+                    parent_process_id: 10,
+                },
+                ProcessEvent::Exited { process_id: 10 },
+            ],
+        );
+        assert!(ended == [(10, key(1000)), (10, key(1001))]);
+        assert!(cache.has_ended(&key(1000)));
+        assert!(cache.has_ended(&key(1001)));
+        assert!(!cache.has_ended(&key(1002)));
+
+        // A second exit of the same id finds nothing left.
+        let ended = notify(
+            &mut index,
+            &mut cache,
+            &[ProcessEvent::Exited { process_id: 10 }],
+        );
+        assert!(ended.is_empty());
+    }
+
+    #[test]
+    fn connections_that_ended_on_their_own_are_not_ended_again() {
+        let mut index = ProcessIndex::new();
+        let mut cache = FakeCache::new();
+        cache.add(&mut index, 10, key(1000));
+        cache.add(&mut index, 10, key(1001));
+
+        cache.end(&key(1000));
+        index.remove(10, &key(1000));
+
+        let ended = notify(
+            &mut index,
+            &mut cache,
+            &[ProcessEvent::Exited { process_id: 10 }],
+        );
+        assert!(ended == [(10, key(1001))]);
+    }
+
+    #[test]
+    fn keys_follow_the_connections_of_the_process() {
+        let mut index = ProcessIndex::new();
+        index.add(10, key(1000));
+        index.add(10, key(1001));
+        index.add(11, key(1002));
+        assert!(index.keys(10) == [key(1000), key(1001)]);
+
+        index.remove(10, &key(1000));
+        assert!(index.keys(10) == [key(1001)]);
+        // Looking does not take them.
+        assert!(index.keys(10) == [key(1001)]);
+        assert!(index.keys(12).is_empty());
+    }
+
+    #[test]
+    fn unknown_process_is_not_indexed() {
+        let mut index = ProcessIndex::new();
+        index.add(0, key(1000));
+        assert!(index.take(0).is_empty());
+
+        index.add(10, key(1000));
+        index.add(10, key(1000));
+        assert!(index.keys(10) == [key(1000)]);
+        assert!(index.take(10) == [key(1000)]);
+        assert!(index.take(10).is_empty());
+
+        index.add(10, key(1000));
+        index.clear();
+        assert!(index.take(10).is_empty());
+    }
+}
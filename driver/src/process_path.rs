@@ -0,0 +1,122 @@
+//! Normalisation of process image paths.
+//!
+//! WFP reports the image path of a process in its NT form, lower case:
+//! `\device\harddiskvolume3\windows\system32\svchost.exe`. The volume number depends on the order
+//! in which the volumes were mounted, so it can change between boots and means nothing to user
+//! space. Paths on a volume with a drive letter are rewritten to `c:\windows\...`, and paths on a
+//! network share (`\device\mup\server\share\...`) to their UNC form `\\server\share\...`.
+//!
+//! The drive letters are looked up by the device when it starts, and again on a process creation
+//! after a path on an unknown volume was seen: the lookup has to happen at PASSIVE_LEVEL.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const MUP_DEVICE: &str = "\\device\\mup";
+
+pub struct VolumeMap {
+    // (volume device name in lower case, drive letter)
+    volumes: Vec<(String, char)>,
+}
+
+impl VolumeMap {
+    pub const fn new() -> Self {
+        Self {
+            volumes: Vec::new(),
+        }
+    }
+
+    /// Replaces all mappings. `volumes` holds the device name of each volume with a drive letter
+    /// and that letter.
+    pub fn set(&mut self, volumes: Vec<(String, char)>) {
+        self.volumes = volumes
+            .into_iter()
+            .map(|(device, letter)| (device.to_lowercase(), letter.to_ascii_lowercase()))
+            .collect();
+    }
+
+    /// Returns the stable form of `path`. Returns None if the path is on a volume device without a
+    /// known drive letter; it has to be sent as it is then.
+    pub fn normalize(&self, path: &str) -> Option<String> {
+        if let Some(rest) = strip_device(path, MUP_DEVICE) {
+            return Some(format!("\\{}", rest));
+        }
+        for (device, letter) in self.volumes.iter() {
+            if let Some(rest) = strip_device(path, device) {
+                return Some(format!("{}:{}", letter, rest));
+            }
+        }
+        if starts_with_ignore_case(path, "\\device\\") {
+            return None;
+        }
+        return Some(String::from(path));
+    }
+}
+
+// Strips `device` from the start of `path`. The device name has to be followed by a separator, so
+// `\device\harddiskvolume1` does not match `\device\harddiskvolume10\...`.
+fn strip_device<'a>(path: &'a str, device: &str) -> Option<&'a str> {
+    if !starts_with_ignore_case(path, device) {
+        return None;
+    }
+    let rest = &path[device.len()..];
+    if rest.starts_with('\\') {
+        return Some(rest);
+    }
+    return None;
+}
+
+fn starts_with_ignore_case(value: &str, prefix: &str) -> bool {
+    value.len() >= prefix.len()
+        && value.as_bytes()[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VolumeMap;
+    use alloc::string::String;
+    use alloc::vec;
+
+    fn volume_map() -> VolumeMap {
+        let mut map = VolumeMap::new();
+        map.set(vec![
+            (String::from("\\Device\\HarddiskVolume3"), 'C'),
+            (String::from("\\Device\\HarddiskVolume10"), 'D'),
+        ]);
+        return map;
+    }
+
+    #[test]
+    fn volume_devices_are_replaced_by_drive_letters() {
+        let map = volume_map();
+        assert_eq!(
+            map.normalize("\\device\\harddiskvolume3\\windows\\system32\\svchost.exe")
+                .as_deref(),
+            Some("c:\\windows\\system32\\svchost.exe")
+        );
+        assert_eq!(
+            map.normalize("\\Device\\HarddiskVolume10\\Games\\game.exe")
+                .as_deref(),
+            Some("d:\\Games\\game.exe")
+        );
+    }
+
+    #[test]
+    fn unknown_volumes_are_reported() {
+        let map = volume_map();
+        assert_eq!(map.normalize("\\device\\harddiskvolume1\\app.exe"), None);
+        assert_eq!(map.normalize("\\device\\harddiskvolume3"), None);
+    }
+
+    #[test]
+    fn network_shares_and_other_paths() {
+        let map = VolumeMap::new();
+        assert_eq!(
+            map.normalize("\\device\\mup\\server\\share\\app.exe")
+                .as_deref(),
+            Some("\\\\server\\share\\app.exe")
+        );
+        assert_eq!(map.normalize("System").as_deref(), Some("System"));
+    }
+}
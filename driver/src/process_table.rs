@@ -0,0 +1,88 @@
+//! Parent of every running process.
+//!
+//! A connection is often made by a helper (a browser renderer, an updater stub) on behalf of the
+//! application that started it. The process notify callback records the parent of each process
+//! when it is created and forgets it when the process exits, so new connections can be sent with
+//! the parent pid and user space can attribute them to the application.
+//!
+//! Processes that were already running when the driver started are not in the table, their parent
+//! is reported as 0 (unknown).
+
+use alloc::collections::BTreeMap;
+use wdk::process::ProcessEvent;
+
+pub struct ProcessTable {
+    // process id → parent process id
+    parents: BTreeMap<u64, u64>,
+}
+
+impl ProcessTable {
+    pub const fn new() -> Self {
+        Self {
+            parents: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the parent of the process. None if the process started before the driver, or has
+    /// exited.
+    pub fn parent_of(&self, process_id: u64) -> Option<u64> {
+        self.parents.get(&process_id).copied()
+    }
+
+    /// Records the parent on creation and removes the process on exit. A process id can be reused
+    /// once the process has exited, so a creation always replaces what was there.
+    pub fn handle_event(&mut self, event: ProcessEvent) {
+        match event {
+            ProcessEvent::Created {
+                process_id,
+                parent_process_id,
+            } => {
+                self.parents.insert(process_id, parent_process_id);
+            }
+            ProcessEvent::Exited { process_id } => {
+                self.parents.remove(&process_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProcessTable;
+    use wdk::process::ProcessEvent;
+
+    fn created(process_id: u64, parent_process_id: u64) -> ProcessEvent {
+        ProcessEvent::Created {
+            process_id,
+            parent_process_id,
+        }
+    }
+
+    #[test]
+    fn parent_is_recorded_until_exit() {
+        let mut table = ProcessTable::new();
+        table.handle_event(created(100, 4));
+        table.handle_event(created(200, 100));
+        assert_eq!(table.parent_of(200), Some(100));
+        assert_eq!(table.parent_of(100), Some(4));
+
+        // The parent exiting does not change what is reported for the child.
+        table.handle_event(ProcessEvent::Exited { process_id: 100 });
+        assert_eq!(table.parent_of(100), None);
+        assert_eq!(table.parent_of(200), Some(100));
+
+        table.handle_event(ProcessEvent::Exited { process_id: 200 });
+        assert_eq!(table.parent_of(200), None);
+    }
+
+    #[test]
+    fn unknown_and_reused_process_ids() {
+        let mut table = ProcessTable::new();
+        assert_eq!(table.parent_of(300), None);
+
+        table.handle_event(created(300, 10));
+        table.handle_event(ProcessEvent::Exited { process_id: 300 });
+        table.handle_event(created(300, 20));
+        assert_eq!(table.parent_of(300), Some(20));
+    }
+}
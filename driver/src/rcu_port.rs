@@ -2,20 +2,20 @@ use alloc::boxed::Box;
 use alloc::sync::Arc;
 use core::{
     ptr,
-    sync::atomic::{AtomicPtr, AtomicU16, AtomicU64, Ordering},
+    sync::atomic::{AtomicPtr, AtomicU64, Ordering},
 };
 
 use crate::connection::Connection;
+use crate::epoch::{Collector, Guard, Retired};
 use crate::mpsc_queue::MpscQueue;
 use wdk::rw_spin_lock::{Mutex, MutexWriteGuard};
 
 // -------------------------------------------------------------------------------------------------
 // RCU-style per-port slot.
-// Readers acquire a RCUPortReadGuard (lock-free, loads raw pointer) and access the snapshot
-// through it. The guard increments the ConnectionArray's reader counter on creation and
-// decrements it on drop, enabling deferred reclamation by the consumer of unlinked_ports.
-// Writers acquire the write mutex and publish changes atomically. The old snapshot is stamped
-// with an unlinked timestamp and pushed onto a caller-supplied MPSC queue for safe reclamation.
+// Readers pin the collector of the cache (see epoch.rs) and load the snapshot lock-free; the
+// snapshot lives as long as the pin. Writers acquire the write mutex and publish changes
+// atomically. The old snapshot is retired with the current epoch, pushed onto a caller-supplied
+// MPSC queue, and freed by the collector once no pinned reader can still see it.
 // -------------------------------------------------------------------------------------------------
 
 // Heap-allocated, fixed-length array of connections. Length is set on publish and never changes.
@@ -23,8 +23,21 @@ use wdk::rw_spin_lock::{Mutex, MutexWriteGuard};
 pub(crate) struct ConnectionArray<T: Connection> {
     array: Box<[Arc<T>]>,
 
-    pub(crate) readers: AtomicU16,
-    pub(crate) unlinked_timestamp: AtomicU64,
+    // Epoch of the collector when the array was unlinked.
+    retired_epoch: AtomicU64,
+}
+
+impl<T: Connection> Retired for ConnectionArray<T> {
+    fn retired_epoch(&self) -> u64 {
+        self.retired_epoch.load(Ordering::SeqCst)
+    }
+}
+
+// Frees a connection array popped from an unlinked queue.
+pub(crate) fn free_array<T: Connection>(array: *mut ConnectionArray<T>) {
+    if !array.is_null() {
+        unsafe { drop(Box::from_raw(array)) };
+    }
 }
 
 pub(crate) struct RCUPort<T: Connection> {
@@ -55,16 +68,14 @@ impl<T: Connection> RCUPort<T> {
         self.connections.load(Ordering::SeqCst).is_null()
     }
 
-    // Lock-free. Loads the current snapshot pointer and returns a guard.
-    pub(crate) fn read(&self) -> RCUPortReadGuard<'_, T> {
+    // Lock-free. Returns the current snapshot, valid while the guard pins the collector the
+    // replaced snapshots of this port are retired to.
+    pub(crate) fn read<'a>(&'a self, _guard: &'a Guard<'_>) -> Option<&'a [Arc<T>]> {
         let ptr = self.connections.load(Ordering::SeqCst);
-        // Increment reader count only if non-null (null = no connections on this port).
-        if !ptr.is_null() {
-            unsafe { (*ptr).readers.fetch_add(1, Ordering::SeqCst) };
-        }
-        RCUPortReadGuard {
-            _port: self,
-            snapshot: ptr as *const _,
+        if ptr.is_null() {
+            None
+        } else {
+            unsafe { Some(&(*ptr).array) }
         }
     }
 
@@ -77,30 +88,6 @@ impl<T: Connection> RCUPort<T> {
     }
 }
 
-// Guard returned by RCUPort::read(). Holds a raw snapshot pointer for the caller.
-pub(crate) struct RCUPortReadGuard<'a, T: Connection> {
-    _port: &'a RCUPort<T>,
-    snapshot: *const ConnectionArray<T>,
-}
-
-impl<T: Connection> RCUPortReadGuard<'_, T> {
-    pub(crate) fn get(&self) -> Option<&[Arc<T>]> {
-        if self.snapshot.is_null() {
-            None
-        } else {
-            unsafe { Some(&(*self.snapshot).array) }
-        }
-    }
-}
-
-impl<T: Connection> Drop for RCUPortReadGuard<'_, T> {
-    fn drop(&mut self) {
-        if !self.snapshot.is_null() {
-            unsafe { (*self.snapshot).readers.fetch_sub(1, Ordering::SeqCst) };
-        }
-    }
-}
-
 // Guard returned by RCUPort::lock(). publish() and snapshot() are only accessible through this
 // type, so the compiler statically enforces that no write can happen without holding the lock.
 pub(crate) struct RCUPortWriteGuard<'a, T: Connection> {
@@ -120,18 +107,18 @@ impl<T: Connection> RCUPortWriteGuard<'_, T> {
     }
 
     // Publishes a new snapshot. The slice must be fully built before calling.
-    // The old snapshot pointer is stamped with the current time and pushed onto
-    // the provided queue for deferred reclamation.
+    // The old snapshot is retired with the current epoch and pushed onto the provided queue,
+    // then the collector frees what no reader can see anymore.
     pub(crate) fn publish(
         &self,
         new: Option<Box<[Arc<T>]>>,
         queue: &MpscQueue<ConnectionArray<T>>,
+        collector: &Collector,
     ) {
         let new_raw: *mut ConnectionArray<T> = match new {
             Some(v) => Box::into_raw(Box::new(ConnectionArray {
                 array: v,
-                readers: AtomicU16::new(0),
-                unlinked_timestamp: AtomicU64::new(0),
+                retired_epoch: AtomicU64::new(0),
             })),
             None => ptr::null_mut(),
         };
@@ -142,10 +129,11 @@ impl<T: Connection> RCUPortWriteGuard<'_, T> {
         }
         unsafe {
             (*old)
-                .unlinked_timestamp
-                .store(wdk::utils::get_system_timestamp_ms(), Ordering::SeqCst);
+                .retired_epoch
+                .store(collector.epoch(), Ordering::SeqCst);
         }
 
         queue.push(old);
+        collector.reclaim(queue, free_array);
     }
 }
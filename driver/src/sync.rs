@@ -0,0 +1,20 @@
+//! Atomics of the epoch based reclamation (`epoch.rs`).
+//!
+//! They are the core ones in the driver, and loom's when built with `--cfg loom`, so the model
+//! checker can run every interleaving of the reclamation:
+//!
+//! `RUSTFLAGS="--cfg loom" cargo test --release epoch`
+
+#[cfg(not(loom))]
+pub(crate) use core::sync::atomic::{fence, AtomicBool, AtomicU64, Ordering};
+
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{fence, AtomicBool, AtomicU64, Ordering};
+
+/// Called while waiting for another thread. Under loom this lets the other threads run.
+pub(crate) fn spin_loop() {
+    #[cfg(not(loom))]
+    core::hint::spin_loop();
+    #[cfg(loom)]
+    loom::thread::yield_now();
+}
@@ -0,0 +1,444 @@
+//! Killing a TCP connection (`KillConnection` command).
+//!
+//! A `PermanentBlock` verdict only drops the packets that come after it, so both ends of the
+//! connection keep waiting for data that never arrives until they time out. A kill sends a RST to
+//! each end instead, and both sockets are torn down right away.
+//!
+//! A RST is only taken when its sequence number is the one the receiver expects next. Anything else
+//! in the window gets a challenge ACK (RFC 5961), the rest is dropped. The packet layer records the
+//! sequence numbers of every segment of a connection in its `TcpSequence`: where the data of each
+//! side ends, and what each side acknowledged last. The RST to one end carries the next sequence
+//! number of the other.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use smoltcp::wire::{
+    IpAddress, IpProtocol, Ipv4Packet, Ipv6Packet, TcpPacket, TcpSeqNumber, IPV4_HEADER_LEN,
+    IPV6_HEADER_LEN, TCP_HEADER_LEN,
+};
+
+use crate::connection::{Direction, Key};
+
+/// Hop limit of the RST packets, the Windows default.
+const HOP_LIMIT: u8 = 128;
+
+/// Set on a tracked sequence number once one was seen. The number itself is the low 32 bits.
+const KNOWN: u64 = 1 << 32;
+
+/// The sequence numbers and flags of a TCP segment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TcpSegment {
+    pub seq: u32,
+    /// None without the ACK flag.
+    pub ack: Option<u32>,
+    /// Sequence space the segment takes: the payload, plus one for SYN and one for FIN.
+    pub len: u32,
+    pub syn: bool,
+    pub fin: bool,
+    pub rst: bool,
+}
+
+impl TcpSegment {
+    /// Parses the TCP header at the start of `header`, of a segment `segment_len` bytes long,
+    /// header included. None if the header is truncated.
+    pub fn parse(header: &[u8], segment_len: usize) -> Option<Self> {
+        if header.len() < TCP_HEADER_LEN {
+            return None;
+        }
+        let tcp_packet = TcpPacket::new_unchecked(header);
+        let header_len = tcp_packet.header_len() as usize;
+        if header_len < TCP_HEADER_LEN || header_len > segment_len {
+            return None;
+        }
+        let payload_len = (segment_len - header_len) as u32;
+        Some(Self {
+            seq: tcp_packet.seq_number().0 as u32,
+            ack: tcp_packet.ack().then(|| tcp_packet.ack_number().0 as u32),
+            len: payload_len + tcp_packet.syn() as u32 + tcp_packet.fin() as u32,
+            syn: tcp_packet.syn(),
+            fin: tcp_packet.fin(),
+            rst: tcp_packet.rst(),
+        })
+    }
+}
+
+/// Sequence numbers seen on a connection, updated by the packet layer.
+pub struct TcpSequence {
+    // Both indexed by the side that sent the segment, see `side`.
+    /// Where the data sent so far ends: the next sequence number of the side.
+    next_seq: [AtomicU64; 2],
+    /// The highest acknowledgement the side sent.
+    ack: [AtomicU64; 2],
+}
+
+impl TcpSequence {
+    pub const fn new() -> Self {
+        Self {
+            next_seq: [AtomicU64::new(0), AtomicU64::new(0)],
+            ack: [AtomicU64::new(0), AtomicU64::new(0)],
+        }
+    }
+
+    /// Records a segment going in `direction`: outbound segments are sent by the local end,
+    /// inbound ones by the remote end. Retransmissions and reordered segments never move a number
+    /// back, and a RST has nothing to record.
+    pub fn record(&self, direction: Direction, segment: &TcpSegment) {
+        if segment.rst {
+            return;
+        }
+        let side = side(direction);
+        advance(&self.next_seq[side], segment.seq.wrapping_add(segment.len));
+        if let Some(ack) = segment.ack {
+            advance(&self.ack[side], ack);
+        }
+    }
+
+    /// Reports whether `seq` is the next sequence number of the end sending in `direction`, or
+    /// that number is not known yet.
+    pub fn expects(&self, direction: Direction, seq: u32) -> bool {
+        load(&self.next_seq[side(direction)]).is_none_or(|next_seq| next_seq == seq)
+    }
+
+    /// Returns the sequence and acknowledgement numbers of a RST going in `direction`: outbound
+    /// to the remote end, inbound to the local stack. None if nothing was seen that tells what the
+    /// receiver expects.
+    pub fn reset_numbers(&self, direction: Direction) -> Option<(u32, Option<u32>)> {
+        // The RST is sent as the end that sends in `direction`: the local end for the outbound RST,
+        // the remote end for the inbound one.
+        let sender = side(direction);
+        let receiver = 1 - sender;
+        // What the sender sent last is what the receiver expects, even before it acknowledged it.
+        let seq = load(&self.next_seq[sender]).or_else(|| load(&self.ack[receiver]))?;
+        let ack = load(&self.next_seq[receiver]).or_else(|| load(&self.ack[sender]));
+        Some((seq, ack))
+    }
+}
+
+impl Clone for TcpSequence {
+    fn clone(&self) -> Self {
+        let copy = |value: &AtomicU64| AtomicU64::new(value.load(Ordering::SeqCst));
+        Self {
+            next_seq: [copy(&self.next_seq[0]), copy(&self.next_seq[1])],
+            ack: [copy(&self.ack[0]), copy(&self.ack[1])],
+        }
+    }
+}
+
+// Index of the side that sends in `direction`: 0 for the local end, 1 for the remote one.
+fn side(direction: Direction) -> usize {
+    match direction {
+        Direction::Outbound => 0,
+        Direction::Inbound => 1,
+    }
+}
+
+fn load(value: &AtomicU64) -> Option<u32> {
+    let value = value.load(Ordering::SeqCst);
+    (value & KNOWN != 0).then_some(value as u32)
+}
+
+// Stores `number` unless the stored one is already past it, modulo 2^32.
+fn advance(value: &AtomicU64, number: u32) {
+    _ = value.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |current| {
+        let newer = current & KNOWN == 0 || (number.wrapping_sub(current as u32) as i32) > 0;
+        newer.then_some(KNOWN | number as u64)
+    });
+}
+
+/// Builds a RST|ACK (a plain RST without `ack`) of the connection going in `direction`: outbound
+/// from the local end to the remote one, inbound from the remote end to the local stack. Returns
+/// the whole IP packet, checksums filled in.
+pub fn build_rst(
+    key: &Key,
+    direction: Direction,
+    seq: u32,
+    ack: Option<u32>,
+) -> Result<Vec<u8>, String> {
+    let (src_address, src_port, dst_address, dst_port) = match direction {
+        Direction::Outbound => (
+            key.local_address,
+            key.local_port,
+            key.remote_address,
+            key.remote_port,
+        ),
+        Direction::Inbound => (
+            key.remote_address,
+            key.remote_port,
+            key.local_address,
+            key.local_port,
+        ),
+    };
+
+    let mut packet = match (src_address, dst_address) {
+        (IpAddress::Ipv4(src), IpAddress::Ipv4(dst)) => {
+            let mut packet = alloc::vec![0u8; IPV4_HEADER_LEN + TCP_HEADER_LEN];
+            let mut ip_packet = Ipv4Packet::new_unchecked(&mut packet[..]);
+            ip_packet.set_version(4);
+            ip_packet.set_header_len(IPV4_HEADER_LEN as u8);
+            ip_packet.set_total_len((IPV4_HEADER_LEN + TCP_HEADER_LEN) as u16);
+            ip_packet.set_dont_frag(true);
+            ip_packet.set_hop_limit(HOP_LIMIT);
+            ip_packet.set_next_header(IpProtocol::Tcp);
+            ip_packet.set_src_addr(src);
+            ip_packet.set_dst_addr(dst);
+            ip_packet.fill_checksum();
+            packet
+        }
+        (IpAddress::Ipv6(src), IpAddress::Ipv6(dst)) => {
+            let mut packet = alloc::vec![0u8; IPV6_HEADER_LEN + TCP_HEADER_LEN];
+            let mut ip_packet = Ipv6Packet::new_unchecked(&mut packet[..]);
+            ip_packet.set_version(6);
+            ip_packet.set_payload_len(TCP_HEADER_LEN as u16);
+            ip_packet.set_next_header(IpProtocol::Tcp);
+            ip_packet.set_hop_limit(HOP_LIMIT);
+            ip_packet.set_src_addr(src);
+            ip_packet.set_dst_addr(dst);
+            packet
+        }
+        _ => return Err("local and remote address of different ip versions".to_string()),
+    };
+
+    let l4_offset = packet.len() - TCP_HEADER_LEN;
+    let mut tcp_packet = TcpPacket::new_unchecked(&mut packet[l4_offset..]);
+    tcp_packet.set_src_port(src_port);
+    tcp_packet.set_dst_port(dst_port);
+    tcp_packet.set_seq_number(TcpSeqNumber(seq as i32));
+    tcp_packet.set_header_len(TCP_HEADER_LEN as u8);
+    tcp_packet.set_rst(true);
+    if let Some(ack) = ack {
+        tcp_packet.set_ack(true);
+        tcp_packet.set_ack_number(TcpSeqNumber(ack as i32));
+    }
+    tcp_packet.fill_checksum(&src_address, &dst_address);
+    Ok(packet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_rst, TcpSegment, TcpSequence};
+    use crate::connection::{Direction, Key};
+    use smoltcp::wire::{
+        IpAddress, IpProtocol, Ipv4Address, Ipv4Packet, Ipv6Address, Ipv6Packet, TcpPacket,
+    };
+
+    fn key_v4() -> Key {
+        Key {
+            protocol: IpProtocol::Tcp,
+            local_address: IpAddress::Ipv4(Ipv4Address::new(192, 168, 1, 2)),
+            local_port: 50000,
+            remote_address: IpAddress::Ipv4(Ipv4Address::new(1, 1, 1, 1)),
+            remote_port: 443,
+        }
+    }
+
+    fn segment(seq: u32, ack: Option<u32>, len: u32) -> TcpSegment {
+        TcpSegment {
+            seq,
+            ack,
+            len,
+            syn: false,
+            fin: false,
+            rst: false,
+        }
+    }
+
+    #[test]
+    fn segment_length_counts_syn_and_fin() {
+        // Source port, destination port, seq 1000, ack 2000, data offset 5 with SYN|ACK, window.
+        let mut header = [0u8; 20];
+        header[4..8].copy_from_slice(&1000u32.to_be_bytes());
+        header[8..12].copy_from_slice(&2000u32.to_be_bytes());
+        header[12] = 5 << 4;
+        header[13] = 0x12;
+        assert_eq!(
+            TcpSegment::parse(&header, 20),
+            Some(TcpSegment {
+                syn: true,
+                ..segment(1000, Some(2000), 1)
+            })
+        );
+        // FIN|ACK with 100 bytes of payload.
+        header[13] = 0x11;
+        assert_eq!(
+            TcpSegment::parse(&header, 120),
+            Some(TcpSegment {
+                fin: true,
+                ..segment(1000, Some(2000), 101)
+            })
+        );
+        // No ACK flag, no acknowledgement.
+        header[13] = 0x02;
+        assert_eq!(
+            TcpSegment::parse(&header, 20),
+            Some(TcpSegment {
+                syn: true,
+                ..segment(1000, None, 1)
+            })
+        );
+        // A RST|ACK takes no sequence space.
+        header[13] = 0x14;
+        assert_eq!(
+            TcpSegment::parse(&header, 20),
+            Some(TcpSegment {
+                rst: true,
+                ..segment(1000, Some(2000), 0)
+            })
+        );
+        // A truncated header, and a header longer than the segment.
+        header[13] = 0x10;
+        assert_eq!(TcpSegment::parse(&header[..19], 20), None);
+        header[12] = 8 << 4;
+        assert_eq!(TcpSegment::parse(&header, 20), None);
+    }
+
+    #[test]
+    fn reset_takes_the_next_sequence_number_of_the_sending_end() {
+        let sequence = TcpSequence::new();
+        assert_eq!(sequence.reset_numbers(Direction::Outbound), None);
+
+        // A bare acknowledgement from the local end is enough for both.
+        sequence.record(Direction::Outbound, &segment(100, Some(5000), 0));
+        assert_eq!(
+            sequence.reset_numbers(Direction::Inbound),
+            Some((5000, Some(100)))
+        );
+        assert_eq!(
+            sequence.reset_numbers(Direction::Outbound),
+            Some((100, Some(5000)))
+        );
+
+        // Data from the remote end moves past what the local end acknowledged.
+        sequence.record(Direction::Inbound, &segment(5000, Some(100), 1460));
+        sequence.record(Direction::Outbound, &segment(100, Some(6460), 200));
+        assert_eq!(
+            sequence.reset_numbers(Direction::Inbound),
+            Some((6460, Some(300)))
+        );
+        assert_eq!(
+            sequence.reset_numbers(Direction::Outbound),
+            Some((300, Some(6460)))
+        );
+
+        // A retransmission does not move it back, and a RST does not count.
+        sequence.record(Direction::Inbound, &segment(5000, Some(100), 1460));
+        let reset = TcpSegment {
+            rst: true,
+            ..segment(9000, Some(9000), 0)
+        };
+        sequence.record(Direction::Inbound, &reset);
+        assert_eq!(
+            sequence.reset_numbers(Direction::Inbound),
+            Some((6460, Some(300)))
+        );
+        assert_eq!(
+            sequence.clone().reset_numbers(Direction::Inbound),
+            Some((6460, Some(300)))
+        );
+    }
+
+    // Sends the RST the way `send_resets` builds it and reads its numbers back.
+    fn rst_numbers(sequence: &TcpSequence, direction: Direction) -> (u32, u32) {
+        let key = key_v4();
+        let (seq, ack) = sequence.reset_numbers(direction).unwrap();
+        let packet = build_rst(&key, direction, seq, ack).unwrap();
+        let ip_packet = Ipv4Packet::new_checked(&packet[..]).unwrap();
+        let tcp_packet = TcpPacket::new_checked(ip_packet.payload()).unwrap();
+        assert!(tcp_packet.rst() && tcp_packet.ack());
+        (
+            tcp_packet.seq_number().0 as u32,
+            tcp_packet.ack_number().0 as u32,
+        )
+    }
+
+    #[test]
+    fn resets_after_a_handshake_are_accepted_by_rfc_5961() {
+        // RFC 5961: a RST is only acted on if its sequence number is exactly RCV.NXT (anything
+        // else in the window gets a challenge ACK), and its acknowledgement lies in
+        // SND.UNA..=SND.NXT of the receiver.
+        fn accepts(rcv_nxt: u32, snd_una: u32, snd_nxt: u32, (seq, ack): (u32, u32)) -> bool {
+            seq == rcv_nxt && (snd_una..=snd_nxt).contains(&ack)
+        }
+
+        let sequence = TcpSequence::new();
+        // Handshake, 100 bytes from the remote end, then 50 bytes from the local end that the
+        // remote end has not acknowledged yet.
+        sequence.record(
+            Direction::Outbound,
+            &TcpSegment {
+                syn: true,
+                ..segment(1000, None, 1)
+            },
+        );
+        sequence.record(
+            Direction::Inbound,
+            &TcpSegment {
+                syn: true,
+                ..segment(5000, Some(1001), 1)
+            },
+        );
+        sequence.record(Direction::Outbound, &segment(1001, Some(5001), 0));
+        sequence.record(Direction::Inbound, &segment(5001, Some(1001), 100));
+        sequence.record(Direction::Outbound, &segment(1001, Some(5101), 50));
+
+        // The remote end: RCV.NXT 1051, everything it sent is acknowledged.
+        let to_remote = rst_numbers(&sequence, Direction::Outbound);
+        assert_eq!(to_remote, (1051, 5101));
+        assert!(accepts(1051, 5101, 5101, to_remote));
+        // The local stack: RCV.NXT 5101, its last 50 bytes are still in flight.
+        let to_local = rst_numbers(&sequence, Direction::Inbound);
+        assert_eq!(to_local, (5101, 1051));
+        assert!(accepts(5101, 1001, 1051, to_local));
+        // The sides swapped would be answered with a challenge ACK.
+        assert!(!accepts(1051, 5101, 5101, to_local));
+        assert!(!accepts(5101, 1001, 1051, to_remote));
+    }
+
+    #[test]
+    fn sequence_numbers_wrap_around() {
+        let sequence = TcpSequence::new();
+        sequence.record(Direction::Inbound, &segment(u32::MAX - 99, None, 100));
+        assert_eq!(sequence.reset_numbers(Direction::Inbound), Some((0, None)));
+        sequence.record(Direction::Inbound, &segment(0, None, 50));
+        sequence.record(Direction::Inbound, &segment(u32::MAX - 99, None, 100));
+        assert_eq!(sequence.reset_numbers(Direction::Inbound), Some((50, None)));
+    }
+
+    #[test]
+    fn rst_packets_parse_back() {
+        let key = key_v4();
+        let to_remote = build_rst(&key, Direction::Outbound, 300, Some(6460)).unwrap();
+        let ip_packet = Ipv4Packet::new_checked(&to_remote[..]).unwrap();
+        assert!(ip_packet.verify_checksum());
+        assert_eq!(ip_packet.next_header(), IpProtocol::Tcp);
+        assert_eq!(IpAddress::Ipv4(ip_packet.src_addr()), key.local_address);
+        assert_eq!(IpAddress::Ipv4(ip_packet.dst_addr()), key.remote_address);
+        let tcp_packet = TcpPacket::new_checked(ip_packet.payload()).unwrap();
+        assert!(tcp_packet.verify_checksum(&key.local_address, &key.remote_address));
+        assert!(tcp_packet.rst() && tcp_packet.ack() && !tcp_packet.syn());
+        assert_eq!((tcp_packet.src_port(), tcp_packet.dst_port()), (50000, 443));
+        assert_eq!(tcp_packet.seq_number().0 as u32, 300);
+        assert_eq!(tcp_packet.ack_number().0 as u32, 6460);
+
+        let key = Key {
+            local_address: IpAddress::Ipv6(Ipv6Address::new(0xfd00, 0, 0, 0, 0, 0, 0, 2)),
+            remote_address: IpAddress::Ipv6(Ipv6Address::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+            ..key_v4()
+        };
+        let to_local = build_rst(&key, Direction::Inbound, u32::MAX, None).unwrap();
+        let ip_packet = Ipv6Packet::new_checked(&to_local[..]).unwrap();
+        assert_eq!(IpAddress::Ipv6(ip_packet.src_addr()), key.remote_address);
+        assert_eq!(IpAddress::Ipv6(ip_packet.dst_addr()), key.local_address);
+        let tcp_packet = TcpPacket::new_checked(ip_packet.payload()).unwrap();
+        assert!(tcp_packet.verify_checksum(&key.remote_address, &key.local_address));
+        assert!(tcp_packet.rst() && !tcp_packet.ack());
+        assert_eq!((tcp_packet.src_port(), tcp_packet.dst_port()), (443, 50000));
+        assert_eq!(tcp_packet.seq_number().0 as u32, u32::MAX);
+
+        let mixed = Key {
+            remote_address: IpAddress::Ipv4(Ipv4Address::new(1, 1, 1, 1)),
+            ..key
+        };
+        assert!(build_rst(&mixed, Direction::Outbound, 0, None).is_err());
+    }
+}
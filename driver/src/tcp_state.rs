@@ -0,0 +1,336 @@
+//! TCP state tracking in the packet layer.
+//!
+//! Past its 5-tuple a TCP packet used to be opaque to the packet layer. Every TCP connection now
+//! follows the flags of its segments: SYN, SYN-ACK, established, a FIN from each end, and RST. The
+//! connection ends when both ends sent a FIN or one sent a RST, also the connections that were
+//! open before the driver started, which the ALE endpoint closure never reports. Those are picked
+//! up as established from their first segment.
+//!
+//! A segment that does not fit the state, like data before the handshake is done or a SYN on an
+//! established connection, is out of state, and strict mode (`SetTcpTracking` command) drops it.
+//! A RST from the remote end only counts with the exact sequence number the local stack expects
+//! (see `tcp_reset.rs`), so a blind RST the stack would ignore does not end the connection here.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+
+use crate::connection::Direction;
+use crate::tcp_reset::{TcpSegment, TcpSequence};
+
+// The state of a TCP connection. Sent with the state change event. Make sure this is in sync with
+// the Go version.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive)]
+#[repr(u8)]
+#[rustfmt::skip]
+pub enum TcpState {
+    New         = 0, // No segment seen yet.
+    Syn         = 1,
+    SynAck      = 2, // The SYN was answered, the handshake waits for the last ACK.
+    Established = 3,
+    FinLocal    = 4, // The local end sent a FIN.
+    FinRemote   = 5, // The remote end sent a FIN.
+    Closed      = 6, // Both ends sent a FIN.
+    Reset       = 7,
+}
+
+/// What a segment did to the state of its connection.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TcpTransition {
+    Same,
+    Changed(TcpState),
+    /// The segment does not fit the state, which is left as it was.
+    OutOfState,
+}
+
+/// Set next to the state when the SYN of the connection came from the remote end.
+const SYN_INBOUND: u8 = 0x80;
+
+/// The state of a connection, and which end sent its SYN, in one atomic byte.
+pub struct TcpStateTracker(AtomicU8);
+
+impl TcpStateTracker {
+    pub const fn new() -> Self {
+        Self(AtomicU8::new(TcpState::New as u8))
+    }
+
+    /// Moves the state on with a segment going in `direction`. `sequence` holds the segments seen
+    /// before this one; a RST from the remote end is out of state unless it carries the next
+    /// sequence number.
+    pub fn advance(
+        &self,
+        direction: Direction,
+        segment: &TcpSegment,
+        sequence: &TcpSequence,
+    ) -> TcpTransition {
+        let inbound = matches!(direction, Direction::Inbound);
+        if segment.rst && inbound && !sequence.expects(direction, segment.seq) {
+            return TcpTransition::OutOfState;
+        }
+        let mut transition = TcpTransition::Same;
+        _ = self
+            .0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |current| {
+                let new = next(current, inbound, segment);
+                transition = match new {
+                    None => TcpTransition::OutOfState,
+                    Some(new) if state_of(new) != state_of(current) => {
+                        TcpTransition::Changed(state_of(new))
+                    }
+                    Some(_) => TcpTransition::Same,
+                };
+                new.filter(|new| *new != current)
+            });
+        transition
+    }
+}
+
+impl Clone for TcpStateTracker {
+    fn clone(&self) -> Self {
+        Self(AtomicU8::new(self.0.load(Ordering::SeqCst)))
+    }
+}
+
+fn state_of(value: u8) -> TcpState {
+    TcpState::from_u8(value & !SYN_INBOUND).unwrap_or(TcpState::New)
+}
+
+fn pack(state: TcpState, syn_inbound: bool) -> u8 {
+    state as u8 | if syn_inbound { SYN_INBOUND } else { 0 }
+}
+
+// The state after `segment`, None if it is out of state.
+fn next(current: u8, inbound: bool, segment: &TcpSegment) -> Option<u8> {
+    let state = state_of(current);
+    let syn_inbound = current & SYN_INBOUND != 0;
+    // The end that sent the SYN, the client.
+    let from_client = inbound == syn_inbound;
+
+    if segment.rst {
+        return match state {
+            TcpState::New | TcpState::Reset => None,
+            _ => Some(pack(TcpState::Reset, syn_inbound)),
+        };
+    }
+
+    if segment.syn {
+        let ack = segment.ack.is_some();
+        return match (state, ack) {
+            // A new connection, also one that reuses the tuple of one that ended.
+            (TcpState::New | TcpState::Closed | TcpState::Reset, false) => {
+                Some(pack(TcpState::Syn, inbound))
+            }
+            // Picked up in the middle of the handshake.
+            (TcpState::New, true) => Some(pack(TcpState::SynAck, !inbound)),
+            (TcpState::Syn, true) if !from_client => Some(pack(TcpState::SynAck, syn_inbound)),
+            // Retransmissions, the answer was lost.
+            (TcpState::Syn | TcpState::SynAck, false) if from_client => Some(current),
+            (TcpState::SynAck, true) if !from_client => Some(current),
+            // The last ACK of the handshake was lost, the server sends its SYN-ACK again.
+            (TcpState::Established, true) => Some(current),
+            _ => None,
+        };
+    }
+
+    let state = match state {
+        // Picked up after the handshake: the connection was open before the driver started.
+        TcpState::New => TcpState::Established,
+        TcpState::SynAck if from_client && segment.ack.is_some() => TcpState::Established,
+        // Nothing but the handshake before it is done, nothing at all after a RST.
+        TcpState::Syn | TcpState::SynAck | TcpState::Reset => return None,
+        state => state,
+    };
+
+    let state = match (state, segment.fin, inbound) {
+        (TcpState::Established, true, false) => TcpState::FinLocal,
+        (TcpState::Established, true, true) => TcpState::FinRemote,
+        (TcpState::FinLocal, true, true) | (TcpState::FinRemote, true, false) => TcpState::Closed,
+        // The rest of the data and the ACKs of a closing connection, retransmitted FINs.
+        (state, _, _) => state,
+    };
+    Some(pack(state, syn_inbound))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{state_of, TcpState, TcpStateTracker, TcpTransition};
+    use crate::connection::Direction;
+    use crate::tcp_reset::{TcpSegment, TcpSequence};
+    use alloc::vec::Vec;
+    use core::sync::atomic::Ordering;
+
+    use Direction::{Inbound as In, Outbound as Out};
+
+    // Flags of the test segments.
+    const SYN: u8 = 1;
+    const ACK: u8 = 2;
+    const FIN: u8 = 4;
+    const RST: u8 = 8;
+
+    struct Connection {
+        state: TcpStateTracker,
+        sequence: TcpSequence,
+    }
+
+    impl Connection {
+        fn new() -> Self {
+            Self {
+                state: TcpStateTracker::new(),
+                sequence: TcpSequence::new(),
+            }
+        }
+
+        fn state(&self) -> TcpState {
+            state_of(self.state.0.load(Ordering::SeqCst))
+        }
+
+        // Sends a segment through the state machine like the packet layer does.
+        fn send(&self, direction: Direction, flags: u8, seq: u32, len: u32) -> TcpTransition {
+            let segment = TcpSegment {
+                seq,
+                ack: (flags & ACK != 0).then_some(1),
+                len: len + (flags & SYN != 0) as u32 + (flags & FIN != 0) as u32,
+                syn: flags & SYN != 0,
+                fin: flags & FIN != 0,
+                rst: flags & RST != 0,
+            };
+            let transition = self.state.advance(direction, &segment, &self.sequence);
+            if transition != TcpTransition::OutOfState {
+                self.sequence.record(direction, &segment);
+            }
+            transition
+        }
+
+        // Sends the segments and returns the states they changed to.
+        fn run(&self, segments: &[(Direction, u8, u32, u32)]) -> Vec<TcpState> {
+            let mut states = Vec::new();
+            for &(direction, flags, seq, len) in segments {
+                match self.send(direction, flags, seq, len) {
+                    TcpTransition::Changed(state) => states.push(state),
+                    TcpTransition::Same => {}
+                    TcpTransition::OutOfState => panic!("out of state: {:?} {}", direction, flags),
+                }
+            }
+            states
+        }
+    }
+
+    #[test]
+    fn handshake_data_and_close() {
+        let conn = Connection::new();
+        let states = conn.run(&[
+            (Out, SYN, 100, 0),
+            // The SYN is sent again before the answer comes.
+            (Out, SYN, 100, 0),
+            (In, SYN | ACK, 5000, 0),
+            (Out, ACK, 101, 0),
+            (Out, ACK, 101, 500),
+            (In, ACK, 5001, 1460),
+            (In, FIN | ACK, 6461, 0),
+            (Out, ACK, 601, 0),
+            // The FIN is sent again, the ACK was lost.
+            (In, FIN | ACK, 6461, 0),
+            (Out, FIN | ACK, 601, 0),
+            (In, ACK, 6462, 0),
+        ]);
+        assert_eq!(
+            states,
+            [
+                TcpState::Syn,
+                TcpState::SynAck,
+                TcpState::Established,
+                TcpState::FinRemote,
+                TcpState::Closed,
+            ]
+        );
+
+        // The tuple is used again by a new connection, here an inbound one.
+        let states = conn.run(&[
+            (In, SYN, 9000, 0),
+            (Out, SYN | ACK, 7000, 0),
+            (In, ACK, 9001, 0),
+            (Out, FIN | ACK, 7001, 0),
+        ]);
+        assert_eq!(
+            states,
+            [
+                TcpState::Syn,
+                TcpState::SynAck,
+                TcpState::Established,
+                TcpState::FinLocal,
+            ]
+        );
+    }
+
+    #[test]
+    fn connections_older_than_the_driver_are_picked_up() {
+        let conn = Connection::new();
+        assert_eq!(conn.state(), TcpState::New);
+        let states = conn.run(&[(In, ACK, 5000, 100), (Out, FIN | ACK, 300, 0)]);
+        assert_eq!(states, [TcpState::Established, TcpState::FinLocal]);
+
+        let conn = Connection::new();
+        let states = conn.run(&[(In, SYN | ACK, 5000, 0), (Out, ACK, 101, 0)]);
+        assert_eq!(states, [TcpState::SynAck, TcpState::Established]);
+    }
+
+    #[test]
+    fn segments_that_do_not_fit_the_state() {
+        let conn = Connection::new();
+        conn.run(&[(Out, SYN, 100, 0)]);
+        // Data before the handshake, and a SYN-ACK from the end that sent the SYN.
+        assert_eq!(conn.send(Out, ACK, 101, 10), TcpTransition::OutOfState);
+        assert_eq!(conn.send(In, ACK, 5000, 10), TcpTransition::OutOfState);
+        assert_eq!(conn.send(Out, SYN | ACK, 100, 0), TcpTransition::OutOfState);
+        assert_eq!(conn.state(), TcpState::Syn);
+
+        conn.run(&[(In, SYN | ACK, 5000, 0), (Out, ACK, 101, 0)]);
+        // The SYN-ACK sent again is fine, a new SYN is not.
+        assert_eq!(conn.send(In, SYN | ACK, 5000, 0), TcpTransition::Same);
+        assert_eq!(conn.send(Out, SYN, 100, 0), TcpTransition::OutOfState);
+        assert_eq!(conn.state(), TcpState::Established);
+
+        // Nothing is left after a RST. A RST on a connection that was never seen fits nothing.
+        assert_eq!(
+            conn.send(Out, RST | ACK, 101, 0),
+            TcpTransition::Changed(TcpState::Reset)
+        );
+        assert_eq!(conn.send(In, ACK, 5001, 10), TcpTransition::OutOfState);
+        assert_eq!(
+            Connection::new().send(In, RST, 0, 0),
+            TcpTransition::OutOfState
+        );
+    }
+
+    #[test]
+    fn remote_reset_needs_the_next_sequence_number() {
+        let conn = Connection::new();
+        conn.run(&[
+            (Out, SYN, 100, 0),
+            (In, SYN | ACK, 5000, 0),
+            (Out, ACK, 101, 0),
+            (In, ACK, 5001, 1000),
+        ]);
+        // Blind RSTs in and out of the window.
+        assert_eq!(conn.send(In, RST, 5500, 0), TcpTransition::OutOfState);
+        assert_eq!(conn.send(In, RST, 1, 0), TcpTransition::OutOfState);
+        assert_eq!(conn.state(), TcpState::Established);
+        assert_eq!(
+            conn.send(In, RST, 6001, 0),
+            TcpTransition::Changed(TcpState::Reset)
+        );
+
+        // The answer to a SYN that nobody listens for: nothing from the remote end seen yet.
+        let refused = Connection::new();
+        refused.run(&[(Out, SYN, 100, 0)]);
+        assert_eq!(
+            refused.send(In, RST | ACK, 0, 0),
+            TcpTransition::Changed(TcpState::Reset)
+        );
+        // The tuple is used again.
+        assert_eq!(
+            refused.send(Out, SYN, 200, 0),
+            TcpTransition::Changed(TcpState::Syn)
+        );
+    }
+}
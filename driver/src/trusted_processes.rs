@@ -0,0 +1,137 @@
+//! Processes whose connections are accepted without asking user space.
+//!
+//! The user space service registers its own processes here. Their connections would otherwise be
+//! held until the service itself answers, which it can not do while it is the one waiting (update
+//! checks, resolvers, the tunnel). The callouts give them a permanent accept right away and send no
+//! event. A registration drops out as soon as its process exits.
+//!
+//! The list is generic over the process handle so it can be tested on the host.
+
+use alloc::vec::Vec;
+
+/// A registered process.
+pub trait TrustedProcess {
+    fn has_exited(&self) -> bool;
+}
+
+impl TrustedProcess for wdk::process::Process {
+    fn has_exited(&self) -> bool {
+        wdk::process::Process::has_exited(self)
+    }
+}
+
+pub struct TrustedProcesses<P> {
+    processes: Vec<(u64, P)>,
+}
+
+impl<P: TrustedProcess> TrustedProcesses<P> {
+    pub const fn new() -> Self {
+        Self {
+            processes: Vec::new(),
+        }
+    }
+
+    /// Registers the process, replacing an older registration with the same id. A process id is
+    /// only reused after the previous process with that id is gone.
+    pub fn add(&mut self, process_id: u64, process: P) {
+        match self
+            .processes
+            .iter_mut()
+            .find(|(pid, _)| *pid == process_id)
+        {
+            Some((_, existing)) => *existing = process,
+            None => self.processes.push((process_id, process)),
+        }
+    }
+
+    /// Removes the registration of the process. Returns false if there was none.
+    pub fn remove(&mut self, process_id: u64) -> bool {
+        let len = self.processes.len();
+        self.processes.retain(|(pid, _)| *pid != process_id);
+        return self.processes.len() != len;
+    }
+
+    /// Removes the processes that have exited.
+    pub fn remove_exited(&mut self) {
+        self.processes.retain(|(_, process)| !process.has_exited());
+    }
+
+    pub fn clear(&mut self) {
+        self.processes.clear();
+    }
+
+    /// Returns the registered process with the given id. It may have exited since.
+    pub fn find(&self, process_id: u64) -> Option<&P> {
+        self.processes
+            .iter()
+            .find(|(pid, _)| *pid == process_id)
+            .map(|(_, process)| process)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TrustedProcess, TrustedProcesses};
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+
+    #[derive(Clone)]
+    struct FakeProcess {
+        exited: Rc<Cell<bool>>,
+    }
+
+    impl FakeProcess {
+        fn new() -> Self {
+            Self {
+                exited: Rc::new(Cell::new(false)),
+            }
+        }
+    }
+
+    impl TrustedProcess for FakeProcess {
+        fn has_exited(&self) -> bool {
+            self.exited.get()
+        }
+    }
+
+    #[test]
+    fn registered_processes_are_found() {
+        let mut trusted = TrustedProcesses::new();
+        trusted.add(10, FakeProcess::new());
+        trusted.add(11, FakeProcess::new());
+        assert!(trusted.find(10).is_some());
+        assert!(trusted.find(11).is_some());
+        assert!(trusted.find(12).is_none());
+
+        assert!(trusted.remove(10));
+        assert!(!trusted.remove(10));
+        assert!(trusted.find(10).is_none());
+
+        trusted.clear();
+        assert!(trusted.find(11).is_none());
+    }
+
+    #[test]
+    fn exited_processes_drop_out() {
+        let mut trusted = TrustedProcesses::new();
+        let process = FakeProcess::new();
+        trusted.add(10, process.clone());
+        trusted.add(11, FakeProcess::new());
+
+        process.exited.set(true);
+        assert!(trusted.find(10).unwrap().has_exited());
+        trusted.remove_exited();
+        assert!(trusted.find(10).is_none());
+        assert!(trusted.find(11).is_some());
+    }
+
+    #[test]
+    fn add_replaces_the_old_registration() {
+        let mut trusted = TrustedProcesses::new();
+        let old = FakeProcess::new();
+        old.exited.set(true);
+        trusted.add(10, old);
+        trusted.add(10, FakeProcess::new());
+        assert!(!trusted.find(10).unwrap().has_exited());
+    }
+}
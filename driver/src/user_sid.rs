@@ -0,0 +1,111 @@
+//! String form of a user SID.
+//!
+//! The ALE layers carry the token of the process that owns a connection. The driver takes the user
+//! SID out of it in its binary form: revision, sub authority count, a 48-bit big endian identifier
+//! authority and the sub authorities as little endian u32. User space wants the usual string form
+//! `S-1-5-21-...`, which is built here once, when the connection is added.
+
+use alloc::string::String;
+use core::fmt::Write;
+
+// Revision (1 byte), sub authority count (1 byte) and identifier authority (6 bytes).
+const SID_HEADER_SIZE: usize = 8;
+// Windows does not allow more than 15 sub authorities.
+const SID_MAX_SUB_AUTHORITIES: usize = 15;
+
+/// Returns the string form of a binary SID. None if the SID is malformed.
+pub fn format_sid(sid: &[u8]) -> Option<String> {
+    if sid.len() < SID_HEADER_SIZE {
+        return None;
+    }
+    let revision = sid[0];
+    let sub_authority_count = sid[1] as usize;
+    if sub_authority_count > SID_MAX_SUB_AUTHORITIES
+        || sid.len() != SID_HEADER_SIZE + 4 * sub_authority_count
+    {
+        return None;
+    }
+
+    let mut authority: u64 = 0;
+    for byte in &sid[2..SID_HEADER_SIZE] {
+        authority = (authority << 8) | *byte as u64;
+    }
+
+    let mut result = String::new();
+    // Large authorities are written in hex, as ConvertSidToStringSid does.
+    if authority >= 1 << 32 {
+        let _ = write!(result, "S-{}-0x{:012X}", revision, authority);
+    } else {
+        let _ = write!(result, "S-{}-{}", revision, authority);
+    }
+    for sub_authority in sid[SID_HEADER_SIZE..].chunks_exact(4) {
+        let value = u32::from_le_bytes([
+            sub_authority[0],
+            sub_authority[1],
+            sub_authority[2],
+            sub_authority[3],
+        ]);
+        let _ = write!(result, "-{}", value);
+    }
+    return Some(result);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_sid;
+    use alloc::vec::Vec;
+
+    fn sid(authority: [u8; 6], sub_authorities: &[u32]) -> Vec<u8> {
+        let mut sid = Vec::new();
+        sid.push(1);
+        sid.push(sub_authorities.len() as u8);
+        sid.extend_from_slice(&authority);
+        for sub_authority in sub_authorities {
+            sid.extend_from_slice(&sub_authority.to_le_bytes());
+        }
+        return sid;
+    }
+
+    #[test]
+    fn well_known_and_user_sids() {
+        let nt_authority = [0, 0, 0, 0, 0, 5];
+        assert_eq!(
+            format_sid(&sid(nt_authority, &[18])).as_deref(),
+            Some("S-1-5-18")
+        );
+        assert_eq!(
+            format_sid(&sid(
+                nt_authority,
+                &[21, 3623811015, 3361044348, 30300820, 1013]
+            ))
+            .as_deref(),
+            Some("S-1-5-21-3623811015-3361044348-30300820-1013")
+        );
+        assert_eq!(
+            format_sid(&sid([0, 0, 0, 0, 0, 1], &[0])).as_deref(),
+            Some("S-1-1-0")
+        );
+    }
+
+    #[test]
+    fn large_authority_is_written_in_hex() {
+        assert_eq!(
+            format_sid(&sid([0, 1, 0, 0, 0, 0], &[1])).as_deref(),
+            Some("S-1-0x000100000000-1")
+        );
+    }
+
+    #[test]
+    fn malformed_sids_are_rejected() {
+        assert_eq!(format_sid(&[]), None);
+        assert_eq!(format_sid(&[1, 0, 0, 0]), None);
+        // Sub authority count does not match the length.
+        let mut truncated = sid([0, 0, 0, 0, 0, 5], &[21, 1]);
+        truncated.pop();
+        assert_eq!(format_sid(&truncated), None);
+        let mut count_too_large = sid([0, 0, 0, 0, 0, 5], &[]);
+        count_too_large[1] = 16;
+        count_too_large.extend_from_slice(&[0; 64]);
+        assert_eq!(format_sid(&count_too_large), None);
+    }
+}
@@ -0,0 +1,124 @@
+//! The verdict of a connection, and when it expires.
+//!
+//! Temporary verdicts are asked for on every packet, permanent ones last as long as the connection.
+//! A permanent verdict can also be given a time to live (`TimedVerdict` and `TimedUpdateById`
+//! commands): the ALE and packet layers apply it in the kernel like any permanent verdict until it
+//! expires, then the connection is back to `Undecided` and user space is asked again. That is an
+//! "allow for 10 minutes" answer that does not send every packet to user space.
+//!
+//! The verdict and its expiry are one atomic word, so a reader never pairs a new verdict with the
+//! expiry of the old one. The low byte is the verdict, the rest the expiry time in milliseconds,
+//! 0 for never.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use num_traits::FromPrimitive;
+
+use crate::connection::Verdict;
+
+pub struct VerdictState(AtomicU64);
+
+impl VerdictState {
+    pub const fn new(verdict: Verdict) -> Self {
+        Self(AtomicU64::new(verdict as u64))
+    }
+
+    /// Sets the verdict until `expiry_ms`, 0 for good. Replaces any earlier expiry.
+    pub fn set(&self, verdict: Verdict, expiry_ms: u64) {
+        self.0.store(pack(verdict, expiry_ms), Ordering::SeqCst);
+    }
+
+    /// Returns the verdict. An expired one is replaced with `Undecided` first. `now` is only
+    /// called when there is an expiry to check.
+    pub fn get(&self, now: impl FnOnce() -> u64) -> Verdict {
+        let state = self.0.load(Ordering::SeqCst);
+        let (verdict, expiry_ms) = unpack(state);
+        if expiry_ms == 0 || now() < expiry_ms {
+            return verdict;
+        }
+        // A verdict set in the meantime wins over the revert.
+        match self.0.compare_exchange(
+            state,
+            Verdict::Undecided as u64,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        ) {
+            Ok(_) => Verdict::Undecided,
+            Err(current) => unpack(current).0,
+        }
+    }
+}
+
+impl Clone for VerdictState {
+    fn clone(&self) -> Self {
+        Self(AtomicU64::new(self.0.load(Ordering::SeqCst)))
+    }
+}
+
+/// Returns the expiry time of `verdict` sent with a time to live of `ttl_secs` at `now`. Only
+/// permanent verdicts expire, the others are asked for again on the next packet anyway. 0 for
+/// never, which is also what a TTL of 0 means.
+pub fn expiry_ms(verdict: Verdict, ttl_secs: u32, now: u64) -> u64 {
+    let permanent = matches!(
+        verdict,
+        Verdict::PermanentAccept | Verdict::PermanentBlock | Verdict::PermanentDrop
+    );
+    if !permanent || ttl_secs == 0 {
+        return 0;
+    }
+    now + ttl_secs as u64 * 1000
+}
+
+fn pack(verdict: Verdict, expiry_ms: u64) -> u64 {
+    (expiry_ms << 8) | verdict as u64
+}
+
+fn unpack(state: u64) -> (Verdict, u64) {
+    let verdict = Verdict::from_u8(state as u8).unwrap_or(Verdict::Undecided);
+    (verdict, state >> 8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expiry_ms, VerdictState};
+    use crate::connection::Verdict;
+
+    #[test]
+    fn verdict_without_expiry_never_reverts() {
+        let state = VerdictState::new(Verdict::Undecided);
+        state.set(Verdict::PermanentAccept, 0);
+        let verdict = state.get(|| panic!("no expiry to check"));
+        assert!(matches!(verdict, Verdict::PermanentAccept));
+    }
+
+    #[test]
+    fn expired_verdict_reverts_to_undecided() {
+        // System time in milliseconds is far past 32 bits.
+        let now = 13_370_000_000_000;
+        let state = VerdictState::new(Verdict::Undecided);
+        state.set(Verdict::PermanentBlock, now + 600_000);
+        assert!(matches!(state.get(|| now), Verdict::PermanentBlock));
+        assert!(matches!(
+            state.get(|| now + 599_999),
+            Verdict::PermanentBlock
+        ));
+        assert!(matches!(state.get(|| now + 600_000), Verdict::Undecided));
+        // Reverted for good, the expiry is gone with it.
+        let verdict = state.get(|| panic!("no expiry to check"));
+        assert!(matches!(verdict, Verdict::Undecided));
+
+        // A new verdict replaces the expiry.
+        state.set(Verdict::PermanentDrop, now + 1000);
+        state.set(Verdict::PermanentAccept, 0);
+        assert!(matches!(state.get(|| now + 2000), Verdict::PermanentAccept));
+    }
+
+    #[test]
+    fn only_permanent_verdicts_expire() {
+        let now = 13_370_000_000_000;
+        assert_eq!(expiry_ms(Verdict::PermanentAccept, 600, now), now + 600_000);
+        assert_eq!(expiry_ms(Verdict::PermanentDrop, 1, now), now + 1000);
+        assert_eq!(expiry_ms(Verdict::PermanentBlock, 0, now), 0);
+        assert_eq!(expiry_ms(Verdict::Accept, 600, now), 0);
+        assert_eq!(expiry_ms(Verdict::RedirectTunnel, 600, now), 0);
+    }
+}
@@ -1,20 +1,47 @@
 package kext_interface
 
 import (
+	"bytes"
 	"encoding/binary"
 	"io"
 )
 
 const (
-	CommandShutdown              = 0
-	CommandVerdict               = 1
-	CommandUpdateV4              = 2
-	CommandUpdateV6              = 3
-	CommandClearCache            = 4
-	CommandGetConnectionsUpdate  = 5
-	CommandGetLogs               = 6
-	CommandPrintMemoryStats      = 7
-	CommandCleanEndedConnections = 8
+	CommandShutdown                = 0
+	CommandVerdict                 = 1
+	CommandUpdateV4                = 2
+	CommandUpdateV6                = 3
+	CommandClearCache              = 4
+	CommandGetConnectionsUpdate    = 5
+	CommandGetLogs                 = 6
+	CommandPrintMemoryStats        = 7
+	CommandCleanEndedConnections   = 8
+	CommandSetBindRedirectV4       = 9
+	CommandSetBindRedirectV6       = 10
+	CommandClearBindRedirects      = 11
+	CommandSetEgressRouteV4        = 12
+	CommandSetEgressRouteV6        = 13
+	CommandClearEgressRoutes       = 14
+	CommandSetKillSwitch           = 15
+	CommandAddKillSwitchPrefixV4   = 16
+	CommandAddKillSwitchPrefixV6   = 17
+	CommandClearKillSwitchPrefixes = 18
+	CommandSetMonitorMode          = 19
+	CommandRegisterTrustedProcess  = 20
+	CommandClearTrustedProcesses   = 21
+	CommandUpdateById              = 22
+	CommandSetCacheTimeouts        = 23
+	CommandSetCacheLimit           = 24
+	CommandUpdateByProcess         = 25
+	CommandGetProcessConnections   = 26
+	CommandUpsertV4                = 27
+	CommandUpsertV6                = 28
+	CommandBulkUpdateV4            = 29
+	CommandBulkUpdateV6            = 30
+	CommandTimedVerdict            = 31
+	CommandTimedUpdateById         = 32
+	CommandKillConnection          = 33
+	CommandSetTcpTracking          = 34
 )
 
 type KextVerdict uint8
@@ -41,6 +68,15 @@ type Verdict struct {
 	Verdict uint8
 }
 
+// TimedVerdict is the same as Verdict, but a permanent verdict reverts to undecided after TtlSecs
+// and the connection is sent for a decision again. A TtlSecs of 0 never expires.
+type TimedVerdict struct {
+	command uint8
+	Id      uint64
+	Verdict uint8
+	TtlSecs uint32
+}
+
 type RedirectV4 struct {
 	command       uint8
 	Id            uint64
@@ -73,8 +109,211 @@ type UpdateV6 struct {
 	RemoteAddress [16]byte
 	RemotePort    uint16
 	Verdict       uint8
+}
+
+// UpsertV4 is the same as UpdateV4, but adds the connection when the driver does not know it yet,
+// so it has its verdict before the first packet. Direction is 0 for outbound, 1 for inbound.
+// ProcessId 0 means unknown.
+type UpsertV4 struct {
+	command       uint8
+	Protocol      uint8
+	LocalAddress  [4]byte
+	LocalPort     uint16
+	RemoteAddress [4]byte
+	RemotePort    uint16
+	Direction     uint8
+	Verdict       uint8
+	ProcessId     uint64
+}
+
+// UpsertV6 is the ipv6 version of UpsertV4.
+type UpsertV6 struct {
+	command       uint8
+	Protocol      uint8
+	LocalAddress  [16]byte
+	LocalPort     uint16
+	RemoteAddress [16]byte
+	RemotePort    uint16
+	Direction     uint8
+	Verdict       uint8
+	ProcessId     uint64
+}
+
+// BulkUpdateV4 sets the verdict of every active connection that matches all the fields. A
+// Protocol, LocalPort or ProcessId of 0 matches any, as does a RemotePrefixLen of 0. Remote ports
+// match from RemotePortMin to RemotePortMax, a RemotePortMax of 0 matches any. The number of
+// matches is sent back as a BulkUpdateResult.
+type BulkUpdateV4 struct {
+	command         uint8
+	Protocol        uint8
+	LocalPort       uint16
+	RemoteAddress   [4]byte
+	RemotePrefixLen uint8
+	RemotePortMin   uint16
+	RemotePortMax   uint16
+	ProcessId       uint64
+	Verdict         uint8
+}
+
+// BulkUpdateV6 is the ipv6 version of BulkUpdateV4.
+type BulkUpdateV6 struct {
+	command         uint8
+	Protocol        uint8
+	LocalPort       uint16
+	RemoteAddress   [16]byte
+	RemotePrefixLen uint8
+	RemotePortMin   uint16
+	RemotePortMax   uint16
+	ProcessId       uint64
+	Verdict         uint8
 }
 
+// BindRedirectV4 pins the ipv4 sockets of a process (or of every process started from AppPath,
+// when ProcessId is 0) to LocalAddress. AppPath is in the form of ConnectionMetadata.ProcessPath.
+// An unspecified LocalAddress removes the rule.
+type BindRedirectV4 struct {
+	ProcessId    uint64
+	LocalAddress [4]byte
+	AppPath      string
+}
+
+// BindRedirectV6 is the ipv6 version of BindRedirectV4.
+type BindRedirectV6 struct {
+	ProcessId    uint64
+	LocalAddress [16]byte
+	AppPath      string
+}
+
+type bindRedirectV4Header struct {
+	command      uint8
+	ProcessId    uint64
+	LocalAddress [4]byte
+	AppPathLen   uint16
+}
+
+type bindRedirectV6Header struct {
+	command      uint8
+	ProcessId    uint64
+	LocalAddress [16]byte
+	AppPathLen   uint16
+}
+
+// EgressRouteV4 sends the ipv4 traffic of a process out of InterfaceIndex, with the local address
+// rewritten to SourceAddress. An unspecified SourceAddress removes the route.
+type EgressRouteV4 struct {
+	command           uint8
+	ProcessId         uint64
+	InterfaceIndex    uint32
+	SubInterfaceIndex uint32
+	SourceAddress     [4]byte
+}
+
+// EgressRouteV6 is the ipv6 version of EgressRouteV4.
+type EgressRouteV6 struct {
+	command           uint8
+	ProcessId         uint64
+	InterfaceIndex    uint32
+	SubInterfaceIndex uint32
+	SourceAddress     [16]byte
+}
+
+// KillSwitch turns the kill switch on or off. While it is on, the kext only lets through traffic on
+// TunnelInterfaceIndex, loopback traffic and traffic to the allowed prefixes.
+type KillSwitch struct {
+	command              uint8
+	Enabled              uint8
+	TunnelInterfaceIndex uint32
+}
+
+// KillSwitchPrefixV4 allows the traffic to a remote ipv4 prefix while the kill switch is on.
+type KillSwitchPrefixV4 struct {
+	command      uint8
+	Address      [4]byte
+	PrefixLength uint8
+}
+
+// KillSwitchPrefixV6 is the ipv6 version of KillSwitchPrefixV4.
+type KillSwitchPrefixV6 struct {
+	command      uint8
+	Address      [16]byte
+	PrefixLength uint8
+}
+
+// MonitorMode turns monitor-only mode on or off. In monitor-only mode the kext keeps tracking and
+// reporting connections, but never holds traffic for a verdict.
+type MonitorMode struct {
+	command uint8
+	Enabled uint8
+}
+
+// SetTcpTracking sets the options of the TCP state tracking. With StateEvents the kext sends a
+// TcpStateChange on every state change of a TCP connection, in Strict mode it drops the packets
+// that do not fit the state of their connection. 0 is off.
+type SetTcpTracking struct {
+	command     uint8
+	StateEvents uint8
+	Strict      uint8
+}
+
+type trustedProcessesHeader struct {
+	command      uint8
+	ProcessCount uint16
+}
+
+// UpdateById sets the verdict of the connection with the ConnectionId sent in its events.
+type UpdateById struct {
+	command      uint8
+	ConnectionId uint64
+	Verdict      uint8
+}
+
+// TimedUpdateById is the same as UpdateById, with a TTL like TimedVerdict.
+type TimedUpdateById struct {
+	command      uint8
+	ConnectionId uint64
+	Verdict      uint8
+	TtlSecs      uint32
+}
+
+// KillConnection ends the TCP connection with the ConnectionId sent in its events, with a RST to
+// both ends. The end event comes with EndReasonKilledByCommand.
+type KillConnection struct {
+	command      uint8
+	ConnectionId uint64
+}
+
+// SetCacheTimeouts sets how long the connection cache keeps its entries, in seconds. Ended
+// connections are removed after the retention, connections without traffic are reported as ended
+// after the idle expiry. 0 keeps the current value.
+type SetCacheTimeouts struct {
+	command               uint8
+	TcpEndedRetentionSecs uint32
+	TcpIdleExpirySecs     uint32
+	UdpEndedRetentionSecs uint32
+	UdpIdleExpirySecs     uint32
+}
+
+// SetCacheLimit sets the number of connections over which the connection cache evicts entries,
+// ended ones first, then the least recently used. 0 removes the limit.
+type SetCacheLimit struct {
+	command    uint8
+	MaxEntries uint32
+}
+
+// UpdateByProcess sets the verdict of every active connection of the process.
+type UpdateByProcess struct {
+	command   uint8
+	ProcessId uint64
+	Verdict   uint8
+}
+
+// processConnections requests the active connections of a process, see
+// SendGetProcessConnectionsCommand.
+type processConnections struct {
+	command   uint8
+	processId uint64
+}
+
 type ConnectionsUpdate struct {
 	command   uint8
 	timestamp uint64
@@ -90,6 +329,11 @@ func SendVerdictCommand(writer io.Writer, verdict Verdict) error {
 	return binary.Write(writer, binary.LittleEndian, verdict)
 }
 
+func SendTimedVerdictCommand(writer io.Writer, verdict TimedVerdict) error {
+	verdict.command = CommandTimedVerdict
+	return binary.Write(writer, binary.LittleEndian, verdict)
+}
+
 func SendUpdateV4Command(writer io.Writer, update UpdateV4) error {
 	update.command = CommandUpdateV4
 	return binary.Write(writer, binary.LittleEndian, update)
@@ -100,6 +344,26 @@ func SendUpdateV6Command(writer io.Writer, update UpdateV6) error {
 	return binary.Write(writer, binary.LittleEndian, update)
 }
 
+func SendUpsertV4Command(writer io.Writer, upsert UpsertV4) error {
+	upsert.command = CommandUpsertV4
+	return binary.Write(writer, binary.LittleEndian, upsert)
+}
+
+func SendUpsertV6Command(writer io.Writer, upsert UpsertV6) error {
+	upsert.command = CommandUpsertV6
+	return binary.Write(writer, binary.LittleEndian, upsert)
+}
+
+func SendBulkUpdateV4Command(writer io.Writer, update BulkUpdateV4) error {
+	update.command = CommandBulkUpdateV4
+	return binary.Write(writer, binary.LittleEndian, update)
+}
+
+func SendBulkUpdateV6Command(writer io.Writer, update BulkUpdateV6) error {
+	update.command = CommandBulkUpdateV6
+	return binary.Write(writer, binary.LittleEndian, update)
+}
+
 func SendClearCacheCommand(writer io.Writer) error {
 	_, err := writer.Write([]byte{CommandClearCache})
 	return err
@@ -127,3 +391,144 @@ func SendCleanEndedConnectionsCommand(writer io.Writer) error {
 	_, err := writer.Write([]byte{CommandCleanEndedConnections})
 	return err
 }
+
+func SendSetBindRedirectV4Command(writer io.Writer, rule BindRedirectV4) error {
+	header := bindRedirectV4Header{
+		command:      CommandSetBindRedirectV4,
+		ProcessId:    rule.ProcessId,
+		LocalAddress: rule.LocalAddress,
+		AppPathLen:   uint16(len(rule.AppPath)),
+	}
+	return writeWithTail(writer, header, []byte(rule.AppPath))
+}
+
+func SendSetBindRedirectV6Command(writer io.Writer, rule BindRedirectV6) error {
+	header := bindRedirectV6Header{
+		command:      CommandSetBindRedirectV6,
+		ProcessId:    rule.ProcessId,
+		LocalAddress: rule.LocalAddress,
+		AppPathLen:   uint16(len(rule.AppPath)),
+	}
+	return writeWithTail(writer, header, []byte(rule.AppPath))
+}
+
+func SendClearBindRedirectsCommand(writer io.Writer) error {
+	_, err := writer.Write([]byte{CommandClearBindRedirects})
+	return err
+}
+
+func SendSetEgressRouteV4Command(writer io.Writer, route EgressRouteV4) error {
+	route.command = CommandSetEgressRouteV4
+	return binary.Write(writer, binary.LittleEndian, route)
+}
+
+func SendSetEgressRouteV6Command(writer io.Writer, route EgressRouteV6) error {
+	route.command = CommandSetEgressRouteV6
+	return binary.Write(writer, binary.LittleEndian, route)
+}
+
+func SendClearEgressRoutesCommand(writer io.Writer) error {
+	_, err := writer.Write([]byte{CommandClearEgressRoutes})
+	return err
+}
+
+func SendSetKillSwitchCommand(writer io.Writer, killSwitch KillSwitch) error {
+	killSwitch.command = CommandSetKillSwitch
+	return binary.Write(writer, binary.LittleEndian, killSwitch)
+}
+
+func SendAddKillSwitchPrefixV4Command(writer io.Writer, prefix KillSwitchPrefixV4) error {
+	prefix.command = CommandAddKillSwitchPrefixV4
+	return binary.Write(writer, binary.LittleEndian, prefix)
+}
+
+func SendAddKillSwitchPrefixV6Command(writer io.Writer, prefix KillSwitchPrefixV6) error {
+	prefix.command = CommandAddKillSwitchPrefixV6
+	return binary.Write(writer, binary.LittleEndian, prefix)
+}
+
+func SendClearKillSwitchPrefixesCommand(writer io.Writer) error {
+	_, err := writer.Write([]byte{CommandClearKillSwitchPrefixes})
+	return err
+}
+
+func SendSetMonitorModeCommand(writer io.Writer, mode MonitorMode) error {
+	mode.command = CommandSetMonitorMode
+	return binary.Write(writer, binary.LittleEndian, mode)
+}
+
+func SendSetTcpTrackingCommand(writer io.Writer, tracking SetTcpTracking) error {
+	tracking.command = CommandSetTcpTracking
+	return binary.Write(writer, binary.LittleEndian, tracking)
+}
+
+// SendRegisterTrustedProcessCommand makes the kext accept the connections of the given processes
+// without asking. A process stays trusted until it exits.
+func SendRegisterTrustedProcessCommand(writer io.Writer, processIds []uint64) error {
+	header := trustedProcessesHeader{
+		command:      CommandRegisterTrustedProcess,
+		ProcessCount: uint16(len(processIds)),
+	}
+	tail := make([]byte, 8*len(processIds))
+	for i, id := range processIds {
+		binary.LittleEndian.PutUint64(tail[8*i:], id)
+	}
+	return writeWithTail(writer, header, tail)
+}
+
+func SendClearTrustedProcessesCommand(writer io.Writer) error {
+	_, err := writer.Write([]byte{CommandClearTrustedProcesses})
+	return err
+}
+
+func SendUpdateByIdCommand(writer io.Writer, update UpdateById) error {
+	update.command = CommandUpdateById
+	return binary.Write(writer, binary.LittleEndian, update)
+}
+
+func SendTimedUpdateByIdCommand(writer io.Writer, update TimedUpdateById) error {
+	update.command = CommandTimedUpdateById
+	return binary.Write(writer, binary.LittleEndian, update)
+}
+
+func SendKillConnectionCommand(writer io.Writer, kill KillConnection) error {
+	kill.command = CommandKillConnection
+	return binary.Write(writer, binary.LittleEndian, kill)
+}
+
+func SendSetCacheTimeoutsCommand(writer io.Writer, timeouts SetCacheTimeouts) error {
+	timeouts.command = CommandSetCacheTimeouts
+	return binary.Write(writer, binary.LittleEndian, timeouts)
+}
+
+func SendSetCacheLimitCommand(writer io.Writer, limit SetCacheLimit) error {
+	limit.command = CommandSetCacheLimit
+	return binary.Write(writer, binary.LittleEndian, limit)
+}
+
+// writeWithTail writes a fixed size command followed by its variable sized data in a single write,
+// the driver handles every write as a separate command.
+func writeWithTail(writer io.Writer, header any, tail []byte) error {
+	var buf bytes.Buffer
+	if err := binary.Write(&buf, binary.LittleEndian, header); err != nil {
+		return err
+	}
+	buf.Write(tail)
+	_, err := writer.Write(buf.Bytes())
+	return err
+}
+
+func SendUpdateByProcessCommand(writer io.Writer, update UpdateByProcess) error {
+	update.command = CommandUpdateByProcess
+	return binary.Write(writer, binary.LittleEndian, update)
+}
+
+// SendGetProcessConnectionsCommand requests the active connections of the process. They are sent
+// as connection update events, followed by a ConnectionUpdateEnd.
+func SendGetProcessConnectionsCommand(writer io.Writer, processId uint64) error {
+	req := processConnections{
+		command:   CommandGetProcessConnections,
+		processId: processId,
+	}
+	return binary.Write(writer, binary.LittleEndian, req)
+}
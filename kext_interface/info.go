@@ -17,6 +17,10 @@ const (
 	infoConnectionUpdateEventV4 byte = 5
 	infoConnectionUpdateEventV6 byte = 6
 	infoConnectionUpdateEnd     byte = 7
+	infoBindRedirectEventV4     byte = 8
+	infoBindRedirectEventV6     byte = 9
+	infoBulkUpdateResult        byte = 10
+	infoTcpStateChange          byte = 11
 )
 
 const (
@@ -49,9 +53,31 @@ type connectionV4Internal struct {
 	PayloadLayer uint8
 }
 
+// ConnectionMetadata holds the details the driver captures once per connection. They are sent as a
+// versioned block after the payload; fields of a newer version than MetadataVersion are left empty.
+type ConnectionMetadata struct {
+	MetadataVersion uint8
+	// ProcessPath is the lowercased image path of the process, with the volume device replaced by
+	// its drive letter where the driver knows it (`c:\windows\...`). Empty if unknown.
+	ProcessPath string
+	// UserSid is the SID of the user the process runs as (`S-1-5-21-...`). Empty if unknown.
+	UserSid string
+	// InterfaceIndex is the interface the connection uses: the egress route interface if the
+	// process has one.
+	InterfaceIndex    uint32
+	SubInterfaceIndex uint32
+	CompartmentId     uint32
+	// ParentProcessId is the process that started ProcessId. 0 if it started before the driver.
+	ParentProcessId uint64
+	// ConnectionId identifies the connection for as long as the kext runs, also after its 5-tuple
+	// is reused. 0 if the kext does not keep the connection. See SendUpdateByIdCommand.
+	ConnectionId uint64
+}
+
 type ConnectionV4 struct {
 	connectionV4Internal
 	Payload []byte
+	ConnectionMetadata
 }
 
 func (c *ConnectionV4) Compare(other *ConnectionV4) bool {
@@ -84,6 +110,7 @@ type connectionV6Internal struct {
 type ConnectionV6 struct {
 	connectionV6Internal
 	Payload []byte
+	ConnectionMetadata
 }
 
 func (c ConnectionV6) Compare(other *ConnectionV6) bool {
@@ -101,7 +128,32 @@ func (c *ConnectionV6) IsInfoOnly() bool {
 	return c.Id == INFO_ONLY_PACKET_ID
 }
 
-type ConnectionEndV4 struct {
+// EndReason tells why a connection ended. Make sure this is in sync with the Rust version.
+type EndReason uint8
+
+const (
+	// EndReasonEndpointClosure means the socket was closed.
+	EndReasonEndpointClosure EndReason = 0
+	// EndReasonResourceRelease means the local port was released, or its assignment was discarded.
+	EndReasonResourceRelease EndReason = 1
+	// EndReasonIdleTimeout means nothing was seen for a while and the kext dropped the connection.
+	// The counters are final.
+	EndReasonIdleTimeout EndReason = 2
+	EndReasonProcessExit EndReason = 3
+	EndReasonClearCache  EndReason = 4
+	// EndReasonShutdown is sent for the connections that were still active when the kext shut
+	// down, as far as they can still be read.
+	EndReasonShutdown EndReason = 5
+	// EndReasonEvicted means the connection cache was full and the connection was dropped from it.
+	EndReasonEvicted EndReason = 6
+	// EndReasonKilledByCommand means the connection was reset with a KillConnection command.
+	EndReasonKilledByCommand EndReason = 7
+	// EndReasonTcpClosed means both ends sent a FIN, EndReasonTcpReset that one sent a RST.
+	EndReasonTcpClosed EndReason = 8
+	EndReasonTcpReset  EndReason = 9
+)
+
+type connectionEndV4Internal struct {
 	ProcessId  uint64
 	Direction  byte
 	Protocol   byte
@@ -113,9 +165,15 @@ type ConnectionEndV4 struct {
 	RxPackets  uint64
 	TxBytes    uint64
 	TxPackets  uint64
+	EndReason  EndReason
 }
 
-type ConnectionEndV6 struct {
+type ConnectionEndV4 struct {
+	connectionEndV4Internal
+	ConnectionMetadata
+}
+
+type connectionEndV6Internal struct {
 	ProcessId  uint64
 	Direction  byte
 	Protocol   byte
@@ -127,9 +185,15 @@ type ConnectionEndV6 struct {
 	RxPackets  uint64
 	TxBytes    uint64
 	TxPackets  uint64
+	EndReason  EndReason
 }
 
-type ConnectionUpdateV4 struct {
+type ConnectionEndV6 struct {
+	connectionEndV6Internal
+	ConnectionMetadata
+}
+
+type connectionUpdateV4Internal struct {
 	Protocol   byte
 	LocalIp    [4]byte
 	RemoteIp   [4]byte
@@ -141,7 +205,12 @@ type ConnectionUpdateV4 struct {
 	TxPackets  uint64
 }
 
-type ConnectionUpdateV6 struct {
+type ConnectionUpdateV4 struct {
+	connectionUpdateV4Internal
+	ConnectionMetadata
+}
+
+type connectionUpdateV6Internal struct {
 	Protocol   byte
 	LocalIp    [16]byte
 	RemoteIp   [16]byte
@@ -152,9 +221,62 @@ type ConnectionUpdateV6 struct {
 	TxBytes    uint64
 	TxPackets  uint64
 }
+
+type ConnectionUpdateV6 struct {
+	connectionUpdateV6Internal
+	ConnectionMetadata
+}
 
 type ConnectionUpdateEnd struct{}
 
+// BindRedirectEventV4 reports a bind request of ProcessId that was moved from OriginalIp to
+// RedirectIp by a bind redirect rule.
+type BindRedirectEventV4 struct {
+	ProcessId  uint64
+	Protocol   byte
+	OriginalIp [4]byte
+	RedirectIp [4]byte
+	LocalPort  uint16
+}
+
+type BindRedirectEventV6 struct {
+	ProcessId  uint64
+	Protocol   byte
+	OriginalIp [16]byte
+	RedirectIp [16]byte
+	LocalPort  uint16
+}
+
+// BulkUpdateResult is the number of connections a SendBulkUpdateV4Command or
+// SendBulkUpdateV6Command matched.
+type BulkUpdateResult struct {
+	Matched uint64
+}
+
+// TcpState is the state of a TCP connection as seen by the kext. Make sure this is in sync with
+// the Rust version.
+type TcpState uint8
+
+const (
+	// TcpStateNew means no segment was seen yet.
+	TcpStateNew TcpState = 0
+	TcpStateSyn TcpState = 1
+	// TcpStateSynAck means the SYN was answered, the handshake waits for the last ACK.
+	TcpStateSynAck      TcpState = 2
+	TcpStateEstablished TcpState = 3
+	// TcpStateFinLocal and TcpStateFinRemote mean one end sent a FIN, TcpStateClosed that both did.
+	TcpStateFinLocal  TcpState = 4
+	TcpStateFinRemote TcpState = 5
+	TcpStateClosed    TcpState = 6
+	TcpStateReset     TcpState = 7
+)
+
+// TcpStateChange is sent when a TCP connection changes state, see SendSetTcpTrackingCommand.
+type TcpStateChange struct {
+	ConnectionId uint64
+	State        TcpState
+}
+
 func parseGenericInfo[T any](data []byte) (Info, error) {
 	var new T
 	reader := bytes.NewReader(data)
@@ -194,6 +316,11 @@ func parseConnectionV4(data []byte) (Info, error) {
 	if err != nil {
 		return nil, err
 	}
+
+	err = parseConnectionMetadata(reader, &conn.ConnectionMetadata)
+	if err != nil {
+		return nil, err
+	}
 	return conn, nil
 }
 
@@ -220,9 +347,135 @@ func parseConnectionV6(data []byte) (Info, error) {
 	if err != nil {
 		return nil, err
 	}
+
+	err = parseConnectionMetadata(reader, &conn.ConnectionMetadata)
+	if err != nil {
+		return nil, err
+	}
 	return conn, nil
 }
 
+// parseConnectionMetadata reads the metadata block that follows the payload. Older drivers do not
+// send it, and fields of versions newer than this reader are skipped.
+func parseConnectionMetadata(reader *bytes.Reader, metadata *ConnectionMetadata) error {
+	if reader.Len() == 0 {
+		return nil
+	}
+	err := binary.Read(reader, binary.LittleEndian, &metadata.MetadataVersion)
+	if err != nil {
+		return err
+	}
+
+	if metadata.MetadataVersion >= 1 {
+		metadata.ProcessPath, err = readMetadataString(reader)
+		if err != nil {
+			return err
+		}
+	}
+	if metadata.MetadataVersion >= 2 {
+		metadata.UserSid, err = readMetadataString(reader)
+		if err != nil {
+			return err
+		}
+	}
+	if metadata.MetadataVersion >= 3 {
+		for _, value := range []*uint32{&metadata.InterfaceIndex, &metadata.SubInterfaceIndex, &metadata.CompartmentId} {
+			err = binary.Read(reader, binary.LittleEndian, value)
+			if err != nil {
+				return err
+			}
+		}
+	}
+	if metadata.MetadataVersion >= 4 {
+		err = binary.Read(reader, binary.LittleEndian, &metadata.ParentProcessId)
+		if err != nil {
+			return err
+		}
+	}
+	if metadata.MetadataVersion >= 5 {
+		err = binary.Read(reader, binary.LittleEndian, &metadata.ConnectionId)
+		if err != nil {
+			return err
+		}
+	}
+	return nil
+}
+
+// readMetadataString reads a string of the metadata block: u16 length followed by UTF-8 bytes.
+func readMetadataString(reader *bytes.Reader) (string, error) {
+	var size uint16
+	err := binary.Read(reader, binary.LittleEndian, &size)
+	if err != nil {
+		return "", err
+	}
+	value := make([]byte, size)
+	err = binary.Read(reader, binary.LittleEndian, value)
+	if err != nil {
+		return "", err
+	}
+	return string(value), nil
+}
+
+func parseConnectionEndV4(data []byte) (Info, error) {
+	end := &ConnectionEndV4{}
+	reader := bytes.NewReader(data)
+
+	err := binary.Read(reader, binary.LittleEndian, &end.connectionEndV4Internal)
+	if err != nil {
+		return nil, err
+	}
+	err = parseConnectionMetadata(reader, &end.ConnectionMetadata)
+	if err != nil {
+		return nil, err
+	}
+	return end, nil
+}
+
+func parseConnectionEndV6(data []byte) (Info, error) {
+	end := &ConnectionEndV6{}
+	reader := bytes.NewReader(data)
+
+	err := binary.Read(reader, binary.LittleEndian, &end.connectionEndV6Internal)
+	if err != nil {
+		return nil, err
+	}
+	err = parseConnectionMetadata(reader, &end.ConnectionMetadata)
+	if err != nil {
+		return nil, err
+	}
+	return end, nil
+}
+
+func parseConnectionUpdateV4(data []byte) (Info, error) {
+	update := &ConnectionUpdateV4{}
+	reader := bytes.NewReader(data)
+
+	err := binary.Read(reader, binary.LittleEndian, &update.connectionUpdateV4Internal)
+	if err != nil {
+		return nil, err
+	}
+	err = parseConnectionMetadata(reader, &update.ConnectionMetadata)
+	if err != nil {
+		return nil, err
+	}
+	return update, nil
+}
+
+func parseConnectionUpdateV6(data []byte) (Info, error) {
+	update := &ConnectionUpdateV6{}
+	reader := bytes.NewReader(data)
+
+	err := binary.Read(reader, binary.LittleEndian, &update.connectionUpdateV6Internal)
+	if err != nil {
+		return nil, err
+	}
+	err = parseConnectionMetadata(reader, &update.ConnectionMetadata)
+	if err != nil {
+		return nil, err
+	}
+	return update, nil
+}
+
 func parseLogLine(data []byte) (Info, error) {
 	var logLine LogLine
 	reader := bytes.NewReader(data)
@@ -272,11 +525,15 @@ func RecvInfo(reader io.Reader) (Info, error) {
 		infoLogLine:                 parseLogLine,
 		infoConnectionIpv4:          parseConnectionV4,
 		infoConnectionIpv6:          parseConnectionV6,
-		infoConnectionEndEventV4:    parseGenericInfo[ConnectionEndV4],
-		infoConnectionEndEventV6:    parseGenericInfo[ConnectionEndV6],
-		infoConnectionUpdateEventV4: parseGenericInfo[ConnectionUpdateV4],
-		infoConnectionUpdateEventV6: parseGenericInfo[ConnectionUpdateV6],
+		infoConnectionEndEventV4:    parseConnectionEndV4,
+		infoConnectionEndEventV6:    parseConnectionEndV6,
+		infoConnectionUpdateEventV4: parseConnectionUpdateV4,
+		infoConnectionUpdateEventV6: parseConnectionUpdateV6,
 		infoConnectionUpdateEnd:     parseEmptyInfo[ConnectionUpdateEnd],
+		infoBindRedirectEventV4:     parseGenericInfo[BindRedirectEventV4],
+		infoBindRedirectEventV6:     parseGenericInfo[BindRedirectEventV6],
+		infoBulkUpdateResult:        parseGenericInfo[BulkUpdateResult],
+		infoTcpStateChange:          parseGenericInfo[TcpStateChange],
 	}
 
 	parser, ok := parsers[infoType]
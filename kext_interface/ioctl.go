@@ -4,6 +4,8 @@
 package kext_interface
 
 import (
+	"fmt"
+
 	"golang.org/x/sys/windows"
 )
 
@@ -34,3 +36,16 @@ func ReadVersion(file *KextFile) ([]uint8, error) {
 	}
 	return data, nil
 }
+
+// CheckVersion fails if the running driver speaks a different wire format than this package. The
+// major version changes with every change that old readers would misparse.
+func CheckVersion(file *KextFile) error {
+	version, err := ReadVersion(file)
+	if err != nil {
+		return err
+	}
+	if version[0] != InterfaceVersion[0] {
+		return fmt.Errorf("kext interface version mismatch: driver %v, expected %v", version, InterfaceVersion)
+	}
+	return nil
+}
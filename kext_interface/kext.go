@@ -215,7 +215,13 @@ func (s *KextService) OpenFile(readBufferSize int) (*KextFile, error) {
 		return nil, err
 	}
 
-	return &KextFile{handle: handle, buffer: make([]byte, readBufferSize)}, nil
+	file := &KextFile{handle: handle, buffer: make([]byte, readBufferSize)}
+	// Refuse a driver with a different wire format, its events would be misparsed.
+	if err := CheckVersion(file); err != nil {
+		_ = file.Close()
+		return nil, err
+	}
+	return file, nil
 }
 
 func CreateKextService(driverName string, driverPath string) (*KextService, error) {
@@ -13,6 +13,16 @@ func TestRustInfoFile(t *testing.T) {
 		panic(err)
 	}
 	defer file.Close() //nolint:errcheck
+	expectedMetadata := ConnectionMetadata{
+		MetadataVersion:   5,
+		ProcessPath:       "C:\\Windows\\System32\\svchost.exe",
+		UserSid:           "S-1-5-18",
+		InterfaceIndex:    11,
+		SubInterfaceIndex: 12,
+		CompartmentId:     1,
+		ParentProcessId:   13,
+		ConnectionId:      14,
+	}
 	for {
 		info, err := RecvInfo(file)
 		if err != nil {
@@ -50,6 +60,9 @@ func TestRustInfoFile(t *testing.T) {
 			if !v.Compare(&expected) {
 				t.Errorf("unexpected ConnectionV4: %+v\n", v)
 			}
+			if v.ConnectionMetadata != expectedMetadata {
+				t.Errorf("unexpected ConnectionV4 metadata: %+v\n", v.ConnectionMetadata)
+			}
 
 		case *ConnectionV6:
 			t.Logf("ConnectionV6: %+v\n", v)
@@ -70,21 +83,28 @@ func TestRustInfoFile(t *testing.T) {
 			if !v.Compare(&expected) {
 				t.Errorf("unexpected ConnectionV6: %+v\n", v)
 			}
+			if v.ConnectionMetadata != expectedMetadata {
+				t.Errorf("unexpected ConnectionV6 metadata: %+v\n", v.ConnectionMetadata)
+			}
 
 		case *ConnectionEndV4:
 			t.Logf("ConnectionEndV4: %+v\n", v)
 			expected := ConnectionEndV4{
-				ProcessId:  1,
-				Direction:  2,
-				Protocol:   3,
-				LocalIp:    [4]byte{1, 2, 3, 4},
-				RemoteIp:   [4]byte{2, 3, 4, 5},
-				LocalPort:  4,
-				RemotePort: 5,
-				RxBytes:    6,
-				RxPackets:  7,
-				TxBytes:    8,
-				TxPackets:  9,
+				connectionEndV4Internal: connectionEndV4Internal{
+					ProcessId:  1,
+					Direction:  2,
+					Protocol:   3,
+					LocalIp:    [4]byte{1, 2, 3, 4},
+					RemoteIp:   [4]byte{2, 3, 4, 5},
+					LocalPort:  4,
+					RemotePort: 5,
+					RxBytes:    6,
+					RxPackets:  7,
+					TxBytes:    8,
+					TxPackets:  9,
+					EndReason:  EndReasonIdleTimeout,
+				},
+				ConnectionMetadata: expectedMetadata,
 			}
 			if *v != expected {
 				t.Errorf("unexpected ConnectionEndV4: %+v\n", v)
@@ -92,17 +112,21 @@ func TestRustInfoFile(t *testing.T) {
 		case *ConnectionEndV6:
 			t.Logf("ConnectionEndV6: %+v\n", v)
 			expected := ConnectionEndV6{
-				ProcessId:  1,
-				Direction:  2,
-				Protocol:   3,
-				LocalIp:    [16]byte{1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16},
-				RemoteIp:   [16]byte{2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17},
-				LocalPort:  4,
-				RemotePort: 5,
-				RxBytes:    6,
-				RxPackets:  7,
-				TxBytes:    8,
-				TxPackets:  9,
+				connectionEndV6Internal: connectionEndV6Internal{
+					ProcessId:  1,
+					Direction:  2,
+					Protocol:   3,
+					LocalIp:    [16]byte{1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16},
+					RemoteIp:   [16]byte{2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17},
+					LocalPort:  4,
+					RemotePort: 5,
+					RxBytes:    6,
+					RxPackets:  7,
+					TxBytes:    8,
+					TxPackets:  9,
+					EndReason:  EndReasonIdleTimeout,
+				},
+				ConnectionMetadata: expectedMetadata,
 			}
 			if *v != expected {
 				t.Errorf("unexpected ConnectionEndV6: %+v\n", v)
@@ -110,15 +134,18 @@ func TestRustInfoFile(t *testing.T) {
 		case *ConnectionUpdateV4:
 			t.Logf("ConnectionUpdateV4: %+v\n", v)
 			expected := ConnectionUpdateV4{
-				Protocol:   1,
-				LocalIp:    [4]byte{1, 2, 3, 4},
-				RemoteIp:   [4]byte{2, 3, 4, 5},
-				LocalPort:  2,
-				RemotePort: 3,
-				RxBytes:    4,
-				RxPackets:  5,
-				TxBytes:    6,
-				TxPackets:  7,
+				connectionUpdateV4Internal: connectionUpdateV4Internal{
+					Protocol:   1,
+					LocalIp:    [4]byte{1, 2, 3, 4},
+					RemoteIp:   [4]byte{2, 3, 4, 5},
+					LocalPort:  2,
+					RemotePort: 3,
+					RxBytes:    4,
+					RxPackets:  5,
+					TxBytes:    6,
+					TxPackets:  7,
+				},
+				ConnectionMetadata: expectedMetadata,
 			}
 			if *v != expected {
 				t.Errorf("unexpected ConnectionUpdateV4: %+v\n", v)
@@ -126,15 +153,18 @@ func TestRustInfoFile(t *testing.T) {
 		case *ConnectionUpdateV6:
 			t.Logf("ConnectionUpdateV6: %+v\n", v)
 			expected := ConnectionUpdateV6{
-				Protocol:   1,
-				LocalIp:    [16]byte{1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16},
-				RemoteIp:   [16]byte{2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17},
-				LocalPort:  2,
-				RemotePort: 3,
-				RxBytes:    4,
-				RxPackets:  5,
-				TxBytes:    6,
-				TxPackets:  7,
+				connectionUpdateV6Internal: connectionUpdateV6Internal{
+					Protocol:   1,
+					LocalIp:    [16]byte{1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16},
+					RemoteIp:   [16]byte{2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17},
+					LocalPort:  2,
+					RemotePort: 3,
+					RxBytes:    4,
+					RxPackets:  5,
+					TxBytes:    6,
+					TxPackets:  7,
+				},
+				ConnectionMetadata: expectedMetadata,
 			}
 			if *v != expected {
 				t.Errorf("unexpected ConnectionUpdateV6: %+v\n", v)
@@ -142,6 +172,42 @@ func TestRustInfoFile(t *testing.T) {
 		case *ConnectionUpdateEnd:
 			t.Logf("ConnectionUpdateEnd: %+v\n", v)
 			// Empty struct
+		case *BindRedirectEventV4:
+			t.Logf("BindRedirectEventV4: %+v\n", v)
+			expected := BindRedirectEventV4{
+				ProcessId:  1,
+				Protocol:   2,
+				OriginalIp: [4]byte{1, 2, 3, 4},
+				RedirectIp: [4]byte{2, 3, 4, 5},
+				LocalPort:  3,
+			}
+			if *v != expected {
+				t.Errorf("unexpected BindRedirectEventV4: %+v\n", v)
+			}
+		case *BindRedirectEventV6:
+			t.Logf("BindRedirectEventV6: %+v\n", v)
+			expected := BindRedirectEventV6{
+				ProcessId:  1,
+				Protocol:   2,
+				OriginalIp: [16]byte{1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16},
+				RedirectIp: [16]byte{2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17},
+				LocalPort:  3,
+			}
+			if *v != expected {
+				t.Errorf("unexpected BindRedirectEventV6: %+v\n", v)
+			}
+		case *BulkUpdateResult:
+			t.Logf("BulkUpdateResult: %+v\n", v)
+			expected := BulkUpdateResult{Matched: 42}
+			if *v != expected {
+				t.Errorf("unexpected BulkUpdateResult: %+v\n", v)
+			}
+		case *TcpStateChange:
+			t.Logf("TcpStateChange: %+v\n", v)
+			expected := TcpStateChange{ConnectionId: 1234, State: TcpStateEstablished}
+			if *v != expected {
+				t.Errorf("unexpected TcpStateChange: %+v\n", v)
+			}
 		default:
 			t.Errorf("unexpected info type: %T\n", v)
 		}
@@ -163,6 +229,32 @@ func TestGenerateCommandFile(t *testing.T) {
 		CommandGetConnectionsUpdate,
 		CommandGetLogs,
 		CommandCleanEndedConnections,
+		CommandSetBindRedirectV4,
+		CommandSetBindRedirectV6,
+		CommandClearBindRedirects,
+		CommandSetEgressRouteV4,
+		CommandSetEgressRouteV6,
+		CommandClearEgressRoutes,
+		CommandSetKillSwitch,
+		CommandAddKillSwitchPrefixV4,
+		CommandAddKillSwitchPrefixV6,
+		CommandClearKillSwitchPrefixes,
+		CommandSetMonitorMode,
+		CommandRegisterTrustedProcess,
+		CommandClearTrustedProcesses,
+		CommandUpdateById,
+		CommandSetCacheTimeouts,
+		CommandSetCacheLimit,
+		CommandUpdateByProcess,
+		CommandGetProcessConnections,
+		CommandUpsertV4,
+		CommandUpsertV6,
+		CommandBulkUpdateV4,
+		CommandBulkUpdateV6,
+		CommandTimedVerdict,
+		CommandTimedUpdateById,
+		CommandKillConnection,
+		CommandSetTcpTracking,
 	}
 
 	selected := make([]byte, 5000)
@@ -182,6 +274,22 @@ func TestGenerateCommandFile(t *testing.T) {
 					Id:      1,
 					Verdict: 2,
 				})
+			}
+		case CommandTimedVerdict:
+			{
+				_ = SendTimedVerdictCommand(file, TimedVerdict{
+					Id:      1,
+					Verdict: 3,
+					TtlSecs: 600,
+				})
+			}
+		case CommandTimedUpdateById:
+			{
+				_ = SendTimedUpdateByIdCommand(file, TimedUpdateById{ConnectionId: 1234, Verdict: 5, TtlSecs: 600})
+			}
+		case CommandKillConnection:
+			{
+				_ = SendKillConnectionCommand(file, KillConnection{ConnectionId: 1234})
 			}
 		case CommandUpdateV4:
 			{
@@ -203,6 +311,58 @@ func TestGenerateCommandFile(t *testing.T) {
 					RemoteAddress: [16]byte{2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17},
 					RemotePort:    3,
 					Verdict:       4,
+				})
+			}
+		case CommandUpsertV4:
+			{
+				_ = SendUpsertV4Command(file, UpsertV4{
+					Protocol:      6,
+					LocalAddress:  [4]byte{1, 2, 3, 4},
+					LocalPort:     2,
+					RemoteAddress: [4]byte{2, 3, 4, 5},
+					RemotePort:    3,
+					Direction:     1,
+					Verdict:       3,
+					ProcessId:     4321,
+				})
+			}
+		case CommandUpsertV6:
+			{
+				_ = SendUpsertV6Command(file, UpsertV6{
+					Protocol:      6,
+					LocalAddress:  [16]byte{1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16},
+					LocalPort:     2,
+					RemoteAddress: [16]byte{2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17},
+					RemotePort:    3,
+					Direction:     1,
+					Verdict:       3,
+					ProcessId:     4321,
+				})
+			}
+		case CommandBulkUpdateV4:
+			{
+				_ = SendBulkUpdateV4Command(file, BulkUpdateV4{
+					Protocol:        6,
+					LocalPort:       2,
+					RemoteAddress:   [4]byte{10, 0, 0, 0},
+					RemotePrefixLen: 8,
+					RemotePortMin:   80,
+					RemotePortMax:   443,
+					ProcessId:       4321,
+					Verdict:         5,
+				})
+			}
+		case CommandBulkUpdateV6:
+			{
+				_ = SendBulkUpdateV6Command(file, BulkUpdateV6{
+					Protocol:        6,
+					LocalPort:       2,
+					RemoteAddress:   [16]byte{0xfd},
+					RemotePrefixLen: 8,
+					RemotePortMin:   80,
+					RemotePortMax:   443,
+					ProcessId:       4321,
+					Verdict:         5,
 				})
 			}
 		case CommandClearCache:
@@ -225,6 +385,114 @@ func TestGenerateCommandFile(t *testing.T) {
 			{
 				_ = SendCleanEndedConnectionsCommand(file)
 			}
+		case CommandSetBindRedirectV4:
+			{
+				_ = SendSetBindRedirectV4Command(file, BindRedirectV4{
+					ProcessId:    1,
+					LocalAddress: [4]byte{1, 2, 3, 4},
+					AppPath:      "C:\\test.exe",
+				})
+			}
+		case CommandSetBindRedirectV6:
+			{
+				_ = SendSetBindRedirectV6Command(file, BindRedirectV6{
+					ProcessId:    1,
+					LocalAddress: [16]byte{1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16},
+					AppPath:      "C:\\test.exe",
+				})
+			}
+		case CommandClearBindRedirects:
+			{
+				_ = SendClearBindRedirectsCommand(file)
+			}
+		case CommandSetEgressRouteV4:
+			{
+				_ = SendSetEgressRouteV4Command(file, EgressRouteV4{
+					ProcessId:         1,
+					InterfaceIndex:    2,
+					SubInterfaceIndex: 3,
+					SourceAddress:     [4]byte{1, 2, 3, 4},
+				})
+			}
+		case CommandSetEgressRouteV6:
+			{
+				_ = SendSetEgressRouteV6Command(file, EgressRouteV6{
+					ProcessId:         1,
+					InterfaceIndex:    2,
+					SubInterfaceIndex: 3,
+					SourceAddress:     [16]byte{1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16},
+				})
+			}
+		case CommandClearEgressRoutes:
+			{
+				_ = SendClearEgressRoutesCommand(file)
+			}
+		case CommandSetKillSwitch:
+			{
+				_ = SendSetKillSwitchCommand(file, KillSwitch{
+					Enabled:              1,
+					TunnelInterfaceIndex: 2,
+				})
+			}
+		case CommandAddKillSwitchPrefixV4:
+			{
+				_ = SendAddKillSwitchPrefixV4Command(file, KillSwitchPrefixV4{
+					Address:      [4]byte{1, 2, 3, 4},
+					PrefixLength: 24,
+				})
+			}
+		case CommandAddKillSwitchPrefixV6:
+			{
+				_ = SendAddKillSwitchPrefixV6Command(file, KillSwitchPrefixV6{
+					Address:      [16]byte{1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16},
+					PrefixLength: 64,
+				})
+			}
+		case CommandClearKillSwitchPrefixes:
+			{
+				_ = SendClearKillSwitchPrefixesCommand(file)
+			}
+		case CommandSetMonitorMode:
+			{
+				_ = SendSetMonitorModeCommand(file, MonitorMode{Enabled: 1})
+			}
+		case CommandSetTcpTracking:
+			{
+				_ = SendSetTcpTrackingCommand(file, SetTcpTracking{StateEvents: 1, Strict: 1})
+			}
+		case CommandRegisterTrustedProcess:
+			{
+				_ = SendRegisterTrustedProcessCommand(file, []uint64{1, 2, 3})
+			}
+		case CommandClearTrustedProcesses:
+			{
+				_ = SendClearTrustedProcessesCommand(file)
+			}
+		case CommandUpdateById:
+			{
+				_ = SendUpdateByIdCommand(file, UpdateById{ConnectionId: 1234, Verdict: 2})
+			}
+		case CommandSetCacheTimeouts:
+			{
+				_ = SendSetCacheTimeoutsCommand(file, SetCacheTimeouts{
+					TcpEndedRetentionSecs: 60,
+					TcpIdleExpirySecs:     3600,
+					UdpEndedRetentionSecs: 5,
+					UdpIdleExpirySecs:     30,
+				})
+			}
+		case CommandSetCacheLimit:
+			{
+				_ = SendSetCacheLimitCommand(file, SetCacheLimit{MaxEntries: 50000})
+			}
+		case CommandUpdateByProcess:
+			{
+				_ = SendUpdateByProcessCommand(file, UpdateByProcess{ProcessId: 4321, Verdict: 4})
+			}
+		case CommandGetProcessConnections:
+			{
+				_ = SendGetProcessConnectionsCommand(file, 4321)
+			}
 		}
 	}
 }
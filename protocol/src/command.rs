@@ -7,15 +7,41 @@ use num_derive::FromPrimitive;
 #[derive(Clone, Copy, FromPrimitive)]
 #[rustfmt::skip]
 pub enum CommandType {
-    Shutdown              = 0,
-    Verdict               = 1,
-    UpdateV4              = 2,
-    UpdateV6              = 3,
-    ClearCache            = 4,
-    GetConnectionsUpdate  = 5,
-    GetLogs               = 6,
-    PrintMemoryStats      = 7,
-    CleanEndedConnections = 8,
+    Shutdown                = 0,
+    Verdict                 = 1,
+    UpdateV4                = 2,
+    UpdateV6                = 3,
+    ClearCache              = 4,
+    GetConnectionsUpdate    = 5,
+    GetLogs                 = 6,
+    PrintMemoryStats        = 7,
+    CleanEndedConnections   = 8,
+    SetBindRedirectV4       = 9,
+    SetBindRedirectV6       = 10,
+    ClearBindRedirects      = 11,
+    SetEgressRouteV4        = 12,
+    SetEgressRouteV6        = 13,
+    ClearEgressRoutes       = 14,
+    SetKillSwitch           = 15,
+    AddKillSwitchPrefixV4   = 16,
+    AddKillSwitchPrefixV6   = 17,
+    ClearKillSwitchPrefixes = 18,
+    SetMonitorMode          = 19,
+    RegisterTrustedProcess  = 20,
+    ClearTrustedProcesses   = 21,
+    UpdateById              = 22,
+    SetCacheTimeouts        = 23,
+    SetCacheLimit           = 24,
+    UpdateByProcess         = 25,
+    GetProcessConnections   = 26,
+    UpsertV4                = 27,
+    UpsertV6                = 28,
+    BulkUpdateV4            = 29,
+    BulkUpdateV6            = 30,
+    TimedVerdict            = 31,
+    TimedUpdateById         = 32,
+    KillConnection          = 33,
+    SetTcpTracking          = 34,
 }
 
 #[repr(C, packed)]
@@ -31,6 +57,16 @@ pub struct Verdict {
     pub verdict: u8,
 }
 
+// Same as Verdict, but a permanent verdict reverts to undecided after ttl_secs and user space is
+// asked again. A TTL of 0 never expires.
+#[repr(C, packed)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct TimedVerdict {
+    pub id: u64,
+    pub verdict: u8,
+    pub ttl_secs: u32,
+}
+
 #[repr(C, packed)]
 #[derive(Debug, PartialEq, Eq)]
 pub struct UpdateV4 {
@@ -53,12 +89,221 @@ pub struct UpdateV6 {
     pub verdict: u8,
 }
 
+// Same as UpdateV4/UpdateV6, but adds the connection when it is not in the cache yet, so it has
+// its verdict before the first packet. Direction is 0 for outbound, 1 for inbound. A process id
+// of 0 means unknown.
+#[repr(C, packed)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct UpsertV4 {
+    pub protocol: u8,
+    pub local_address: [u8; 4],
+    pub local_port: u16,
+    pub remote_address: [u8; 4],
+    pub remote_port: u16,
+    pub direction: u8,
+    pub verdict: u8,
+    pub process_id: u64,
+}
+
+#[repr(C, packed)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct UpsertV6 {
+    pub protocol: u8,
+    pub local_address: [u8; 16],
+    pub local_port: u16,
+    pub remote_address: [u8; 16],
+    pub remote_port: u16,
+    pub direction: u8,
+    pub verdict: u8,
+    pub process_id: u64,
+}
+
+// Sets the verdict of every active connection that matches all the fields. A protocol, local port
+// or process id of 0 matches any, as does a remote prefix length of 0. Remote ports match from
+// remote_port_min to remote_port_max, a max of 0 matches any. The number of matches is sent back
+// in a BulkUpdateResult event.
+#[repr(C, packed)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct BulkUpdateV4 {
+    pub protocol: u8,
+    pub local_port: u16,
+    pub remote_address: [u8; 4],
+    pub remote_prefix_len: u8,
+    pub remote_port_min: u16,
+    pub remote_port_max: u16,
+    pub process_id: u64,
+    pub verdict: u8,
+}
+
+#[repr(C, packed)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct BulkUpdateV6 {
+    pub protocol: u8,
+    pub local_port: u16,
+    pub remote_address: [u8; 16],
+    pub remote_prefix_len: u8,
+    pub remote_port_min: u16,
+    pub remote_port_max: u16,
+    pub process_id: u64,
+    pub verdict: u8,
+}
+
+// Same as UpdateV4/UpdateV6, for the connection with the id sent in its events.
+#[repr(C, packed)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct UpdateById {
+    pub connection_id: u64,
+    pub verdict: u8,
+}
+
+// Same as UpdateById, with a TTL like TimedVerdict.
+#[repr(C, packed)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct TimedUpdateById {
+    pub connection_id: u64,
+    pub verdict: u8,
+    pub ttl_secs: u32,
+}
+
+// Ends the TCP connection with the id sent in its events, with a RST to both ends.
+#[repr(C, packed)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct KillConnection {
+    pub connection_id: u64,
+}
+
+// How long the connection cache keeps its entries, in seconds. Ended entries are removed after
+// the retention, entries without traffic are reported as ended after the idle expiry. 0 keeps the
+// current value.
+#[repr(C, packed)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct SetCacheTimeouts {
+    pub tcp_ended_retention_secs: u32,
+    pub tcp_idle_expiry_secs: u32,
+    pub udp_ended_retention_secs: u32,
+    pub udp_idle_expiry_secs: u32,
+}
+
+// Number of connections over which the connection cache evicts entries. 0 removes the limit.
+#[repr(C, packed)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct SetCacheLimit {
+    pub max_entries: u32,
+}
+
+// Same as UpdateById, for every active connection of the process.
+#[repr(C, packed)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct UpdateByProcess {
+    pub process_id: u64,
+    pub verdict: u8,
+}
+
+// Requests the active connections of the process. They are sent as connection update events,
+// followed by a ConnectionUpdateEnd.
+#[repr(C, packed)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct ProcessConnections {
+    pub process_id: u64,
+}
+
 #[repr(C, packed)]
 #[derive(Debug, PartialEq, Eq)]
 pub struct ConnectionsUpdate {
     pub timestamp: u64,
 }
 
+// Followed by `app_path_len` bytes of the UTF-8 application path, normalised like the process path
+// in the connection events (`c:\windows\...`). A process id of 0 selects the rule by application
+// path instead. An unspecified local address removes the rule.
+#[repr(C, packed)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct BindRedirectV4 {
+    pub process_id: u64,
+    pub local_address: [u8; 4],
+    pub app_path_len: u16,
+}
+
+// Same as BindRedirectV4, for ipv6 sockets.
+#[repr(C, packed)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct BindRedirectV6 {
+    pub process_id: u64,
+    pub local_address: [u8; 16],
+    pub app_path_len: u16,
+}
+
+// Sends the traffic of a process out of the given interface, with the local address rewritten to
+// `source_address`. An unspecified source address removes the route.
+#[repr(C, packed)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct EgressRouteV4 {
+    pub process_id: u64,
+    pub interface_index: u32,
+    pub sub_interface_index: u32,
+    pub source_address: [u8; 4],
+}
+
+// Same as EgressRouteV4, for ipv6 connections.
+#[repr(C, packed)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct EgressRouteV6 {
+    pub process_id: u64,
+    pub interface_index: u32,
+    pub sub_interface_index: u32,
+    pub source_address: [u8; 16],
+}
+
+// Turns the kill switch on or off. While it is on, only traffic on the tunnel interface, loopback
+// traffic and traffic to the allowed prefixes passes.
+#[repr(C, packed)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct KillSwitch {
+    pub enabled: u8,
+    pub tunnel_interface_index: u32,
+}
+
+// Allows the traffic to a remote prefix (usually the LAN) while the kill switch is on.
+#[repr(C, packed)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct KillSwitchPrefixV4 {
+    pub address: [u8; 4],
+    pub prefix_length: u8,
+}
+
+// Same as KillSwitchPrefixV4, for ipv6 prefixes.
+#[repr(C, packed)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct KillSwitchPrefixV6 {
+    pub address: [u8; 16],
+    pub prefix_length: u8,
+}
+
+// Turns monitor-only mode on or off. In monitor-only mode connections are still tracked and
+// reported, but traffic is never held for a verdict.
+#[repr(C, packed)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct MonitorMode {
+    pub enabled: u8,
+}
+
+// Options of the TCP state tracking: a TcpStateChange event on every state change, and strict
+// mode, which drops the packets that do not fit the state of their connection. 0 is off.
+#[repr(C, packed)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct SetTcpTracking {
+    pub state_events: u8,
+    pub strict: u8,
+}
+
+// Followed by `process_count` process ids (u64 each). Connections of these processes are accepted
+// without asking user space, until the process exits.
+#[repr(C, packed)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct TrustedProcesses {
+    pub process_count: u16,
+}
+
 pub fn parse_type(bytes: &[u8]) -> Option<CommandType> {
     FromPrimitive::from_u8(bytes[0])
 }
@@ -67,6 +312,10 @@ pub fn parse_verdict(bytes: &[u8]) -> &Verdict {
     as_type(bytes)
 }
 
+pub fn parse_timed_verdict(bytes: &[u8]) -> &TimedVerdict {
+    as_type(bytes)
+}
+
 pub fn parse_update_v4(bytes: &[u8]) -> &UpdateV4 {
     as_type(bytes)
 }
@@ -75,16 +324,126 @@ pub fn parse_update_v6(bytes: &[u8]) -> &UpdateV6 {
     as_type(bytes)
 }
 
+pub fn parse_upsert_v4(bytes: &[u8]) -> &UpsertV4 {
+    as_type(bytes)
+}
+
+pub fn parse_upsert_v6(bytes: &[u8]) -> &UpsertV6 {
+    as_type(bytes)
+}
+
+pub fn parse_bulk_update_v4(bytes: &[u8]) -> &BulkUpdateV4 {
+    as_type(bytes)
+}
+
+pub fn parse_bulk_update_v6(bytes: &[u8]) -> &BulkUpdateV6 {
+    as_type(bytes)
+}
+
+pub fn parse_update_by_id(bytes: &[u8]) -> &UpdateById {
+    as_type(bytes)
+}
+
+pub fn parse_timed_update_by_id(bytes: &[u8]) -> &TimedUpdateById {
+    as_type(bytes)
+}
+
+pub fn parse_kill_connection(bytes: &[u8]) -> &KillConnection {
+    as_type(bytes)
+}
+
+pub fn parse_set_cache_timeouts(bytes: &[u8]) -> &SetCacheTimeouts {
+    as_type(bytes)
+}
+
+pub fn parse_set_cache_limit(bytes: &[u8]) -> &SetCacheLimit {
+    as_type(bytes)
+}
+
+pub fn parse_update_by_process(bytes: &[u8]) -> &UpdateByProcess {
+    as_type(bytes)
+}
+
+pub fn parse_process_connections(bytes: &[u8]) -> &ProcessConnections {
+    as_type(bytes)
+}
+
 pub fn parse_update_info(bytes: &[u8]) -> &ConnectionsUpdate {
     as_type(bytes)
 }
 
+pub fn parse_bind_redirect_v4(bytes: &[u8]) -> (&BindRedirectV4, &[u8]) {
+    let rule: &BindRedirectV4 = as_type(bytes);
+    let app_path = as_tail(
+        bytes,
+        core::mem::size_of::<BindRedirectV4>(),
+        rule.app_path_len as usize,
+    );
+    (rule, app_path)
+}
+
+pub fn parse_bind_redirect_v6(bytes: &[u8]) -> (&BindRedirectV6, &[u8]) {
+    let rule: &BindRedirectV6 = as_type(bytes);
+    let app_path = as_tail(
+        bytes,
+        core::mem::size_of::<BindRedirectV6>(),
+        rule.app_path_len as usize,
+    );
+    (rule, app_path)
+}
+
+pub fn parse_egress_route_v4(bytes: &[u8]) -> &EgressRouteV4 {
+    as_type(bytes)
+}
+
+pub fn parse_egress_route_v6(bytes: &[u8]) -> &EgressRouteV6 {
+    as_type(bytes)
+}
+
+pub fn parse_kill_switch(bytes: &[u8]) -> &KillSwitch {
+    as_type(bytes)
+}
+
+pub fn parse_kill_switch_prefix_v4(bytes: &[u8]) -> &KillSwitchPrefixV4 {
+    as_type(bytes)
+}
+
+pub fn parse_kill_switch_prefix_v6(bytes: &[u8]) -> &KillSwitchPrefixV6 {
+    as_type(bytes)
+}
+
+pub fn parse_monitor_mode(bytes: &[u8]) -> &MonitorMode {
+    as_type(bytes)
+}
+
+pub fn parse_set_tcp_tracking(bytes: &[u8]) -> &SetTcpTracking {
+    as_type(bytes)
+}
+
+pub fn parse_trusted_processes(bytes: &[u8]) -> impl Iterator<Item = u64> + '_ {
+    let header: &TrustedProcesses = as_type(bytes);
+    let process_ids = as_tail(
+        bytes,
+        core::mem::size_of::<TrustedProcesses>(),
+        header.process_count as usize * core::mem::size_of::<u64>(),
+    );
+    process_ids
+        .chunks_exact(core::mem::size_of::<u64>())
+        .map(|id| u64::from_le_bytes([id[0], id[1], id[2], id[3], id[4], id[5], id[6], id[7]]))
+}
+
 fn as_type<T>(bytes: &[u8]) -> &T {
     let ptr: *const u8 = &bytes[0];
     let t_ptr: *const T = ptr as _;
     unsafe { t_ptr.as_ref().unwrap() }
 }
 
+// Returns the variable sized data that follows a fixed size command. Empty if the buffer is shorter
+// than the command claims.
+fn as_tail(bytes: &[u8], offset: usize, len: usize) -> &[u8] {
+    bytes.get(offset..offset + len).unwrap_or(&[])
+}
+
 #[cfg(test)]
 use std::fs::File;
 #[cfg(test)]
@@ -94,6 +453,18 @@ use std::mem::size_of;
 #[cfg(test)]
 use std::panic;
 
+// Reads a command that ends with a length prefixed tail (u16, the last field of the fixed part)
+// and returns the fixed part followed by the tail.
+#[cfg(test)]
+fn read_with_tail(file: &mut File, fixed_size: usize) -> Vec<u8> {
+    let mut buf = vec![0; fixed_size];
+    file.read_exact(&mut buf).unwrap();
+    let tail_len = u16::from_le_bytes([buf[fixed_size - 2], buf[fixed_size - 1]]) as usize;
+    buf.resize(fixed_size + tail_len, 0);
+    file.read_exact(&mut buf[fixed_size..]).unwrap();
+    buf
+}
+
 #[test]
 fn test_go_command_file() {
     let mut file = File::open("../kext_interface/go_command_test.bin").unwrap();
@@ -115,6 +486,52 @@ fn test_go_command_file() {
 
                     assert_eq!(parse_verdict(&buf), &Verdict { id: 1, verdict: 2 })
                 }
+                CommandType::TimedVerdict => {
+                    let mut buf = [0; size_of::<TimedVerdict>()];
+                    let bytes_count = file.read(&mut buf).unwrap();
+                    if bytes_count != size_of::<TimedVerdict>() {
+                        panic!("unexpected bytes count")
+                    }
+
+                    assert_eq!(
+                        parse_timed_verdict(&buf),
+                        &TimedVerdict {
+                            id: 1,
+                            verdict: 3,
+                            ttl_secs: 600,
+                        }
+                    )
+                }
+                CommandType::TimedUpdateById => {
+                    let mut buf = [0; size_of::<TimedUpdateById>()];
+                    let bytes_count = file.read(&mut buf).unwrap();
+                    if bytes_count != size_of::<TimedUpdateById>() {
+                        panic!("unexpected bytes count")
+                    }
+
+                    assert_eq!(
+                        parse_timed_update_by_id(&buf),
+                        &TimedUpdateById {
+                            connection_id: 1234,
+                            verdict: 5,
+                            ttl_secs: 600,
+                        }
+                    )
+                }
+                CommandType::KillConnection => {
+                    let mut buf = [0; size_of::<KillConnection>()];
+                    let bytes_count = file.read(&mut buf).unwrap();
+                    if bytes_count != size_of::<KillConnection>() {
+                        panic!("unexpected bytes count")
+                    }
+
+                    assert_eq!(
+                        parse_kill_connection(&buf),
+                        &KillConnection {
+                            connection_id: 1234,
+                        }
+                    )
+                }
                 CommandType::UpdateV4 => {
                     let mut buf = [0; size_of::<UpdateV4>()];
                     let bytes_count = file.read(&mut buf).unwrap();
@@ -155,10 +572,311 @@ fn test_go_command_file() {
                         }
                     )
                 }
+                CommandType::UpsertV4 => {
+                    let mut buf = [0; size_of::<UpsertV4>()];
+                    let bytes_count = file.read(&mut buf).unwrap();
+                    if bytes_count != size_of::<UpsertV4>() {
+                        panic!("unexpected bytes count")
+                    }
+
+                    assert_eq!(
+                        parse_upsert_v4(&buf),
+                        &UpsertV4 {
+                            protocol: 6,
+                            local_address: [1, 2, 3, 4],
+                            local_port: 2,
+                            remote_address: [2, 3, 4, 5],
+                            remote_port: 3,
+                            direction: 1,
+                            verdict: 3,
+                            process_id: 4321,
+                        }
+                    )
+                }
+                CommandType::UpsertV6 => {
+                    let mut buf = [0; size_of::<UpsertV6>()];
+                    let bytes_count = file.read(&mut buf).unwrap();
+                    if bytes_count != size_of::<UpsertV6>() {
+                        panic!("unexpected bytes count")
+                    }
+
+                    assert_eq!(
+                        parse_upsert_v6(&buf),
+                        &UpsertV6 {
+                            protocol: 6,
+                            local_address: [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16],
+                            local_port: 2,
+                            remote_address: [
+                                2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17
+                            ],
+                            remote_port: 3,
+                            direction: 1,
+                            verdict: 3,
+                            process_id: 4321,
+                        }
+                    )
+                }
+                CommandType::BulkUpdateV4 => {
+                    let mut buf = [0; size_of::<BulkUpdateV4>()];
+                    let bytes_count = file.read(&mut buf).unwrap();
+                    if bytes_count != size_of::<BulkUpdateV4>() {
+                        panic!("unexpected bytes count")
+                    }
+
+                    assert_eq!(
+                        parse_bulk_update_v4(&buf),
+                        &BulkUpdateV4 {
+                            protocol: 6,
+                            local_port: 2,
+                            remote_address: [10, 0, 0, 0],
+                            remote_prefix_len: 8,
+                            remote_port_min: 80,
+                            remote_port_max: 443,
+                            process_id: 4321,
+                            verdict: 5,
+                        }
+                    )
+                }
+                CommandType::BulkUpdateV6 => {
+                    let mut buf = [0; size_of::<BulkUpdateV6>()];
+                    let bytes_count = file.read(&mut buf).unwrap();
+                    if bytes_count != size_of::<BulkUpdateV6>() {
+                        panic!("unexpected bytes count")
+                    }
+
+                    assert_eq!(
+                        parse_bulk_update_v6(&buf),
+                        &BulkUpdateV6 {
+                            protocol: 6,
+                            local_port: 2,
+                            remote_address: [0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                            remote_prefix_len: 8,
+                            remote_port_min: 80,
+                            remote_port_max: 443,
+                            process_id: 4321,
+                            verdict: 5,
+                        }
+                    )
+                }
                 CommandType::ClearCache => {}
                 CommandType::GetLogs => {}
                 CommandType::PrintMemoryStats => {}
                 CommandType::CleanEndedConnections => {}
+                CommandType::SetBindRedirectV4 => {
+                    let buf = read_with_tail(&mut file, size_of::<BindRedirectV4>());
+                    let (rule, app_path) = parse_bind_redirect_v4(&buf);
+                    assert_eq!(
+                        rule,
+                        &BindRedirectV4 {
+                            process_id: 1,
+                            local_address: [1, 2, 3, 4],
+                            app_path_len: 11,
+                        }
+                    );
+                    assert_eq!(app_path, b"C:\\test.exe");
+                }
+                CommandType::SetBindRedirectV6 => {
+                    let buf = read_with_tail(&mut file, size_of::<BindRedirectV6>());
+                    let (rule, app_path) = parse_bind_redirect_v6(&buf);
+                    assert_eq!(
+                        rule,
+                        &BindRedirectV6 {
+                            process_id: 1,
+                            local_address: [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16],
+                            app_path_len: 11,
+                        }
+                    );
+                    assert_eq!(app_path, b"C:\\test.exe");
+                }
+                CommandType::ClearBindRedirects => {}
+                CommandType::SetEgressRouteV4 => {
+                    let mut buf = [0; size_of::<EgressRouteV4>()];
+                    let bytes_count = file.read(&mut buf).unwrap();
+                    if bytes_count != size_of::<EgressRouteV4>() {
+                        panic!("unexpected bytes count")
+                    }
+
+                    assert_eq!(
+                        parse_egress_route_v4(&buf),
+                        &EgressRouteV4 {
+                            process_id: 1,
+                            interface_index: 2,
+                            sub_interface_index: 3,
+                            source_address: [1, 2, 3, 4],
+                        }
+                    )
+                }
+                CommandType::SetEgressRouteV6 => {
+                    let mut buf = [0; size_of::<EgressRouteV6>()];
+                    let bytes_count = file.read(&mut buf).unwrap();
+                    if bytes_count != size_of::<EgressRouteV6>() {
+                        panic!("unexpected bytes count")
+                    }
+
+                    assert_eq!(
+                        parse_egress_route_v6(&buf),
+                        &EgressRouteV6 {
+                            process_id: 1,
+                            interface_index: 2,
+                            sub_interface_index: 3,
+                            source_address: [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16],
+                        }
+                    )
+                }
+                CommandType::ClearEgressRoutes => {}
+                CommandType::SetKillSwitch => {
+                    let mut buf = [0; size_of::<KillSwitch>()];
+                    let bytes_count = file.read(&mut buf).unwrap();
+                    if bytes_count != size_of::<KillSwitch>() {
+                        panic!("unexpected bytes count")
+                    }
+
+                    assert_eq!(
+                        parse_kill_switch(&buf),
+                        &KillSwitch {
+                            enabled: 1,
+                            tunnel_interface_index: 2,
+                        }
+                    )
+                }
+                CommandType::AddKillSwitchPrefixV4 => {
+                    let mut buf = [0; size_of::<KillSwitchPrefixV4>()];
+                    let bytes_count = file.read(&mut buf).unwrap();
+                    if bytes_count != size_of::<KillSwitchPrefixV4>() {
+                        panic!("unexpected bytes count")
+                    }
+
+                    assert_eq!(
+                        parse_kill_switch_prefix_v4(&buf),
+                        &KillSwitchPrefixV4 {
+                            address: [1, 2, 3, 4],
+                            prefix_length: 24,
+                        }
+                    )
+                }
+                CommandType::AddKillSwitchPrefixV6 => {
+                    let mut buf = [0; size_of::<KillSwitchPrefixV6>()];
+                    let bytes_count = file.read(&mut buf).unwrap();
+                    if bytes_count != size_of::<KillSwitchPrefixV6>() {
+                        panic!("unexpected bytes count")
+                    }
+
+                    assert_eq!(
+                        parse_kill_switch_prefix_v6(&buf),
+                        &KillSwitchPrefixV6 {
+                            address: [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16],
+                            prefix_length: 64,
+                        }
+                    )
+                }
+                CommandType::ClearKillSwitchPrefixes => {}
+                CommandType::SetMonitorMode => {
+                    let mut buf = [0; size_of::<MonitorMode>()];
+                    let bytes_count = file.read(&mut buf).unwrap();
+                    if bytes_count != size_of::<MonitorMode>() {
+                        panic!("unexpected bytes count")
+                    }
+
+                    assert_eq!(parse_monitor_mode(&buf), &MonitorMode { enabled: 1 })
+                }
+                CommandType::SetTcpTracking => {
+                    let mut buf = [0; size_of::<SetTcpTracking>()];
+                    let bytes_count = file.read(&mut buf).unwrap();
+                    if bytes_count != size_of::<SetTcpTracking>() {
+                        panic!("unexpected bytes count")
+                    }
+
+                    assert_eq!(
+                        parse_set_tcp_tracking(&buf),
+                        &SetTcpTracking {
+                            state_events: 1,
+                            strict: 1,
+                        }
+                    )
+                }
+                CommandType::RegisterTrustedProcess => {
+                    let mut buf = vec![0; size_of::<TrustedProcesses>()];
+                    file.read_exact(&mut buf).unwrap();
+                    let process_count = u16::from_le_bytes([buf[0], buf[1]]) as usize;
+                    buf.resize(buf.len() + process_count * size_of::<u64>(), 0);
+                    file.read_exact(&mut buf[size_of::<TrustedProcesses>()..])
+                        .unwrap();
+
+                    let process_ids: Vec<u64> = parse_trusted_processes(&buf).collect();
+                    assert_eq!(process_ids, vec![1, 2, 3]);
+                }
+                CommandType::ClearTrustedProcesses => {}
+                CommandType::UpdateById => {
+                    let mut buf = [0; size_of::<UpdateById>()];
+                    let bytes_count = file.read(&mut buf).unwrap();
+                    if bytes_count != size_of::<UpdateById>() {
+                        panic!("unexpected bytes count")
+                    }
+
+                    assert_eq!(
+                        parse_update_by_id(&buf),
+                        &UpdateById {
+                            connection_id: 1234,
+                            verdict: 2,
+                        }
+                    )
+                }
+                CommandType::SetCacheTimeouts => {
+                    let mut buf = [0; size_of::<SetCacheTimeouts>()];
+                    let bytes_count = file.read(&mut buf).unwrap();
+                    if bytes_count != size_of::<SetCacheTimeouts>() {
+                        panic!("unexpected bytes count")
+                    }
+
+                    assert_eq!(
+                        parse_set_cache_timeouts(&buf),
+                        &SetCacheTimeouts {
+                            tcp_ended_retention_secs: 60,
+                            tcp_idle_expiry_secs: 3600,
+                            udp_ended_retention_secs: 5,
+                            udp_idle_expiry_secs: 30,
+                        }
+                    )
+                }
+                CommandType::SetCacheLimit => {
+                    let mut buf = [0; size_of::<SetCacheLimit>()];
+                    let bytes_count = file.read(&mut buf).unwrap();
+                    if bytes_count != size_of::<SetCacheLimit>() {
+                        panic!("unexpected bytes count")
+                    }
+
+                    assert_eq!(
+                        parse_set_cache_limit(&buf),
+                        &SetCacheLimit { max_entries: 50000 }
+                    )
+                }
+                CommandType::UpdateByProcess => {
+                    let mut buf = [0; size_of::<UpdateByProcess>()];
+                    let bytes_count = file.read(&mut buf).unwrap();
+                    if bytes_count != size_of::<UpdateByProcess>() {
+                        panic!("unexpected bytes count")
+                    }
+
+                    assert_eq!(
+                        parse_update_by_process(&buf),
+                        &UpdateByProcess {
+                            process_id: 4321,
+                            verdict: 4,
+                        }
+                    )
+                }
+                CommandType::GetProcessConnections => {
+                    let mut buf = [0; size_of::<ProcessConnections>()];
+                    let bytes_count = file.read(&mut buf).unwrap();
+                    if bytes_count != size_of::<ProcessConnections>() {
+                        panic!("unexpected bytes count")
+                    }
+
+                    assert_eq!(
+                        parse_process_connections(&buf),
+                        &ProcessConnections { process_id: 4321 }
+                    )
+                }
                 CommandType::GetConnectionsUpdate => {
                     let mut buf = [0; size_of::<ConnectionsUpdate>()];
                     let bytes_count = file.read(&mut buf).unwrap();
@@ -11,6 +11,10 @@ enum InfoType {
     ConnectionUpdateEventV4 = 5,
     ConnectionUpdateEventV6 = 6,
     ConnectionUpdateEnd = 7,
+    BindRedirectEventV4 = 8,
+    BindRedirectEventV6 = 9,
+    BulkUpdateResult = 10,
+    TcpStateChange = 11,
 }
 
 // Fallow this pattern when adding new packets: [InfoType: u8, data_size_in_bytes: u32, data: ...]
@@ -135,6 +139,66 @@ impl core::fmt::Write for Info {
     }
 }
 
+/// Version of the metadata block at the end of the connection events. A block always starts with
+/// its version and only ever grows at the end, so a reader that knows an older version reads the
+/// fields it knows and ignores the rest.
+///
+/// Version 1: process path (u16 length, UTF-8 bytes; empty if unknown).
+/// Version 2: user SID (u16 length, UTF-8 string form `S-1-...`; empty if unknown).
+/// Version 3: interface index, sub interface index and compartment id (u32 each).
+/// Version 4: parent process id (u64; 0 if unknown).
+/// Version 5: connection id (u64; 0 if the connection is not kept by the driver).
+pub const CONNECTION_METADATA_VERSION: u8 = 5;
+
+/// Details about a connection that are captured once, when the connection is first seen.
+#[derive(Default)]
+pub struct ConnectionMetadata<'a> {
+    pub process_path: &'a str,
+    pub user_sid: &'a str,
+    pub interface_index: u32,
+    pub sub_interface_index: u32,
+    pub compartment_id: u32,
+    pub parent_process_id: u64,
+    pub connection_id: u64,
+}
+
+impl ConnectionMetadata<'_> {
+    // The length of a string is sent as a u16, longer values are cut. Windows paths are far
+    // shorter.
+    fn truncated(value: &str) -> &[u8] {
+        let bytes = value.as_bytes();
+        &bytes[..bytes.len().min(u16::MAX as usize)]
+    }
+
+    fn size(&self) -> usize {
+        get_combined_size!(
+            CONNECTION_METADATA_VERSION,
+            0u16,
+            0u16,
+            self.interface_index,
+            self.sub_interface_index,
+            self.compartment_id,
+            self.parent_process_id,
+            self.connection_id
+        ) + Self::truncated(self.process_path).len()
+            + Self::truncated(self.user_sid).len()
+    }
+
+    fn push(&self, vec: &mut Vec<u8>) {
+        push_bytes!(vec, CONNECTION_METADATA_VERSION);
+        for value in [self.process_path, self.user_sid] {
+            let bytes = Self::truncated(value);
+            push_bytes!(vec, bytes.len() as u16);
+            push_bytes!(vec, bytes);
+        }
+        push_bytes!(vec, self.interface_index);
+        push_bytes!(vec, self.sub_interface_index);
+        push_bytes!(vec, self.compartment_id);
+        push_bytes!(vec, self.parent_process_id);
+        push_bytes!(vec, self.connection_id);
+    }
+}
+
 // connection_info_v4 creates an Info packet for a connection (IPv4).
 pub fn connection_info_v4(
     id: u64,
@@ -147,6 +211,7 @@ pub fn connection_info_v4(
     remote_port: u16,
     payload_layer: u8,
     payload: &[u8],
+    metadata: &ConnectionMetadata,
 ) -> Info {
     let mut size = get_combined_size!(
         id,
@@ -161,6 +226,7 @@ pub fn connection_info_v4(
         payload.len() as u32
     );
     size += payload.len();
+    size += metadata.size();
 
     let mut info = Info::new(InfoType::ConnectionIpv4, size);
     let vec = &mut info.0;
@@ -175,6 +241,7 @@ pub fn connection_info_v4(
     push_bytes!(vec, payload_layer);
     push_bytes!(vec, payload.len() as u32);
     push_bytes!(vec, payload);
+    metadata.push(vec);
     info
 }
 
@@ -190,6 +257,7 @@ pub fn connection_info_v6(
     remote_port: u16,
     payload_layer: u8,
     payload: &[u8],
+    metadata: &ConnectionMetadata,
 ) -> Info {
     let mut size = get_combined_size!(
         id,
@@ -204,6 +272,7 @@ pub fn connection_info_v6(
         payload.len() as u32
     );
     size += payload.len();
+    size += metadata.size();
     let mut info = Info::new(InfoType::ConnectionIpv6, size);
     let vec = &mut info.0;
     push_bytes!(vec, id);
@@ -219,6 +288,7 @@ pub fn connection_info_v6(
     if !payload.is_empty() {
         push_bytes!(vec, payload);
     }
+    metadata.push(vec);
     info
 }
 
@@ -235,8 +305,10 @@ pub fn connection_end_event_v4_info(
     rx_packets: u64,
     tx_bytes: u64,
     tx_packets: u64,
+    end_reason: u8,
+    metadata: &ConnectionMetadata,
 ) -> Info {
-    let size = get_combined_size!(
+    let mut size = get_combined_size!(
         process_id,
         direction,
         protocol,
@@ -247,8 +319,10 @@ pub fn connection_end_event_v4_info(
         rx_bytes,
         rx_packets,
         tx_bytes,
-        tx_packets
+        tx_packets,
+        end_reason
     );
+    size += metadata.size();
     let mut info = Info::new(InfoType::ConnectionEndEventV4, size);
     let vec = &mut info.0;
     push_bytes!(vec, process_id);
@@ -262,6 +336,8 @@ pub fn connection_end_event_v4_info(
     push_bytes!(vec, rx_packets);
     push_bytes!(vec, tx_bytes);
     push_bytes!(vec, tx_packets);
+    push_bytes!(vec, end_reason);
+    metadata.push(vec);
     info
 }
 
@@ -278,8 +354,10 @@ pub fn connection_end_event_v6_info(
     rx_packets: u64,
     tx_bytes: u64,
     tx_packets: u64,
+    end_reason: u8,
+    metadata: &ConnectionMetadata,
 ) -> Info {
-    let size = get_combined_size!(
+    let mut size = get_combined_size!(
         process_id,
         direction,
         protocol,
@@ -290,8 +368,10 @@ pub fn connection_end_event_v6_info(
         rx_bytes,
         rx_packets,
         tx_bytes,
-        tx_packets
+        tx_packets,
+        end_reason
     );
+    size += metadata.size();
     let mut info = Info::new(InfoType::ConnectionEndEventV6, size);
     let vec = &mut info.0;
     push_bytes!(vec, process_id);
@@ -305,6 +385,8 @@ pub fn connection_end_event_v6_info(
     push_bytes!(vec, rx_packets);
     push_bytes!(vec, tx_bytes);
     push_bytes!(vec, tx_packets);
+    push_bytes!(vec, end_reason);
+    metadata.push(vec);
     info
 }
 
@@ -319,8 +401,9 @@ pub fn connection_update_event_v4_info(
     rx_packets: u64,
     tx_bytes: u64,
     tx_packets: u64,
+    metadata: &ConnectionMetadata,
 ) -> Info {
-    let size = get_combined_size!(
+    let mut size = get_combined_size!(
         protocol,
         local_ip,
         remote_ip,
@@ -331,6 +414,7 @@ pub fn connection_update_event_v4_info(
         tx_bytes,
         tx_packets
     );
+    size += metadata.size();
     let mut info = Info::new(InfoType::ConnectionUpdateEventV4, size);
     let vec = &mut info.0;
     push_bytes!(vec, protocol);
@@ -342,6 +426,7 @@ pub fn connection_update_event_v4_info(
     push_bytes!(vec, rx_packets);
     push_bytes!(vec, tx_bytes);
     push_bytes!(vec, tx_packets);
+    metadata.push(vec);
     info
 }
 
@@ -356,8 +441,9 @@ pub fn connection_update_event_v6_info(
     rx_packets: u64,
     tx_bytes: u64,
     tx_packets: u64,
+    metadata: &ConnectionMetadata,
 ) -> Info {
-    let size = get_combined_size!(
+    let mut size = get_combined_size!(
         protocol,
         local_ip,
         remote_ip,
@@ -368,6 +454,7 @@ pub fn connection_update_event_v6_info(
         tx_bytes,
         tx_packets
     );
+    size += metadata.size();
     let mut info = Info::new(InfoType::ConnectionUpdateEventV6, size);
     let vec = &mut info.0;
     push_bytes!(vec, protocol);
@@ -379,6 +466,7 @@ pub fn connection_update_event_v6_info(
     push_bytes!(vec, rx_packets);
     push_bytes!(vec, tx_bytes);
     push_bytes!(vec, tx_packets);
+    metadata.push(vec);
     info
 }
 
@@ -388,6 +476,65 @@ pub fn connection_update_end_info() -> Info {
     info
 }
 
+// bind_redirect_event_v4_info creates an Info packet reporting a rewritten bind request (IPv4).
+pub fn bind_redirect_event_v4_info(
+    process_id: u64,
+    protocol: u8,
+    original_ip: [u8; 4],
+    redirect_ip: [u8; 4],
+    local_port: u16,
+) -> Info {
+    let size = get_combined_size!(process_id, protocol, original_ip, redirect_ip, local_port);
+    let mut info = Info::new(InfoType::BindRedirectEventV4, size);
+    let vec = &mut info.0;
+    push_bytes!(vec, process_id);
+    push_bytes!(vec, protocol);
+    push_bytes!(vec, original_ip);
+    push_bytes!(vec, redirect_ip);
+    push_bytes!(vec, local_port);
+    info
+}
+
+// bind_redirect_event_v6_info creates an Info packet reporting a rewritten bind request (IPv6).
+pub fn bind_redirect_event_v6_info(
+    process_id: u64,
+    protocol: u8,
+    original_ip: [u8; 16],
+    redirect_ip: [u8; 16],
+    local_port: u16,
+) -> Info {
+    let size = get_combined_size!(process_id, protocol, original_ip, redirect_ip, local_port);
+    let mut info = Info::new(InfoType::BindRedirectEventV6, size);
+    let vec = &mut info.0;
+    push_bytes!(vec, process_id);
+    push_bytes!(vec, protocol);
+    push_bytes!(vec, original_ip);
+    push_bytes!(vec, redirect_ip);
+    push_bytes!(vec, local_port);
+    info
+}
+
+// bulk_update_result_info creates an Info packet with the number of connections a BulkUpdateV4 or
+// BulkUpdateV6 command matched.
+pub fn bulk_update_result_info(matched: u64) -> Info {
+    let size = get_combined_size!(matched);
+    let mut info = Info::new(InfoType::BulkUpdateResult, size);
+    let vec = &mut info.0;
+    push_bytes!(vec, matched);
+    info
+}
+
+// tcp_state_change_info creates an Info packet with the new TCP state of a connection, see
+// SetTcpTracking.
+pub fn tcp_state_change_info(connection_id: u64, state: u8) -> Info {
+    let size = get_combined_size!(connection_id, state);
+    let mut info = Info::new(InfoType::TcpStateChange, size);
+    let vec = &mut info.0;
+    push_bytes!(vec, connection_id);
+    push_bytes!(vec, state);
+    info
+}
+
 #[repr(u8)]
 #[derive(Clone, Copy)]
 pub enum Severity {
@@ -428,6 +575,10 @@ fn generate_test_info_file() -> Result<(), std::io::Error> {
         InfoType::ConnectionUpdateEventV4,
         InfoType::ConnectionUpdateEventV6,
         InfoType::ConnectionUpdateEnd,
+        InfoType::BindRedirectEventV4,
+        InfoType::BindRedirectEventV6,
+        InfoType::BulkUpdateResult,
+        InfoType::TcpStateChange,
     ];
 
     let mut selected: Vec<InfoType> = Vec::with_capacity(1000);
@@ -457,6 +608,15 @@ fn generate_test_info_file() -> Result<(), std::io::Error> {
                     6,
                     7,
                     &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10],
+                    &ConnectionMetadata {
+                        process_path: "C:\\Windows\\System32\\svchost.exe",
+                        user_sid: "S-1-5-18",
+                        interface_index: 11,
+                        sub_interface_index: 12,
+                        compartment_id: 1,
+                        parent_process_id: 13,
+                        connection_id: 14,
+                    },
                 );
                 info.assert_size();
                 info.0
@@ -473,6 +633,15 @@ fn generate_test_info_file() -> Result<(), std::io::Error> {
                     6,
                     7,
                     &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10],
+                    &ConnectionMetadata {
+                        process_path: "C:\\Windows\\System32\\svchost.exe",
+                        user_sid: "S-1-5-18",
+                        interface_index: 11,
+                        sub_interface_index: 12,
+                        compartment_id: 1,
+                        parent_process_id: 13,
+                        connection_id: 14,
+                    },
                 );
                 info.assert_size();
                 info.0
@@ -490,6 +659,16 @@ fn generate_test_info_file() -> Result<(), std::io::Error> {
                     7,
                     8,
                     9,
+                    2,
+                    &ConnectionMetadata {
+                        process_path: "C:\\Windows\\System32\\svchost.exe",
+                        user_sid: "S-1-5-18",
+                        interface_index: 11,
+                        sub_interface_index: 12,
+                        compartment_id: 1,
+                        parent_process_id: 13,
+                        connection_id: 14,
+                    },
                 );
                 info.assert_size();
                 info.0
@@ -507,6 +686,16 @@ fn generate_test_info_file() -> Result<(), std::io::Error> {
                     7,
                     8,
                     9,
+                    2,
+                    &ConnectionMetadata {
+                        process_path: "C:\\Windows\\System32\\svchost.exe",
+                        user_sid: "S-1-5-18",
+                        interface_index: 11,
+                        sub_interface_index: 12,
+                        compartment_id: 1,
+                        parent_process_id: 13,
+                        connection_id: 14,
+                    },
                 );
                 info.assert_size();
                 info.0
@@ -522,6 +711,15 @@ fn generate_test_info_file() -> Result<(), std::io::Error> {
                     5,
                     6,
                     7,
+                    &ConnectionMetadata {
+                        process_path: "C:\\Windows\\System32\\svchost.exe",
+                        user_sid: "S-1-5-18",
+                        interface_index: 11,
+                        sub_interface_index: 12,
+                        compartment_id: 1,
+                        parent_process_id: 13,
+                        connection_id: 14,
+                    },
                 );
                 info.assert_size();
                 info.0
@@ -537,6 +735,15 @@ fn generate_test_info_file() -> Result<(), std::io::Error> {
                     5,
                     6,
                     7,
+                    &ConnectionMetadata {
+                        process_path: "C:\\Windows\\System32\\svchost.exe",
+                        user_sid: "S-1-5-18",
+                        interface_index: 11,
+                        sub_interface_index: 12,
+                        compartment_id: 1,
+                        parent_process_id: 13,
+                        connection_id: 14,
+                    },
                 );
                 info.assert_size();
                 info.0
@@ -546,6 +753,32 @@ fn generate_test_info_file() -> Result<(), std::io::Error> {
                 info.assert_size();
                 info.0
             }
+            InfoType::BindRedirectEventV4 => {
+                let info = bind_redirect_event_v4_info(1, 2, [1, 2, 3, 4], [2, 3, 4, 5], 3);
+                info.assert_size();
+                info.0
+            }
+            InfoType::BindRedirectEventV6 => {
+                let info = bind_redirect_event_v6_info(
+                    1,
+                    2,
+                    [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16],
+                    [2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17],
+                    3,
+                );
+                info.assert_size();
+                info.0
+            }
+            InfoType::BulkUpdateResult => {
+                let info = bulk_update_result_info(42);
+                info.assert_size();
+                info.0
+            }
+            InfoType::TcpStateChange => {
+                let info = tcp_state_change_info(1234, 3);
+                info.assert_size();
+                info.0
+            }
         })?;
     }
     return Ok(());
@@ -40,6 +40,9 @@ pub const STATUS_FWP_TXN_IN_PROGRESS: i32 = 0xC022000Eu32 as i32;
 // Processor modes (KPROCESSOR_MODE)
 pub const KERNEL_MODE: i8 = 0;
 
+// Wait reasons (KWAIT_REASON)
+pub const EXECUTIVE: u32 = 0;
+
 // Interrupt request levels (KIRQL)
 pub const PASSIVE_LEVEL: u8 = 0;
 pub const APC_LEVEL: u8 = 1;
@@ -60,3 +63,7 @@ pub const FILE_WRITE_ATTRIBUTES: u32 = 0x00000100;
 pub const FILE_WRITE_EA: u32 = 0x00000010;
 pub const FILE_APPEND_DATA: u32 = 0x00000004;
 pub const FILE_EXECUTE: u32 = 0x00000020;
+
+pub const SYMBOLIC_LINK_QUERY: u32 = 0x0001;
+pub const OBJ_CASE_INSENSITIVE: u32 = 0x00000040;
+pub const OBJ_KERNEL_HANDLE: u32 = 0x00000200;
@@ -2,14 +2,19 @@ use core::ffi::c_void;
 
 use windows_sys::{
     core::{GUID, PCWSTR},
-    Wdk::Foundation::{DEVICE_OBJECT, DRIVER_OBJECT, MDL},
+    Wdk::{
+        Foundation::{
+            DEVICE_OBJECT, DRIVER_OBJECT, KDPC, KEVENT, MDL, OBJECT_ATTRIBUTES, PIO_WORKITEM,
+        },
+        System::SystemServices::{KTIMER, WORK_QUEUE_TYPE},
+    },
     Win32::{
         Foundation::{HANDLE, NTSTATUS, UNICODE_STRING},
         NetworkManagement::WindowsFilteringPlatform::{
             FWPM_PROVIDER_CONTEXT2, FWP_CONDITION_VALUE0, FWP_MATCH_TYPE, FWP_VALUE0,
         },
         Networking::WinSock::{ADDRESS_FAMILY, SCOPE_ID},
-        System::Kernel::COMPARTMENT_ID,
+        System::Kernel::{COMPARTMENT_ID, EVENT_TYPE, TIMER_TYPE},
     },
 };
 
@@ -367,7 +372,7 @@ extern "C" {
     /// The FwpsApplyModifiedLayerData0 function applies changes to layer-specific data made after a call to FwpsAcquireWritableLayerDataPointer0.
     pub(crate) fn FwpsApplyModifiedLayerData0(
         classifyHandle: u64,
-        modifiedLayerData: *mut *mut c_void,
+        modifiedLayerData: *mut c_void,
         flags: u32,
     );
 
@@ -416,6 +421,19 @@ extern "C" {
         completionContext: *mut c_void,
     ) -> NTSTATUS;
 
+    /// The FwpsInjectForwardAsync0 function injects packet data into the forwarding data path.
+    pub(crate) fn FwpsInjectForwardAsync0(
+        injectionHandle: HANDLE,
+        injectionContext: HANDLE,
+        flags: u32,
+        addressFamily: ADDRESS_FAMILY,
+        compartmentId: COMPARTMENT_ID,
+        targetInterfaceIndex: u32,
+        netBufferList: *mut NET_BUFFER_LIST,
+        completionFn: FWPS_INJECT_COMPLETE0,
+        completionContext: *mut c_void,
+    ) -> NTSTATUS;
+
     /// The FwpsInjectTransportSendAsync1 function injects packet data from the transport, datagram data, or ICMP error layers into the send data path. This function differs from the previous version (FwpsInjectTransportSendAsync0) in that it takes an updated parameters structure as an argument.
     pub(crate) fn FwpsInjectTransportSendAsync1(
         injectionHandle: HANDLE,
@@ -556,4 +574,116 @@ extern "C" {
         alertable: u8,
         interval: *const i64,
     ) -> NTSTATUS;
+
+    /// The PsLookupProcessByProcessId routine accepts the process ID of a process and returns a
+    /// referenced pointer to EPROCESS structure of the process. Callable at IRQL <= APC_LEVEL.
+    pub(crate) fn PsLookupProcessByProcessId(
+        process_id: HANDLE,
+        process: *mut *mut c_void,
+    ) -> NTSTATUS;
+
+    /// The KeWaitForSingleObject routine puts the current thread into a wait state until the given
+    /// dispatcher object is set to a signaled state or (optionally) until the wait times out.
+    /// With a zero timeout it only tests the state and is callable at IRQL <= DISPATCH_LEVEL.
+    pub(crate) fn KeWaitForSingleObject(
+        object: *mut c_void,
+        wait_reason: u32,
+        wait_mode: i8,
+        alertable: u8,
+        timeout: *const i64,
+    ) -> NTSTATUS;
+
+    /// The ObfDereferenceObject routine decrements the given object's reference count and performs
+    /// retention checks. Callable at IRQL <= DISPATCH_LEVEL.
+    pub(crate) fn ObfDereferenceObject(object: *mut c_void) -> isize;
+
+    /// The PsSetCreateProcessNotifyRoutine routine adds a driver-supplied callback routine to, or
+    /// removes it from, a list of routines to be called whenever a process is created or deleted.
+    /// Callable at IRQL = PASSIVE_LEVEL.
+    pub(crate) fn ZwOpenSymbolicLinkObject(
+        link_handle: *mut HANDLE,
+        desired_access: u32,
+        object_attributes: *const OBJECT_ATTRIBUTES,
+    ) -> NTSTATUS;
+
+    pub(crate) fn ZwQuerySymbolicLinkObject(
+        link_handle: HANDLE,
+        link_target: *mut UNICODE_STRING,
+        returned_length: *mut u32,
+    ) -> NTSTATUS;
+
+    pub(crate) fn ZwClose(handle: HANDLE) -> NTSTATUS;
+
+    pub(crate) fn PsSetCreateProcessNotifyRoutine(
+        notify_routine: unsafe extern "system" fn(HANDLE, HANDLE, u8),
+        remove: u8,
+    ) -> NTSTATUS;
+
+    /// The KeInitializeTimerEx routine initializes an extended kernel timer object.
+    pub(crate) fn KeInitializeTimerEx(timer: *mut KTIMER, timer_type: TIMER_TYPE);
+
+    /// The KeSetTimerEx routine sets the absolute or relative interval at which a timer object is
+    /// to be set to a signaled state, optionally supplies a DPC to be executed when that interval
+    /// expires, and optionally supplies a recurring interval for the timer, in milliseconds.
+    /// Callable at IRQL <= DISPATCH_LEVEL.
+    pub(crate) fn KeSetTimerEx(
+        timer: *mut KTIMER,
+        due_time: i64,
+        period: i32,
+        dpc: *const KDPC,
+    ) -> u8;
+
+    /// The KeCancelTimer routine dequeues a timer object before the timer interval, if any was
+    /// set, expires. Callable at IRQL <= DISPATCH_LEVEL.
+    pub(crate) fn KeCancelTimer(timer: *mut KTIMER) -> u8;
+
+    /// The KeInitializeDpc routine initializes a DPC object, and registers a routine for that
+    /// object. The bindings declare the routine without its arguments.
+    pub(crate) fn KeInitializeDpc(
+        dpc: *mut KDPC,
+        deferred_routine: unsafe extern "system" fn(
+            *const KDPC,
+            *const c_void,
+            *const c_void,
+            *const c_void,
+        ),
+        deferred_context: *const c_void,
+    );
+
+    /// The KeFlushQueuedDpcs routine returns after all queued DPCs on all processors have
+    /// executed. Callable at IRQL = PASSIVE_LEVEL.
+    pub(crate) fn KeFlushQueuedDpcs();
+
+    /// The IoAllocateWorkItem routine allocates a work item. The work item holds a reference to
+    /// the device object while it is queued, so the driver can not unload under it.
+    pub(crate) fn IoAllocateWorkItem(device_object: *const DEVICE_OBJECT) -> PIO_WORKITEM;
+
+    /// The IoQueueWorkItem routine inserts a work item into a queue from which a system worker
+    /// thread removes the item and gives control to the specified callback routine.
+    /// Callable at IRQL <= DISPATCH_LEVEL.
+    pub(crate) fn IoQueueWorkItem(
+        io_work_item: PIO_WORKITEM,
+        worker_routine: unsafe extern "system" fn(*const DEVICE_OBJECT, *const c_void),
+        queue_type: WORK_QUEUE_TYPE,
+        context: *const c_void,
+    );
+
+    /// The IoFreeWorkItem routine frees a work item that was allocated by IoAllocateWorkItem.
+    pub(crate) fn IoFreeWorkItem(io_work_item: PIO_WORKITEM);
+
+    /// The KeInitializeEvent routine initializes an event object as a synchronization (single
+    /// waiter) or notification type event and sets it up to a signaled or not-signaled state.
+    pub(crate) fn KeInitializeEvent(event: *mut KEVENT, event_type: EVENT_TYPE, state: u8);
+
+    /// The KeSetEvent routine sets an event object to a signaled state if the event was not
+    /// already signaled, and returns the previous state. Callable at IRQL <= DISPATCH_LEVEL.
+    pub(crate) fn KeSetEvent(event: *mut KEVENT, increment: i32, wait: u8) -> i32;
+
+    /// The KeClearEvent routine sets an event to a not-signaled state.
+    /// Callable at IRQL <= DISPATCH_LEVEL.
+    pub(crate) fn KeClearEvent(event: *mut KEVENT);
+
+    /// The KeReadStateEvent routine returns the current state, signaled or not signaled, of an
+    /// event object. Callable at IRQL <= DISPATCH_LEVEL.
+    pub(crate) fn KeReadStateEvent(event: *const KEVENT) -> i32;
 }
@@ -0,0 +1,49 @@
+use windows_sys::Win32::Networking::WinSock::{AF_INET, AF_INET6};
+
+/// Writable layer data of the ALE bind redirect layers (`FWPS_BIND_REQUEST0`).
+#[repr(C)]
+pub(crate) struct FwpsBindRequest0 {
+    pub(crate) local_address_and_port: [u8; 128],
+    pub(crate) port_reservation_token: u64,
+    pub(crate) previous_version: *const FwpsBindRequest0,
+    pub(crate) modifier_filter_id: u64,
+}
+
+// Offsets into SOCKADDR_IN and SOCKADDR_IN6. Both start with the u16 family followed by the
+// big-endian port. IPv6 has a u32 flow info before the address.
+const FAMILY_OFFSET: usize = 0;
+const IPV4_ADDRESS_OFFSET: usize = 4;
+const IPV6_ADDRESS_OFFSET: usize = 8;
+
+impl FwpsBindRequest0 {
+    /// Replaces the local address of the request, keeping the port. Returns false if the family of
+    /// the socket address does not match the size of `ip`.
+    pub(crate) fn set_local_address(&mut self, ip: &[u8]) -> bool {
+        let family = u16::from_ne_bytes([
+            self.local_address_and_port[FAMILY_OFFSET],
+            self.local_address_and_port[FAMILY_OFFSET + 1],
+        ]);
+        let offset = match (family, ip.len()) {
+            (AF_INET, 4) => IPV4_ADDRESS_OFFSET,
+            (AF_INET6, 16) => IPV6_ADDRESS_OFFSET,
+            _ => return false,
+        };
+        self.local_address_and_port[offset..offset + ip.len()].copy_from_slice(ip);
+        return true;
+    }
+
+    /// Reports whether the filter already modified this request. WFP classifies the request again
+    /// after another callout changed it, and rewriting it every time would loop forever.
+    pub(crate) fn was_modified_by(&self, filter_id: u64) -> bool {
+        let mut request: *const FwpsBindRequest0 = self;
+        unsafe {
+            while let Some(r) = request.as_ref() {
+                if r.modifier_filter_id == filter_id {
+                    return true;
+                }
+                request = r.previous_version;
+            }
+        }
+        return false;
+    }
+}
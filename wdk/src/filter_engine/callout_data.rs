@@ -1,9 +1,14 @@
 use crate::{
-    ffi::{FwpsCompleteOperation0, FwpsPendOperation0},
+    ffi::{
+        FwpsAcquireClassifyHandle0, FwpsAcquireWritableLayerDataPointer0,
+        FwpsApplyModifiedLayerData0, FwpsCompleteOperation0, FwpsPendOperation0,
+        FwpsReleaseClassifyHandle0,
+    },
     utils::check_ntstatus,
 };
 
 use super::{
+    bind_request::FwpsBindRequest0,
     classify::ClassifyOut,
     layer::{Layer, Value, ValueType},
     metadata::FwpsIncomingMetadataValues,
@@ -14,11 +19,11 @@ use alloc::{
     boxed::Box,
     string::{String, ToString},
 };
-use core::{ffi::c_void, ptr::NonNull};
+use core::{ffi::c_void, mem::size_of, ptr::NonNull};
 use windows_sys::Win32::{
     Foundation::HANDLE,
     NetworkManagement::WindowsFilteringPlatform::FWP_CONDITION_FLAG_IS_REAUTHORIZE,
-    Networking::WinSock::SCOPE_ID,
+    Networking::WinSock::SCOPE_ID, Security::TOKEN_ACCESS_INFORMATION,
 };
 
 pub enum ClassifyDefer {
@@ -63,9 +68,11 @@ impl ClassifyDefer {
 pub struct CalloutData<'a> {
     pub layer: Layer,
     pub(crate) callout_id: usize,
+    pub(crate) filter_id: u64,
     pub(crate) values: &'a [Value],
     pub(crate) metadata: *const FwpsIncomingMetadataValues,
     pub(crate) classify_out: *mut ClassifyOut,
+    pub(crate) classify_context: *mut c_void,
     pub(crate) layer_data: *mut c_void,
 }
 
@@ -98,6 +105,39 @@ impl<'a> CalloutData<'a> {
         };
     }
 
+    /// Returns the SID of the user from a token access information value (the ALE user id field),
+    /// in its binary form. None if the value is of another type or empty.
+    pub fn get_value_user_sid(&'a self, index: usize) -> Option<&'a [u8]> {
+        if !matches!(
+            self.values[index].value_type,
+            ValueType::FwpTokenAccessInformationType
+        ) {
+            return None;
+        }
+        unsafe {
+            let blob = self.values[index].value.byte_blob.as_ref()?;
+            if blob.data.is_null() || (blob.size as usize) < size_of::<TOKEN_ACCESS_INFORMATION>() {
+                return None;
+            }
+            let info = (blob.data as *const TOKEN_ACCESS_INFORMATION).as_ref()?;
+            let sid_hash = info.SidHash.as_ref()?;
+            if sid_hash.SidCount == 0 {
+                return None;
+            }
+            // The first entry is the user, the rest are its groups.
+            let sid = (*sid_hash.SidAttr).Sid as *const u8;
+            if sid.is_null() {
+                return None;
+            }
+            // Revision, sub authority count, 6 byte authority and 4 bytes per sub authority.
+            let sub_authority_count = *sid.add(1) as usize;
+            return Some(core::slice::from_raw_parts(
+                sid,
+                8 + 4 * sub_authority_count,
+            ));
+        }
+    }
+
     pub fn get_process_id(&self) -> Option<u64> {
         unsafe { (*self.metadata).get_process_id() }
     }
@@ -234,6 +274,50 @@ impl<'a> CalloutData<'a> {
         }
     }
 
+    /// Rewrites the local address of the bind request. Only valid in the bind redirect layers.
+    /// Returns false if nothing was changed: the action can not be set, the request was already
+    /// modified by this filter or the address does not match the family of the socket.
+    pub fn redirect_bind(&mut self, local_address: &[u8]) -> Result<bool, String> {
+        match self.layer {
+            Layer::AleBindRedirectV4 | Layer::AleBindRedirectV6 => {}
+            _ => return Err("not a bind redirect layer".to_string()),
+        }
+        unsafe {
+            if !(*self.classify_out).can_set_action() {
+                return Ok(false);
+            }
+
+            let mut classify_handle = 0;
+            let status = FwpsAcquireClassifyHandle0(self.classify_context, 0, &mut classify_handle);
+            check_ntstatus(status)?;
+
+            let mut request: *mut FwpsBindRequest0 = core::ptr::null_mut();
+            let status = FwpsAcquireWritableLayerDataPointer0(
+                classify_handle,
+                self.filter_id,
+                0,
+                &mut request as *mut _ as *mut c_void,
+                self.classify_out,
+            );
+            if let Err(err) = check_ntstatus(status) {
+                FwpsReleaseClassifyHandle0(classify_handle);
+                return Err(err);
+            }
+
+            let mut modified = false;
+            if let Some(request) = request.as_mut() {
+                if !request.was_modified_by(self.filter_id) {
+                    modified = request.set_local_address(local_address);
+                }
+            }
+
+            // The writable data has to be applied even if it was not changed.
+            FwpsApplyModifiedLayerData0(classify_handle, request as *mut c_void, 0);
+            FwpsReleaseClassifyHandle0(classify_handle);
+            return Ok(modified);
+        }
+    }
+
     pub fn get_callout_id(&self) -> usize {
         self.callout_id
     }
@@ -34,6 +34,7 @@ use windows_sys::{
         FWPM_LAYER_OUTBOUND_TRANSPORT_V4_DISCARD, FWPM_LAYER_OUTBOUND_TRANSPORT_V6,
         FWPM_LAYER_OUTBOUND_TRANSPORT_V6_DISCARD, FWPM_LAYER_STREAM_V4,
         FWPM_LAYER_STREAM_V4_DISCARD, FWPM_LAYER_STREAM_V6, FWPM_LAYER_STREAM_V6_DISCARD,
+        FWP_BYTE_BLOB,
     },
 };
 
@@ -57,6 +58,7 @@ pub(crate) union ValueData {
     pub(crate) uint32: u32,
     pub(crate) uint64: *const u64,
     pub(crate) byte_array16: *const [u8; 16],
+    pub(crate) byte_blob: *const FWP_BYTE_BLOB,
     // TODO: add the rest of possible values.
 }
 
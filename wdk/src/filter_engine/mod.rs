@@ -17,6 +17,7 @@ use self::classify::ClassifyOut;
 use self::layer::IncomingValues;
 use self::metadata::FwpsIncomingMetadataValues;
 
+pub(crate) mod bind_request;
 pub mod callout;
 pub mod callout_data;
 pub(crate) mod classify;
@@ -330,7 +331,7 @@ unsafe extern "C" fn catch_all_callout(
     fixed_values: *const IncomingValues,
     meta_values: *const FwpsIncomingMetadataValues,
     layer_data: *mut c_void,
-    _context: *mut c_void,
+    classify_context: *mut c_void,
     filter: *const FWPS_FILTER2,
     _flow_context: u64,
     classify_out: *mut ClassifyOut,
@@ -348,9 +349,11 @@ unsafe extern "C" fn catch_all_callout(
         let data = CalloutData {
             layer: callout.layer,
             callout_id: filter.context as usize,
+            filter_id: filter.filterId,
             values: array,
             metadata: meta_values,
             classify_out,
+            classify_context,
             layer_data,
         };
         // Call the defined function.
@@ -196,6 +196,19 @@ impl NetBufferList {
         NetBufferList { nbl, data: None }
     }
 
+    /// Wraps a packet built by the driver in a new list, to be injected. The list owns the data,
+    /// so it stays valid until the injection completes.
+    pub fn from_packet(
+        packet: Vec<u8>,
+        net_allocator: &NetworkAllocator,
+    ) -> Result<NetBufferList, String> {
+        let nbl = net_allocator.wrap_packet_in_nbl(&packet)?;
+        Ok(NetBufferList {
+            nbl,
+            data: Some(packet),
+        })
+    }
+
     pub fn iter(&self) -> NetBufferListIter {
         NetBufferListIter(self.nbl)
     }
@@ -11,11 +11,11 @@ use windows_sys::Win32::{
 
 use crate::{
     ffi::{
-        FwpsInjectNetworkReceiveAsync0, FwpsInjectNetworkSendAsync0,
+        FwpsInjectForwardAsync0, FwpsInjectNetworkReceiveAsync0, FwpsInjectNetworkSendAsync0,
         FwpsInjectTransportReceiveAsync0, FwpsInjectTransportSendAsync1,
         FwpsInjectionHandleCreate0, FwpsInjectionHandleDestroy0, FwpsQueryPacketInjectionState0,
-        FWPS_INJECTION_TYPE_NETWORK, FWPS_INJECTION_TYPE_TRANSPORT, FWPS_PACKET_INJECTION_STATE,
-        FWPS_TRANSPORT_SEND_PARAMS1, NET_BUFFER_LIST,
+        FWPS_INJECTION_TYPE_FORWARD, FWPS_INJECTION_TYPE_NETWORK, FWPS_INJECTION_TYPE_TRANSPORT,
+        FWPS_PACKET_INJECTION_STATE, FWPS_TRANSPORT_SEND_PARAMS1, NET_BUFFER_LIST,
     },
     utils::check_ntstatus,
 };
@@ -57,6 +57,9 @@ pub struct InjectInfo {
     pub interface_index: u32,
     pub sub_interface_index: u32,
     pub compartment_id: COMPARTMENT_ID,
+    /// Send an outbound packet out of `interface_index` through the forwarding path, instead of
+    /// letting the stack route it.
+    pub forward: bool,
 }
 
 pub struct Injector {
@@ -80,9 +83,11 @@ impl Injector {
             if let Err(err) = check_ntstatus(status) {
                 crate::err!("error allocating transport inject handle: {}", err);
             }
+            // The network handles are also used for forward injection (see InjectInfo::forward).
+            // Sharing them keeps was_network_packet_injected_by_self covering both.
             let status = FwpsInjectionHandleCreate0(
                 AF_INET,
-                FWPS_INJECTION_TYPE_NETWORK,
+                FWPS_INJECTION_TYPE_NETWORK | FWPS_INJECTION_TYPE_FORWARD,
                 &mut packet_inject_handle_v4,
             );
 
@@ -91,7 +96,7 @@ impl Injector {
             }
             let status = FwpsInjectionHandleCreate0(
                 AF_INET6,
-                FWPS_INJECTION_TYPE_NETWORK,
+                FWPS_INJECTION_TYPE_NETWORK | FWPS_INJECTION_TYPE_FORWARD,
                 &mut packet_inject_handle_v6,
             );
 
@@ -249,7 +254,23 @@ impl Injector {
             self.packet_inject_handle_v4
         };
 
-        let status = if inject_info.inbound && !inject_info.loopback {
+        let status = if inject_info.forward && !inject_info.inbound {
+            // Inject outbound on the given interface.
+            let address_family = if inject_info.ipv6 { AF_INET6 } else { AF_INET };
+            unsafe {
+                FwpsInjectForwardAsync0(
+                    inject_handle,
+                    0,
+                    0,
+                    address_family,
+                    inject_info.compartment_id,
+                    inject_info.interface_index,
+                    nbl,
+                    free_packet,
+                    (packet_pointer as *mut NetBufferList) as _,
+                )
+            }
+        } else if inject_info.inbound && !inject_info.loopback {
             // Inject inbound.
             unsafe {
                 FwpsInjectNetworkReceiveAsync0(
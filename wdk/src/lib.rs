@@ -12,6 +12,8 @@ pub mod filter_engine;
 pub mod interface;
 pub mod ioqueue;
 pub mod irp_helpers;
+pub mod periodic;
+pub mod process;
 pub mod rw_spin_lock;
 pub mod spin_lock;
 pub mod utils;
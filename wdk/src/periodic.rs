@@ -0,0 +1,129 @@
+use core::ffi::c_void;
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use windows_sys::Wdk::Foundation::{DEVICE_OBJECT, KDPC, KEVENT, PIO_WORKITEM};
+use windows_sys::Wdk::System::SystemServices::{DelayedWorkQueue, KTIMER};
+use windows_sys::Win32::System::Kernel::{NotificationEvent, NotificationTimer};
+
+use crate::consts::{EXECUTIVE, KERNEL_MODE};
+use crate::driver::Driver;
+use crate::ffi;
+
+// State shared with the DPC and the work item. Boxed, the kernel keeps pointers to the timer and
+// the DPC.
+struct Inner {
+    timer: KTIMER,
+    dpc: KDPC,
+    work_item: PIO_WORKITEM,
+    callback: fn(),
+    // Signaled while no work item is queued or running. The DPC clears it before queuing the work
+    // item, and setting it is the last thing the work routine does, so once it is signaled nothing
+    // touches `Inner` or the work item anymore. A period that ends before the previous call
+    // returned is skipped, a work item can not be queued twice.
+    idle: KEVENT,
+    // Keeps two DPCs on different processors from both queuing the work item.
+    dpc_running: AtomicBool,
+    stopping: AtomicBool,
+}
+
+/// Calls a function periodically at PASSIVE_LEVEL. The call is stopped when this is dropped.
+///
+/// A kernel timer fires a DPC every period. The DPC runs at DISPATCH_LEVEL, so it only queues a
+/// work item on a system worker thread, which makes the call.
+pub struct PeriodicWork {
+    inner: Box<Inner>,
+}
+
+impl PeriodicWork {
+    /// Starts calling `callback` every `period`, the first call is one period from now.
+    /// Only callable at PASSIVE_LEVEL.
+    pub fn start(driver: &Driver, period: Duration, callback: fn()) -> Result<Self, String> {
+        let work_item = unsafe { ffi::IoAllocateWorkItem(driver.get_device_object()) };
+        if work_item == 0 {
+            return Err("failed to allocate work item".into());
+        }
+
+        let mut inner = Box::new(Inner {
+            timer: unsafe { core::mem::zeroed() },
+            dpc: unsafe { core::mem::zeroed() },
+            work_item,
+            callback,
+            idle: unsafe { core::mem::zeroed() },
+            dpc_running: AtomicBool::new(false),
+            stopping: AtomicBool::new(false),
+        });
+
+        // Due time is relative (negative) in 100-nanosecond units, the period is in milliseconds.
+        let due_time = -((period.as_nanos() / 100) as i64);
+        let period_ms = period.as_millis() as i32;
+        unsafe {
+            let context = inner.as_mut() as *mut Inner as *const c_void;
+            ffi::KeInitializeEvent(&mut inner.idle, NotificationEvent, 1);
+            ffi::KeInitializeTimerEx(&mut inner.timer, NotificationTimer);
+            ffi::KeInitializeDpc(&mut inner.dpc, dpc_routine, context);
+            ffi::KeSetTimerEx(&mut inner.timer, due_time, period_ms, &inner.dpc);
+        }
+
+        return Ok(Self { inner });
+    }
+}
+
+impl Drop for PeriodicWork {
+    fn drop(&mut self) {
+        self.inner.stopping.store(true, Ordering::SeqCst);
+        unsafe {
+            // After the timer is canceled and the DPCs that were already queued have run, the only
+            // thing left is a work item that is queued or running. Wait until it has signaled the
+            // event, the work item can only be freed once its routine is done with it.
+            ffi::KeCancelTimer(&mut self.inner.timer);
+            ffi::KeFlushQueuedDpcs();
+            ffi::KeWaitForSingleObject(
+                addr_of_mut!(self.inner.idle) as *mut c_void,
+                EXECUTIVE,
+                KERNEL_MODE,
+                0,
+                core::ptr::null(),
+            );
+
+            ffi::IoFreeWorkItem(self.inner.work_item);
+        }
+    }
+}
+
+unsafe extern "system" fn dpc_routine(
+    _dpc: *const KDPC,
+    context: *const c_void,
+    _system_argument1: *const c_void,
+    _system_argument2: *const c_void,
+) {
+    // The event is changed by the kernel, only reach it through raw pointers.
+    let inner = context as *mut Inner;
+    if (*inner).stopping.load(Ordering::SeqCst) {
+        return;
+    }
+    if (*inner).dpc_running.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    // Not signaled: the previous call is still running.
+    if ffi::KeReadStateEvent(addr_of!((*inner).idle)) != 0 {
+        ffi::KeClearEvent(addr_of_mut!((*inner).idle));
+        ffi::IoQueueWorkItem((*inner).work_item, work_routine, DelayedWorkQueue, context);
+    }
+    (*inner).dpc_running.store(false, Ordering::SeqCst);
+}
+
+unsafe extern "system" fn work_routine(
+    _device_object: *const DEVICE_OBJECT,
+    context: *const c_void,
+) {
+    let inner = context as *mut Inner;
+    if !(*inner).stopping.load(Ordering::SeqCst) {
+        ((*inner).callback)();
+    }
+    // Must be the last access: `drop` frees everything as soon as the event is signaled.
+    ffi::KeSetEvent(addr_of_mut!((*inner).idle), 0, 0);
+}
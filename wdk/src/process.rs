@@ -0,0 +1,143 @@
+use core::ffi::c_void;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use alloc::{format, string::String};
+use windows_sys::Win32::Foundation::{HANDLE, STATUS_SUCCESS};
+
+use crate::consts::{EXECUTIVE, KERNEL_MODE};
+use crate::ffi;
+use crate::utils::check_ntstatus;
+
+/// A process, held by reference.
+///
+/// The id of a process is not reused while its object is referenced, so a `Process` keeps
+/// referring to the process it was looked up for, even after that process has exited.
+pub struct Process {
+    process_id: u64,
+    object: *mut c_void,
+}
+
+// The object is only handed to kernel routines that can be called from any thread.
+unsafe impl Send for Process {}
+unsafe impl Sync for Process {}
+
+impl Process {
+    /// Looks up the process with the given id and takes a reference to it.
+    /// Only callable at IRQL <= APC_LEVEL.
+    pub fn lookup(process_id: u64) -> Result<Self, String> {
+        let mut object = core::ptr::null_mut();
+        let status = unsafe { ffi::PsLookupProcessByProcessId(process_id as HANDLE, &mut object) };
+        if let Err(err) = check_ntstatus(status) {
+            return Err(format!("failed to look up process {}: {}", process_id, err));
+        }
+
+        return Ok(Self { process_id, object });
+    }
+
+    pub fn process_id(&self) -> u64 {
+        self.process_id
+    }
+
+    /// Reports whether the process has exited. A process object is signaled once the process
+    /// ends, so this is a wait with a zero timeout: it never blocks and is callable at
+    /// IRQL <= DISPATCH_LEVEL.
+    pub fn has_exited(&self) -> bool {
+        let timeout: i64 = 0;
+        let status =
+            unsafe { ffi::KeWaitForSingleObject(self.object, EXECUTIVE, KERNEL_MODE, 0, &timeout) };
+        return status == STATUS_SUCCESS;
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::ObfDereferenceObject(self.object);
+        }
+    }
+}
+
+/// A process creation or exit, as reported by the kernel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProcessEvent {
+    Created {
+        process_id: u64,
+        parent_process_id: u64,
+    },
+    Exited {
+        process_id: u64,
+    },
+}
+
+// The callback of the registered notifier. The kernel routine takes no context pointer, so there
+// can only be one registration at a time.
+static NOTIFY_CALLBACK: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Registration of a process create/exit callback. The callback is removed when this is dropped.
+///
+/// The callback is called at PASSIVE_LEVEL, in the context of the process that is created or of
+/// the last thread of the process that exits.
+pub struct ProcessNotify {
+    _private: (),
+}
+
+impl ProcessNotify {
+    /// Registers `callback` for every process creation and exit. Only callable at PASSIVE_LEVEL,
+    /// and only once until the returned registration is dropped.
+    pub fn register(callback: fn(ProcessEvent)) -> Result<Self, String> {
+        if NOTIFY_CALLBACK
+            .compare_exchange(
+                core::ptr::null_mut(),
+                callback as *mut (),
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            )
+            .is_err()
+        {
+            return Err("process notify callback is already registered".into());
+        }
+
+        let status = unsafe { ffi::PsSetCreateProcessNotifyRoutine(notify_routine, 0) };
+        if let Err(err) = check_ntstatus(status) {
+            NOTIFY_CALLBACK.store(core::ptr::null_mut(), Ordering::SeqCst);
+            return Err(format!(
+                "failed to register process notify routine: {}",
+                err
+            ));
+        }
+
+        return Ok(Self { _private: () });
+    }
+}
+
+impl Drop for ProcessNotify {
+    fn drop(&mut self) {
+        // Removing the routine waits for the calls that are still running, so the callback can be
+        // cleared right after.
+        let status = unsafe { ffi::PsSetCreateProcessNotifyRoutine(notify_routine, 1) };
+        if let Err(err) = check_ntstatus(status) {
+            crate::err!("failed to remove process notify routine: {}", err);
+        }
+        NOTIFY_CALLBACK.store(core::ptr::null_mut(), Ordering::SeqCst);
+    }
+}
+
+unsafe extern "system" fn notify_routine(parent_id: HANDLE, process_id: HANDLE, create: u8) {
+    let callback = NOTIFY_CALLBACK.load(Ordering::SeqCst);
+    if callback.is_null() {
+        return;
+    }
+    let callback: fn(ProcessEvent) = core::mem::transmute(callback);
+
+    let event = if create != 0 {
+        ProcessEvent::Created {
+            process_id: process_id as u64,
+            parent_process_id: parent_id as u64,
+        }
+    } else {
+        ProcessEvent::Exited {
+            process_id: process_id as u64,
+        }
+    };
+    callback(event);
+}
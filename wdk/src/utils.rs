@@ -1,9 +1,13 @@
 use alloc::format;
 use alloc::string::{String, ToString};
 use ntstatus::ntstatus::NtStatus;
-use windows_sys::Win32::Foundation::STATUS_SUCCESS;
+use widestring::U16CString;
+use windows_sys::Wdk::Foundation::OBJECT_ATTRIBUTES;
+use windows_sys::Win32::Foundation::{STATUS_SUCCESS, UNICODE_STRING};
 
-use crate::consts::{APC_LEVEL, KERNEL_MODE};
+use crate::consts::{
+    APC_LEVEL, KERNEL_MODE, OBJ_CASE_INSENSITIVE, OBJ_KERNEL_HANDLE, SYMBOLIC_LINK_QUERY,
+};
 use crate::ffi;
 
 /// Kernel wait intervals are counted in 100-nanosecond units.
@@ -50,6 +54,49 @@ pub fn sleep_ms(milliseconds: u32) -> Result<(), String> {
     return check_ntstatus(status);
 }
 
+/// Returns the target of the symbolic link `name`, for example `\Device\HarddiskVolume3` for
+/// `\GLOBAL??\C:`. Only callable at PASSIVE_LEVEL.
+pub fn query_symbolic_link(name: &str) -> Result<String, String> {
+    let Ok(name) = U16CString::from_str(name) else {
+        return Err("invalid symbolic link name".to_string());
+    };
+    let name = UNICODE_STRING {
+        Length: (name.len() * 2) as u16,
+        MaximumLength: (name.len() * 2) as u16,
+        Buffer: name.as_ptr() as *mut u16,
+    };
+    let attributes = OBJECT_ATTRIBUTES {
+        Length: core::mem::size_of::<OBJECT_ATTRIBUTES>() as u32,
+        RootDirectory: 0,
+        ObjectName: &name,
+        Attributes: OBJ_CASE_INSENSITIVE | OBJ_KERNEL_HANDLE,
+        SecurityDescriptor: core::ptr::null(),
+        SecurityQualityOfService: core::ptr::null(),
+    };
+
+    let mut handle = 0;
+    let status =
+        unsafe { ffi::ZwOpenSymbolicLinkObject(&mut handle, SYMBOLIC_LINK_QUERY, &attributes) };
+    check_ntstatus(status)?;
+
+    // Device names are short, a buffer of MAX_PATH characters is plenty.
+    let mut buffer = [0u16; 260];
+    let mut target = UNICODE_STRING {
+        Length: 0,
+        MaximumLength: (buffer.len() * 2) as u16,
+        Buffer: buffer.as_mut_ptr(),
+    };
+    let status =
+        unsafe { ffi::ZwQuerySymbolicLinkObject(handle, &mut target, core::ptr::null_mut()) };
+    unsafe {
+        ffi::ZwClose(handle);
+    }
+    check_ntstatus(status)?;
+
+    return String::from_utf16(&buffer[..target.Length as usize / 2])
+        .map_err(|_| "invalid symbolic link target".to_string());
+}
+
 // get_system_timestamp_ns return the number of nanoseconds since the start of the system. SHould be used only for performance measurements.
 pub fn get_startup_time_ns() -> u64 {
     let mut freq: i64 = 0;